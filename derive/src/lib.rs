@@ -0,0 +1,749 @@
+//! `#[derive(XmlElement)]` for mini-jabber, which implements `mini_jabber::XmlElement` and
+//! through it `XmlCustomSerialize` and `XmlCustomDeserialize`.
+//!
+//! ```ignore
+//! #[derive(XmlElement)]
+//! #[xml(name = "starttls", xmlns = "urn:ietf:params:xml:ns:xmpp-tls")]
+//! pub struct StartTls {
+//!     #[xml(flag = "required")]
+//!     pub required: bool,
+//! }
+//! ```
+//!
+//! The container takes `name`, the qualified element name, an optional `xmlns`, and `open` for
+//! stream headers that are never closed. Elements are matched on their local name and, with
+//! `xmlns`, must be in that namespace whatever prefix the peer uses, or parsing fails with an
+//! `invalid-namespace` stream error. Unprefixed elements declare `xmlns` when written, prefixed
+//! ones rely on the stream header.
+//!
+//! Every field says what it maps to:
+//! - `attribute` or `attribute = "xml:lang"`, a `String` or `Option<String>`
+//! - `child`, another `XmlElement`, optionally in an `Option`
+//! - `children`, a `Vec` of `XmlElement`s
+//! - `text`, the text content, or `text = "name"`, the text of a child element
+//! - `flag = "name"`, a `bool` for an empty child element
+//! - `condition`, the name of an empty child element such as `not-authorized`, empty when
+//!   there is none
+//! - `namespace`, a `String` with the namespace of the element, declared when written
+//!
+//! `flag`, `condition` and `text = "name"` elements take an `xmlns` too. Enums whose variants
+//! each wrap an `XmlElement` pick the variant from the element name.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Expr, Fields, GenericArgument,
+    Ident, LitByteStr, LitStr, Member, PathArguments, Type,
+};
+
+#[proc_macro_derive(XmlElement, attributes(xml))]
+pub fn derive_xml_element(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let result = match &input.data {
+        Data::Struct(data) => derive_struct(&input, &data.fields),
+        Data::Enum(data) => derive_enum(&input, data),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "XmlElement cannot be derived for unions",
+        )),
+    };
+    result.unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Path of the items the generated code uses.
+fn private() -> TokenStream2 {
+    quote!(::mini_jabber::__private)
+}
+
+struct Container {
+    name: LitStr,
+    xmlns: Option<Expr>,
+    open: bool,
+}
+
+impl Container {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut name = None;
+        let mut xmlns = None;
+        let mut open = false;
+        for attr in xml_attributes(&input.attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    name = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("xmlns") {
+                    xmlns = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("open") {
+                    open = true;
+                } else {
+                    return Err(meta.error("expected name, xmlns or open"));
+                }
+                Ok(())
+            })?;
+        }
+
+        let name = name.ok_or_else(|| {
+            syn::Error::new_spanned(&input.ident, "missing #[xml(name = \"...\")]")
+        })?;
+        Ok(Self { name, xmlns, open })
+    }
+}
+
+enum Kind {
+    Attribute(LitStr),
+    Child,
+    Children,
+    Text,
+    ChildText(LitStr),
+    Flag(LitStr),
+    Condition,
+    Namespace,
+}
+
+struct Field {
+    member: Member,
+    /// Local variable holding the field while parsing
+    var: Ident,
+    kind: Kind,
+    xmlns: Option<Expr>,
+    /// `T` of `Option<T>`, when the field is optional
+    optional: Option<Type>,
+    /// Element type of a child, `T` out of `T`, `Option<T>` or `Vec<T>`
+    element: Type,
+}
+
+impl Field {
+    fn parse(index: usize, field: &syn::Field) -> syn::Result<Self> {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(index.into()),
+        };
+
+        let mut kind = None;
+        let mut xmlns = None;
+        for attr in xml_attributes(&field.attrs) {
+            attr.parse_nested_meta(|meta| {
+                let name = |meta: &syn::meta::ParseNestedMeta| -> syn::Result<Option<LitStr>> {
+                    match meta.input.peek(syn::Token![=]) {
+                        true => Ok(Some(meta.value()?.parse()?)),
+                        false => Ok(None),
+                    }
+                };
+
+                if meta.path.is_ident("xmlns") {
+                    xmlns = Some(meta.value()?.parse()?);
+                    return Ok(());
+                }
+                if kind.is_some() {
+                    return Err(meta.error("a field maps to a single attribute or element"));
+                }
+                kind = Some(if meta.path.is_ident("attribute") {
+                    match (name(&meta)?, &field.ident) {
+                        (Some(name), _) => Kind::Attribute(name),
+                        (None, Some(ident)) => {
+                            Kind::Attribute(LitStr::new(&ident.to_string(), ident.span()))
+                        }
+                        (None, None) => {
+                            return Err(meta.error("unnamed fields need attribute = \"...\""))
+                        }
+                    }
+                } else if meta.path.is_ident("child") {
+                    Kind::Child
+                } else if meta.path.is_ident("children") {
+                    Kind::Children
+                } else if meta.path.is_ident("text") {
+                    match name(&meta)? {
+                        Some(name) => Kind::ChildText(name),
+                        None => Kind::Text,
+                    }
+                } else if meta.path.is_ident("flag") {
+                    Kind::Flag(meta.value()?.parse()?)
+                } else if meta.path.is_ident("condition") {
+                    Kind::Condition
+                } else if meta.path.is_ident("namespace") {
+                    Kind::Namespace
+                } else {
+                    return Err(meta.error(
+                        "expected attribute, child, children, text, flag, condition, namespace \
+                         or xmlns",
+                    ));
+                });
+                Ok(())
+            })?;
+        }
+
+        let kind = kind.ok_or_else(|| {
+            syn::Error::new_spanned(
+                field,
+                "missing #[xml(...)], expected attribute, child, children, text, flag, condition \
+                 or namespace",
+            )
+        })?;
+        if xmlns.is_some() && !matches!(kind, Kind::ChildText(_) | Kind::Flag(_) | Kind::Condition)
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "xmlns only applies to text = \"...\", flag and condition",
+            ));
+        }
+
+        let optional = inner_type(&field.ty, "Option").cloned();
+        let element = match kind {
+            Kind::Children => inner_type(&field.ty, "Vec")
+                .ok_or_else(|| syn::Error::new_spanned(&field.ty, "children must be a Vec"))?
+                .clone(),
+            _ => optional.clone().unwrap_or_else(|| field.ty.clone()),
+        };
+
+        Ok(Self {
+            member,
+            var: format_ident!("field_{}", index),
+            kind,
+            xmlns,
+            optional,
+            element,
+        })
+    }
+
+    /// The error for a required field that is absent.
+    fn missing(&self) -> TokenStream2 {
+        match &self.kind {
+            Kind::Attribute(name) => quote!(::mini_jabber::Error::missing_attribute(#name)),
+            Kind::ChildText(name) => quote!(::mini_jabber::Error::missing_element(#name)),
+            _ => {
+                let name = match &self.member {
+                    Member::Named(ident) => ident.to_string(),
+                    Member::Unnamed(index) => format!("field {}", index.index),
+                };
+                quote!(::mini_jabber::Error::missing_element(#name))
+            }
+        }
+    }
+}
+
+fn xml_attributes(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("xml"))
+}
+
+/// `T` out of `wrapper<T>`.
+fn inner_type<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn byte_string(name: &LitStr) -> LitByteStr {
+    LitByteStr::new(name.value().as_bytes(), name.span())
+}
+
+/// Pushes an `xmlns` attribute on the `BytesStart` named `start`.
+fn push_xmlns(start: &Ident, xmlns: &Option<Expr>) -> TokenStream2 {
+    match xmlns {
+        Some(xmlns) => quote!(#start.push_attribute(("xmlns", #xmlns));),
+        None => quote!(),
+    }
+}
+
+fn derive_struct(input: &DeriveInput, shape: &Fields) -> syn::Result<TokenStream2> {
+    let private = private();
+    let container = Container::parse(input)?;
+    let fields = shape
+        .iter()
+        .enumerate()
+        .map(|(index, field)| Field::parse(index, field))
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    if container.open {
+        if let Some(field) = fields
+            .iter()
+            .find(|field| !matches!(field.kind, Kind::Attribute(_) | Kind::Namespace))
+        {
+            return Err(syn::Error::new_spanned(
+                &field.member,
+                "open elements only have attributes",
+            ));
+        }
+    }
+
+    let write = write_struct(&container, &fields);
+    let read = read_struct(&container, &fields, constructor(shape, &fields));
+
+    let ident = &input.ident;
+    let name = container.name.value();
+    let local_name = name
+        .split_once(':')
+        .map_or(name.as_str(), |(_, local)| local);
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mini_jabber::XmlElement for #ident #type_generics #where_clause {
+            fn accepts(local_name: &[u8]) -> bool {
+                local_name == #local_name.as_bytes()
+            }
+
+            fn write_to<W: ::std::io::Write>(
+                &self,
+                writer: &mut #private::quick_xml::Writer<W>,
+            ) -> #private::quick_xml::Result<()> {
+                #write
+            }
+
+            fn read_from(
+                reader: &mut #private::quick_xml::NsReader<&[u8]>,
+                start: &#private::quick_xml::events::BytesStart,
+                empty: bool,
+            ) -> ::std::result::Result<Self, ::mini_jabber::Error> {
+                #read
+            }
+        }
+    })
+}
+
+/// Builds `Self` out of the parsed fields, failing on missing required ones.
+fn constructor(shape: &Fields, fields: &[Field]) -> TokenStream2 {
+    let values = fields.iter().map(|field| {
+        let var = &field.var;
+        let missing = field.missing();
+        match (&field.kind, &field.optional) {
+            (Kind::Children | Kind::Flag(_) | Kind::Namespace, _) => quote!(#var),
+            (Kind::Condition, _) => quote!(#var.unwrap_or_default()),
+            (Kind::Text, None) => quote!(#var),
+            (Kind::Text, Some(_)) => quote!(Some(#var).filter(|text| !text.is_empty())),
+            (_, Some(_)) => quote!(#var),
+            (_, None) => quote!(#var.ok_or_else(|| #missing)?),
+        }
+    });
+
+    match shape {
+        Fields::Named(_) => {
+            let members = fields.iter().map(|field| &field.member);
+            quote!(Self { #(#members: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(Self(#(#values),*)),
+        Fields::Unit => quote!(Self),
+    }
+}
+
+fn write_struct(container: &Container, fields: &[Field]) -> TokenStream2 {
+    let private = private();
+    let name = &container.name;
+    let start = format_ident!("start");
+
+    // A `namespace` field declares the namespace of the element, otherwise `xmlns` does unless
+    // the element is prefixed
+    let name_value = container.name.value();
+    let declaration = match name_value.split_once(':') {
+        Some((prefix, _)) => format!("xmlns:{}", prefix),
+        None => "xmlns".to_string(),
+    };
+    let mut pushes = Vec::new();
+    if !name_value.contains(':')
+        && !fields
+            .iter()
+            .any(|field| matches!(field.kind, Kind::Namespace))
+    {
+        pushes.push(push_xmlns(&start, &container.xmlns));
+    }
+    let mut has_content = Vec::new();
+    let mut always_content = false;
+    let mut content = Vec::new();
+
+    for field in fields {
+        let member = &field.member;
+        let value = quote!(self.#member);
+        let element = format_ident!("element");
+        let element_xmlns = push_xmlns(&element, &field.xmlns);
+        let element_mut = match field.xmlns {
+            Some(_) => quote!(mut),
+            None => quote!(),
+        };
+
+        match (&field.kind, &field.optional) {
+            (Kind::Attribute(attribute), None) => pushes.push(quote! {
+                #start.push_attribute((#attribute, #value.as_str()));
+            }),
+            (Kind::Attribute(attribute), Some(_)) => pushes.push(quote! {
+                if let Some(value) = &#value {
+                    #start.push_attribute((#attribute, value.as_str()));
+                }
+            }),
+            (Kind::Namespace, _) => pushes.push(quote! {
+                #start.push_attribute((#declaration, #value.as_str()));
+            }),
+            (Kind::Child, None) => {
+                always_content = true;
+                content.push(quote!(#value.write_to(writer)?;));
+            }
+            (Kind::Child, Some(_)) => {
+                has_content.push(quote!(#value.is_some()));
+                content.push(quote! {
+                    if let Some(child) = &#value {
+                        child.write_to(writer)?;
+                    }
+                });
+            }
+            (Kind::Children, _) => {
+                has_content.push(quote!(!#value.is_empty()));
+                content.push(quote! {
+                    for child in &#value {
+                        child.write_to(writer)?;
+                    }
+                });
+            }
+            (Kind::Text, None) => {
+                has_content.push(quote!(!#value.is_empty()));
+                content.push(quote! {
+                    writer.write_event(Event::Text(BytesText::new(&#value)))?;
+                });
+            }
+            (Kind::Text, Some(_)) => {
+                has_content.push(quote!(#value.as_ref().is_some_and(|text| !text.is_empty())));
+                content.push(quote! {
+                    if let Some(text) = &#value {
+                        writer.write_event(Event::Text(BytesText::new(text)))?;
+                    }
+                });
+            }
+            (Kind::ChildText(child), optional) => {
+                let write = quote! {
+                    let #element_mut #element = BytesStart::new(#child);
+                    #element_xmlns
+                    writer.write_event(Event::Start(#element))?;
+                    writer.write_event(Event::Text(BytesText::new(text)))?;
+                    writer.write_event(Event::End(BytesEnd::new(#child)))?;
+                };
+                match optional {
+                    Some(_) => {
+                        has_content.push(quote!(#value.is_some()));
+                        content.push(quote! {
+                            if let Some(text) = &#value {
+                                #write
+                            }
+                        });
+                    }
+                    None => {
+                        always_content = true;
+                        content.push(quote! {
+                            let text = &#value;
+                            #write
+                        });
+                    }
+                }
+            }
+            (Kind::Flag(flag), _) => {
+                has_content.push(quote!(#value));
+                content.push(quote! {
+                    if #value {
+                        let #element_mut #element = BytesStart::new(#flag);
+                        #element_xmlns
+                        writer.write_event(Event::Empty(#element))?;
+                    }
+                });
+            }
+            (Kind::Condition, _) => {
+                has_content.push(quote!(!#value.is_empty()));
+                content.push(quote! {
+                    if !#value.is_empty() {
+                        let #element_mut #element = BytesStart::new(#value.as_str());
+                        #element_xmlns
+                        writer.write_event(Event::Empty(#element))?;
+                    }
+                });
+            }
+        }
+    }
+
+    let start_mut = match pushes.iter().any(|push| !push.is_empty()) {
+        true => quote!(mut),
+        false => quote!(),
+    };
+    let imports = quote! {
+        #[allow(unused_imports)]
+        use #private::quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+        let #start_mut #start = BytesStart::new(#name);
+        #(#pushes)*
+    };
+
+    if container.open {
+        return quote! {
+            #imports
+            writer.write_event(Event::Start(#start))
+        };
+    }
+    if content.is_empty() {
+        return quote! {
+            #imports
+            writer.write_event(Event::Empty(#start))
+        };
+    }
+
+    let empty_check = match always_content {
+        true => quote!(),
+        false => quote! {
+            if !(#(#has_content)||*) {
+                return writer.write_event(Event::Empty(#start));
+            }
+        },
+    };
+    quote! {
+        #imports
+        #empty_check
+        writer.write_event(Event::Start(#start))?;
+        #(#content)*
+        writer.write_event(Event::End(BytesEnd::new(#name)))
+    }
+}
+
+fn read_struct(container: &Container, fields: &[Field], construct: TokenStream2) -> TokenStream2 {
+    let name = &container.name;
+
+    let declarations = fields.iter().map(|field| {
+        let var = &field.var;
+        match field.kind {
+            Kind::Children => quote!(let mut #var = Vec::new();),
+            Kind::Flag(_) => quote!(let mut #var = false;),
+            Kind::Text => quote!(let mut #var = String::new();),
+            Kind::Namespace => quote!(let #var = namespace.clone();),
+            _ => quote!(let mut #var = None;),
+        }
+    });
+
+    let attribute_arms = fields.iter().filter_map(|field| {
+        let Kind::Attribute(attribute) = &field.kind else {
+            return None;
+        };
+        let var = &field.var;
+        let key = byte_string(attribute);
+        Some(quote!(#key => #var = Some(attribute.unescape_value()?.into_owned()),))
+    });
+
+    let namespace_check = match &container.xmlns {
+        Some(xmlns) => quote! {
+            if namespace != #xmlns {
+                return Err(::mini_jabber::invalid_namespace(#name, &namespace, #xmlns).into());
+            }
+        },
+        None => quote!(),
+    };
+
+    let children = match container.open {
+        true => quote!(let _ = empty;),
+        false => read_children(container, fields),
+    };
+
+    quote! {
+        #[allow(unused_variables)]
+        let namespace = ::mini_jabber::element_namespace(reader, start)?;
+        #namespace_check
+        #(#declarations)*
+        for attribute in start.attributes() {
+            let attribute = attribute?;
+            #[allow(clippy::match_single_binding)]
+            match attribute.key.as_ref() {
+                #(#attribute_arms)*
+                _ => {}
+            }
+        }
+        #children
+        Ok(#construct)
+    }
+}
+
+/// Guard of a match arm on the namespace of a child, when the field sets one.
+fn namespace_guard(xmlns: &Option<Expr>) -> TokenStream2 {
+    match xmlns {
+        Some(xmlns) => quote!(if element_namespace == #xmlns),
+        None => quote!(),
+    }
+}
+
+fn read_children(container: &Container, fields: &[Field]) -> TokenStream2 {
+    let private = private();
+    let name = &container.name;
+
+    let text = fields.iter().find(|field| matches!(field.kind, Kind::Text));
+    let (text_arm, cdata_arm) = match text {
+        Some(field) => {
+            let var = &field.var;
+            (
+                quote!(#var.push_str(&text.unescape()?)),
+                quote!(#var.push_str(::std::str::from_utf8(&data)?)),
+            )
+        }
+        None => (quote!(let _ = text;), quote!(let _ = data;)),
+    };
+
+    let mut named_arms = Vec::new();
+    let mut element_arms = Vec::new();
+    for field in fields {
+        let var = &field.var;
+        let ty = &field.element;
+        let guard = namespace_guard(&field.xmlns);
+        match &field.kind {
+            Kind::Child => element_arms.push(quote! {
+                child if <#ty as ::mini_jabber::XmlElement>::accepts(child) => {
+                    #var = Some(<#ty as ::mini_jabber::XmlElement>::read_from(
+                        reader,
+                        &element,
+                        element_empty,
+                    )?);
+                }
+            }),
+            Kind::Children => element_arms.push(quote! {
+                child if <#ty as ::mini_jabber::XmlElement>::accepts(child) => {
+                    #var.push(<#ty as ::mini_jabber::XmlElement>::read_from(
+                        reader,
+                        &element,
+                        element_empty,
+                    )?);
+                }
+            }),
+            Kind::ChildText(child) => {
+                let child = byte_string(child);
+                named_arms.push(quote! {
+                    #child #guard => {
+                        #var = Some(::mini_jabber::element_text(reader, &element, element_empty)?);
+                    }
+                });
+            }
+            Kind::Flag(flag) => {
+                let flag = byte_string(flag);
+                named_arms.push(quote! {
+                    #flag #guard => {
+                        #var = true;
+                        ::mini_jabber::skip_element(reader, &element, element_empty)?;
+                    }
+                });
+            }
+            Kind::Condition => {
+                let guard = match &field.xmlns {
+                    Some(xmlns) => quote!(if #var.is_none() && element_namespace == #xmlns),
+                    None => quote!(if #var.is_none()),
+                };
+                element_arms.push(quote! {
+                    condition #guard => {
+                        #var = Some(::std::str::from_utf8(condition)?.to_string());
+                        ::mini_jabber::skip_element(reader, &element, element_empty)?;
+                    }
+                });
+            }
+            Kind::Attribute(_) | Kind::Text | Kind::Namespace => {}
+        }
+    }
+
+    quote! {
+        if !empty {
+            use #private::quick_xml::events::Event;
+            loop {
+                let (element_namespace, event) = reader.read_resolved_event()?;
+                let (element, element_empty) = match event {
+                    Event::Start(element) => (element, false),
+                    Event::Empty(element) => (element, true),
+                    Event::Text(text) => {
+                        #text_arm;
+                        continue;
+                    }
+                    Event::CData(data) => {
+                        #cdata_arm;
+                        continue;
+                    }
+                    Event::End(_) => break,
+                    Event::Eof => return Err(::mini_jabber::Error::unclosed(#name)),
+                    _ => continue,
+                };
+                #[allow(unused_variables)]
+                let element_namespace = ::mini_jabber::resolve_namespace(element_namespace)?;
+                let element_name = element.local_name();
+                #[allow(clippy::match_single_binding)]
+                match element_name.as_ref() {
+                    #(#named_arms)*
+                    #(#element_arms)*
+                    _ => ::mini_jabber::skip_element(reader, &element, element_empty)?,
+                }
+            }
+        }
+    }
+}
+
+fn derive_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let private = private();
+    let ident = &input.ident;
+    if let Some(attr) = xml_attributes(&input.attrs).next() {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "enums take the element names of their variants",
+        ));
+    }
+
+    let mut variants = Vec::new();
+    for variant in &data.variants {
+        match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                variants.push((&variant.ident, &fields.unnamed[0].ty));
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "variants must wrap a single XmlElement",
+                ))
+            }
+        }
+    }
+    if variants.is_empty() {
+        return Err(syn::Error::new(Span::call_site(), "enum has no variants"));
+    }
+
+    let accepts = variants
+        .iter()
+        .map(|(_, ty)| quote!(<#ty as ::mini_jabber::XmlElement>::accepts(name)));
+    let writes = variants
+        .iter()
+        .map(|(variant, _)| quote!(Self::#variant(inner) => inner.write_to(writer),));
+    let reads = variants.iter().map(|(variant, ty)| {
+        quote! {
+            if <#ty as ::mini_jabber::XmlElement>::accepts(name.as_ref()) {
+                return Ok(Self::#variant(<#ty as ::mini_jabber::XmlElement>::read_from(
+                    reader, start, empty,
+                )?));
+            }
+        }
+    });
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mini_jabber::XmlElement for #ident #type_generics #where_clause {
+            fn accepts(name: &[u8]) -> bool {
+                #(#accepts)||*
+            }
+
+            fn write_to<W: ::std::io::Write>(
+                &self,
+                writer: &mut #private::quick_xml::Writer<W>,
+            ) -> #private::quick_xml::Result<()> {
+                match self {
+                    #(#writes)*
+                }
+            }
+
+            fn read_from(
+                reader: &mut #private::quick_xml::NsReader<&[u8]>,
+                start: &#private::quick_xml::events::BytesStart,
+                empty: bool,
+            ) -> ::std::result::Result<Self, ::mini_jabber::Error> {
+                let name = start.local_name();
+                #(#reads)*
+                Err(::mini_jabber::Error::unexpected_element(name))
+            }
+        }
+    })
+}
@@ -0,0 +1,38 @@
+//! A component answering every message with the same body.
+//!
+//! cargo run --bin server -- --component echo.localhost=secret
+//! cargo run --example echo_component -- echo.localhost secret
+
+use mini_jabber::*;
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let domain = args.next().unwrap_or("echo.localhost".to_string());
+    let secret = args.next().unwrap_or("secret".to_string());
+    let address = format!("ws://127.0.0.1:{}", COMPONENT_PORT);
+
+    let mut component = Component::connect(&address, &domain, &secret).await?;
+    println!("connected as {}", component.domain());
+
+    while let Some(stanza) = component.recv().await {
+        let Stanza::Message(message) = stanza else {
+            continue;
+        };
+        if message.body.is_none() {
+            continue;
+        }
+
+        let reply = Message {
+            id: message.id.map(|id| format!("{}-echo", id)),
+            from: message.to,
+            to: message.from,
+            message_type: message.message_type,
+            body: message.body,
+            ..Default::default()
+        };
+        component.send(&reply).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_jabber::*;
+
+fuzz_target!(|data: &str| {
+    if let Ok(result) = DialbackResult::from_string(data) {
+        let _ = DialbackResult::from_string(&result.into_string());
+    }
+    if let Ok(verify) = DialbackVerify::from_string(data) {
+        let _ = DialbackVerify::from_string(&verify.into_string());
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_jabber::*;
+
+// The first line is a path for `find_all`, the rest the element
+fuzz_target!(|data: &str| {
+    let (path, xml) = data.split_once('\n').unwrap_or(("*", data));
+    if let Ok(element) = Element::from_string(xml) {
+        element.find_all(path);
+        let _ = Element::from_string(&element.into_string());
+        let _ = Stanza::try_from(element);
+    }
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_jabber::*;
+
+/// Parses `data` as `T` and writes back whatever was accepted.
+fn check<T: XmlCustomDeserialize + XmlCustomSerialize>(data: &str) {
+    if let Ok(parsed) = T::from_string(data) {
+        let _ = T::from_string(&parsed.into_string());
+    }
+}
+
+fuzz_target!(|data: &str| {
+    check::<StreamHeader>(data);
+    check::<StreamHeaderResponse>(data);
+    check::<StreamFeatures>(data);
+    check::<StartTls>(data);
+    check::<StartTlsResponse>(data);
+    check::<Mechanisms>(data);
+    check::<SaslAuth>(data);
+    // The server decodes the credentials of whatever `<auth/>` it accepts
+    if let Ok(auth) = SaslAuth::from_string(data) {
+        let _ = sasl_plain_decode(&auth.data);
+        let _ = sasl_external_decode(&auth.data);
+    }
+    check::<SaslResponse>(data);
+    check::<StreamError>(data);
+    check::<ComponentHandshake>(data);
+});
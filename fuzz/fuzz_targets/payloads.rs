@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_jabber::*;
+
+/// Parses `data` as `T` and writes back whatever was accepted.
+fn check<T: XmlCustomDeserialize + XmlCustomSerialize>(data: &str) {
+    if let Ok(parsed) = T::from_string(data) {
+        let _ = T::from_string(&parsed.into_string());
+    }
+}
+
+fuzz_target!(|data: &str| {
+    check::<Command>(data);
+    check::<DataForm>(data);
+    check::<RegisterQuery>(data);
+    check::<ResultSet>(data);
+    check::<MamQuery>(data);
+    check::<MamFin>(data);
+    check::<MamResult>(data);
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_jabber::*;
+
+// Frames as a server reads them off a connection, separated by NUL since they cannot contain
+// one: a header, then stanzas, errors or a restarted header.
+fuzz_target!(|data: &str| {
+    let mut frames = data.split('\0');
+    let Some(header) = frames.next() else {
+        return;
+    };
+    let Ok(header) = StreamHeader::from_string(header) else {
+        return;
+    };
+    let _ = header.into_response("id".to_string()).into_string();
+
+    for frame in frames {
+        if let Ok(stanza) = Stanza::from_string(frame) {
+            let _ = stanza.into_string();
+        } else if StreamError::from_string(frame).is_err() {
+            let _ = StreamHeader::from_string(frame);
+        }
+    }
+});
@@ -0,0 +1,293 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use crate::{Error, StanzaError};
+
+/// Who may create accounts through in-band registration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationPolicy {
+    Open,
+    Closed,
+    /// Registration requires one of the server's invitation tokens
+    InviteOnly,
+}
+
+impl FromStr for RegistrationPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "open" => Ok(RegistrationPolicy::Open),
+            "closed" => Ok(RegistrationPolicy::Closed),
+            "invite-only" => Ok(RegistrationPolicy::InviteOnly),
+            _ => Err(Error::config(format!(
+                "unknown registration policy {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Why an account operation was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    /// The username is taken
+    Conflict,
+    /// Registration is closed
+    NotAllowed,
+    /// The username or password is not acceptable
+    NotAcceptable,
+    /// The invitation token is missing or unknown
+    Forbidden,
+    /// There is no such account
+    NotFound,
+}
+
+impl AccountError {
+    /// The stanza error to answer a registration request with.
+    pub fn stanza_error(&self) -> StanzaError {
+        match self {
+            AccountError::Conflict => StanzaError::new("cancel", "conflict"),
+            AccountError::NotAllowed => StanzaError::new("cancel", "not-allowed"),
+            AccountError::NotAcceptable => StanzaError::new("modify", "not-acceptable"),
+            AccountError::Forbidden => StanzaError::new("auth", "forbidden"),
+            AccountError::NotFound => StanzaError::new("cancel", "item-not-found"),
+        }
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.stanza_error().condition)
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+/// Password of an account, hashed with Argon2id. The PHC string keeps the salt and the cost
+/// parameters, so they can be raised without breaking existing accounts.
+///
+/// Hashing and verifying are slow on purpose, servers do both outside the accounts lock and
+/// away from the async executor.
+#[derive(Debug, Clone)]
+pub struct HashedPassword(String);
+
+impl HashedPassword {
+    pub fn new(password: &str) -> Result<Self, AccountError> {
+        if password.is_empty() {
+            return Err(AccountError::NotAcceptable);
+        }
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("the default Argon2 parameters should be valid");
+        Ok(HashedPassword(hash.to_string()))
+    }
+
+    /// Checks `password` in constant time.
+    pub fn verify(&self, password: &str) -> bool {
+        PasswordHash::new(&self.0).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    fn decode(line: &str) -> Result<Self, Error> {
+        PasswordHash::new(line).map_err(|e| Error::config(format!("invalid hash: {}", e)))?;
+        Ok(HashedPassword(line.to_string()))
+    }
+}
+
+/// Checks `password` against the hash of an account. Without an account it is checked against
+/// a hash of no one, so that unknown users take as long as wrong passwords.
+pub fn verify_password(hash: Option<&HashedPassword>, password: &str) -> bool {
+    static NO_ONE: OnceLock<HashedPassword> = OnceLock::new();
+    match hash {
+        Some(hash) => hash.verify(password),
+        None => {
+            let no_one = NO_ONE.get_or_init(|| {
+                HashedPassword::new("no one").expect("the password should not be empty")
+            });
+            no_one.verify(password);
+            false
+        }
+    }
+}
+
+/// Accounts file lines of consumed invitations start with a character usernames can't have.
+const USED_INVITE: &str = ":used-invite";
+
+/// Local user accounts, keyed by username.
+pub struct Accounts {
+    policy: RegistrationPolicy,
+    invites: HashSet<String>,
+    /// Invitations already used, saved with the accounts so they are never valid again
+    used_invites: HashSet<String>,
+    accounts: HashMap<String, HashedPassword>,
+}
+
+impl Accounts {
+    pub fn new(policy: RegistrationPolicy) -> Self {
+        Self {
+            policy,
+            invites: HashSet::new(),
+            used_invites: HashSet::new(),
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// Reads the accounts saved by `save`, starting empty when the file does not exist.
+    pub fn load(policy: RegistrationPolicy, path: &Path) -> Result<Self, Error> {
+        let mut accounts = Self::new(policy);
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(accounts),
+            Err(e) => return Err(e.into()),
+        };
+
+        // One `username hash` line per account, usernames have no whitespace
+        for (i, line) in text.lines().enumerate() {
+            let (username, account) = line.split_once(' ').ok_or_else(|| {
+                Error::config(format!("{}:{}: expected an account", path.display(), i + 1))
+            })?;
+            if username == USED_INVITE {
+                accounts.used_invites.insert(account.to_string());
+                continue;
+            }
+            let account = HashedPassword::decode(account)
+                .map_err(|e| Error::config(format!("{}:{}: {}", path.display(), i + 1, e)))?;
+            accounts.accounts.insert(username.to_string(), account);
+        }
+        Ok(accounts)
+    }
+
+    /// Writes the accounts and the consumed invitations to `path`, replacing the file
+    /// atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut text = String::new();
+        for (username, account) in &self.accounts {
+            text.push_str(&format!("{} {}\n", username, account.0));
+        }
+        for invite in &self.used_invites {
+            text.push_str(&format!("{} {}\n", USED_INVITE, invite));
+        }
+
+        // Only the server may read the password hashes
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let temporary = path.with_extension("tmp");
+        std::fs::remove_file(&temporary).ok();
+        options.open(&temporary)?.write_all(text.as_bytes())?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn policy(&self) -> RegistrationPolicy {
+        self.policy
+    }
+
+    /// Adds an invitation token, each token can be used for a single registration. Tokens
+    /// that were already used are ignored.
+    pub fn add_invite(&mut self, token: String) {
+        if !self.used_invites.contains(&token) {
+            self.invites.insert(token);
+        }
+    }
+
+    pub fn exists(&self, username: &str) -> bool {
+        self.accounts.contains_key(username)
+    }
+
+    /// Creates an account through in-band registration, respecting the registration policy.
+    pub fn register(
+        &mut self,
+        username: &str,
+        password: HashedPassword,
+        invite: Option<&str>,
+    ) -> Result<(), AccountError> {
+        match self.policy {
+            RegistrationPolicy::Open => {}
+            RegistrationPolicy::Closed => return Err(AccountError::NotAllowed),
+            RegistrationPolicy::InviteOnly => {
+                if !invite.is_some_and(|invite| self.invites.contains(invite)) {
+                    return Err(AccountError::Forbidden);
+                }
+            }
+        }
+
+        self.create(username, password)?;
+        if let Some(invite) = invite.and_then(|invite| self.invites.take(invite)) {
+            self.used_invites.insert(invite);
+        }
+        Ok(())
+    }
+
+    /// Creates an account regardless of the registration policy.
+    pub fn create(&mut self, username: &str, password: HashedPassword) -> Result<(), AccountError> {
+        if !is_valid_username(username) {
+            return Err(AccountError::NotAcceptable);
+        }
+        if self.exists(username) {
+            return Err(AccountError::Conflict);
+        }
+
+        self.accounts.insert(username.to_string(), password);
+        Ok(())
+    }
+
+    pub fn change_password(
+        &mut self,
+        username: &str,
+        password: HashedPassword,
+    ) -> Result<(), AccountError> {
+        let account = self
+            .accounts
+            .get_mut(username)
+            .ok_or(AccountError::NotFound)?;
+        *account = password;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, username: &str) -> Result<(), AccountError> {
+        self.accounts
+            .remove(username)
+            .map(|_| ())
+            .ok_or(AccountError::NotFound)
+    }
+
+    /// Password hash of `username`, to verify with `verify_password` once the accounts are
+    /// unlocked.
+    pub fn password(&self, username: &str) -> Option<HashedPassword> {
+        self.accounts.get(username).cloned()
+    }
+
+    /// Checks the password of `username`, see `verify_password`.
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        verify_password(self.accounts.get(username), password)
+    }
+}
+
+/// Usernames become the local part of a JID, so they can't contain JID separators.
+fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 1023
+        && !username
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "@/\"&'<>:".contains(c))
+}
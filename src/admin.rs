@@ -0,0 +1,442 @@
+use std::{sync::Mutex, time::Duration};
+
+use crate::{
+    jid_bare, jid_domain, AccountError, Accounts, AdHocCommand, CommandNote, CommandOutcome,
+    CommandRegistry, DataForm, Field, FieldType, FormType, HashedPassword, Message, StanzaError,
+};
+
+pub const ADMIN_NS: &str = "http://jabber.org/protocol/admin";
+
+/// What the service administration commands (XEP-0133) need from the server.
+///
+/// Administrators manage the users of their own domain, only server administrators run
+/// commands that affect every domain.
+pub trait ServerAdmin {
+    fn is_admin(&self, jid: &str) -> bool;
+
+    fn is_server_admin(&self, jid: &str) -> bool;
+
+    /// Accounts of a local domain.
+    fn accounts(&self, domain: &str) -> Option<&Mutex<Accounts>>;
+
+    /// Full JIDs of the users connected to `domain`.
+    fn online_users(&self, domain: &str) -> Vec<String>;
+
+    /// Sends `message` to every user connected to `domain`.
+    fn announce(&self, domain: &str, message: Message);
+
+    /// Ends the sessions of `jid`, whose account is gone.
+    fn disconnect(&self, jid: &str);
+
+    /// Stops the server after `delay`, telling users why if there is an announcement.
+    fn shutdown(&self, delay: Duration, announcement: Option<String>);
+}
+
+/// Registers the XEP-0133 commands this server supports.
+pub fn register_admin_commands<C: ServerAdmin + 'static>(registry: &mut CommandRegistry<C>) {
+    registry.register(Box::new(AddUser));
+    registry.register(Box::new(DeleteUser));
+    registry.register(Box::new(ChangeUserPassword));
+    registry.register(Box::new(GetOnlineUsers));
+    registry.register(Box::new(Announce));
+    registry.register(Box::new(Shutdown));
+}
+
+fn admin_form(title: &str, instructions: &str) -> DataForm {
+    let mut form = DataForm::with_form_type(FormType::Form, ADMIN_NS);
+    form.title = Some(title.to_string());
+    form.instructions.push(instructions.to_string());
+    form
+}
+
+/// Accounts `jid` belongs to and its username. Administrators only manage the users of their own
+/// domain, so JIDs of other domains are refused.
+fn account_of<'a, 'j, C: ServerAdmin>(
+    context: &'a C,
+    requester: &str,
+    jid: &'j str,
+) -> Result<(&'a Mutex<Accounts>, &'j str), StanzaError> {
+    let Some((username, domain)) = jid_bare(jid)
+        .split_once('@')
+        .filter(|(username, _)| !username.is_empty())
+    else {
+        let mut error = StanzaError::new("modify", "jid-malformed");
+        error.text = Some(format!("{} is not a user JID", jid));
+        return Err(error);
+    };
+    if domain != jid_domain(requester) {
+        return Err(StanzaError::new("auth", "forbidden"));
+    }
+    let accounts = context
+        .accounts(domain)
+        .ok_or(StanzaError::new("cancel", "item-not-found"))?;
+    Ok((accounts, username))
+}
+
+fn completed(note: &str) -> CommandOutcome {
+    CommandOutcome {
+        note: Some(CommandNote::info(note)),
+        form: None,
+    }
+}
+
+fn account_error(error: AccountError) -> StanzaError {
+    let mut stanza_error = error.stanza_error();
+    stanza_error.text = Some(error.to_string());
+    stanza_error
+}
+
+struct AddUser;
+
+impl<C: ServerAdmin> AdHocCommand<C> for AddUser {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#add-user"
+    }
+
+    fn name(&self) -> &str {
+        "Add User"
+    }
+
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form("Adding a User", "Fill out this form to add a user.");
+        form.fields.push(
+            Field::new("accountjid", FieldType::JidSingle)
+                .with_label("The Jabber ID for the account to be added")
+                .required(),
+        );
+        form.fields.push(
+            Field::new("password", FieldType::TextPrivate)
+                .with_label("The password for this account")
+                .required(),
+        );
+        form.fields.push(
+            Field::new("password-verify", FieldType::TextPrivate)
+                .with_label("Retype password")
+                .required(),
+        );
+        form.fields
+            .push(Field::new("email", FieldType::TextSingle).with_label("Email address"));
+        form
+    }
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let form = &submitted[0];
+        let jid = form.value("accountjid").unwrap_or_default();
+        let password = form.value("password").unwrap_or_default();
+        if form.value("password-verify") != Some(password) {
+            let mut error = StanzaError::new("modify", "not-acceptable");
+            error.text = Some("passwords do not match".to_string());
+            return Err(error);
+        }
+
+        let (accounts, username) = account_of(context, requester, jid)?;
+        // Argon2 is slow, hash before locking the accounts
+        let password = HashedPassword::new(password).map_err(account_error)?;
+        accounts
+            .lock()
+            .unwrap()
+            .create(username, password)
+            .map_err(account_error)?;
+        Ok(completed(&format!("Added {}", jid)))
+    }
+}
+
+struct DeleteUser;
+
+impl<C: ServerAdmin> AdHocCommand<C> for DeleteUser {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#delete-user"
+    }
+
+    fn name(&self) -> &str {
+        "Delete User"
+    }
+
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form("Deleting a User", "Fill out this form to delete a user.");
+        form.fields.push(
+            Field::new("accountjids", FieldType::JidMulti)
+                .with_label("The Jabber ID(s) to delete")
+                .required(),
+        );
+        form
+    }
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let jids = submitted[0].values("accountjids");
+        for jid in jids {
+            let (accounts, username) = account_of(context, requester, jid)?;
+            accounts
+                .lock()
+                .unwrap()
+                .remove(username)
+                .map_err(account_error)?;
+            context.disconnect(jid_bare(jid));
+        }
+        Ok(completed(&format!("Deleted {}", jids.join(", "))))
+    }
+}
+
+struct ChangeUserPassword;
+
+impl<C: ServerAdmin> AdHocCommand<C> for ChangeUserPassword {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#change-user-password"
+    }
+
+    fn name(&self) -> &str {
+        "Change User Password"
+    }
+
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form(
+            "Changing a User Password",
+            "Fill out this form to change a user's password.",
+        );
+        form.fields.push(
+            Field::new("accountjid", FieldType::JidSingle)
+                .with_label("The Jabber ID for this account")
+                .required(),
+        );
+        form.fields.push(
+            Field::new("password", FieldType::TextPrivate)
+                .with_label("The password for this account")
+                .required(),
+        );
+        form
+    }
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let form = &submitted[0];
+        let jid = form.value("accountjid").unwrap_or_default();
+        let (accounts, username) = account_of(context, requester, jid)?;
+        let password = HashedPassword::new(form.value("password").unwrap_or_default())
+            .map_err(account_error)?;
+        accounts
+            .lock()
+            .unwrap()
+            .change_password(username, password)
+            .map_err(account_error)?;
+        Ok(completed(&format!("Changed the password of {}", jid)))
+    }
+}
+
+struct GetOnlineUsers;
+
+impl<C: ServerAdmin> AdHocCommand<C> for GetOnlineUsers {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#get-online-users-list"
+    }
+
+    fn name(&self) -> &str {
+        "Get List of Online Users"
+    }
+
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form(
+            "Requesting List of Online Users",
+            "How many users should be returned at most?",
+        );
+        let mut max_items = Field::new("max_items", FieldType::ListSingle)
+            .with_label("Maximum number of items to show")
+            .with_value("25");
+        for option in ["25", "50", "75", "100", "150", "200"] {
+            max_items = max_items.with_option(None, option);
+        }
+        form.fields
+            .push(max_items.with_option(Some("None"), "none"));
+        form
+    }
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let max_items = submitted[0]
+            .value("max_items")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(usize::MAX);
+
+        let mut users = context.online_users(jid_domain(requester));
+        users.sort();
+        users.truncate(max_items);
+
+        let mut form = DataForm::with_form_type(FormType::Result, ADMIN_NS);
+        form.fields.push(Field {
+            values: users,
+            ..Field::new("onlineuserjids", FieldType::JidMulti)
+                .with_label("The list of all online users")
+        });
+        Ok(CommandOutcome {
+            note: None,
+            form: Some(form),
+        })
+    }
+}
+
+struct Announce;
+
+impl<C: ServerAdmin> AdHocCommand<C> for Announce {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#announce"
+    }
+
+    fn name(&self) -> &str {
+        "Send Announcement to Online Users"
+    }
+
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form(
+            "Making an Announcement",
+            "Fill out this form to make an announcement to all active users of this service.",
+        );
+        form.fields
+            .push(Field::new("subject", FieldType::TextSingle).with_label("Subject"));
+        form.fields.push(
+            Field::new("announcement", FieldType::TextMulti)
+                .with_label("Announcement")
+                .required(),
+        );
+        form
+    }
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let form = &submitted[0];
+        context.announce(
+            jid_domain(requester),
+            Message {
+                message_type: Some("headline".to_string()),
+                subject: form.value("subject").map(|subject| subject.to_string()),
+                body: Some(form.values("announcement").join("\n")),
+                ..Default::default()
+            },
+        );
+        Ok(completed("Announcement sent"))
+    }
+}
+
+struct Shutdown;
+
+impl<C: ServerAdmin> AdHocCommand<C> for Shutdown {
+    fn node(&self) -> &str {
+        "http://jabber.org/protocol/admin#shutdown"
+    }
+
+    fn name(&self) -> &str {
+        "Shut Down Service"
+    }
+
+    /// Shutting down stops every domain
+    fn is_allowed(&self, requester: &str, context: &C) -> bool {
+        context.is_server_admin(requester)
+    }
+
+    fn stage_count(&self) -> usize {
+        1
+    }
+
+    fn form(&self, _stage: usize, _submitted: &[DataForm], _context: &C) -> DataForm {
+        let mut form = admin_form(
+            "Shutting Down the Service",
+            "Fill out this form to shut down the service.",
+        );
+        let mut delay = Field::new("delay", FieldType::ListSingle)
+            .with_label("Time delay before shutting down")
+            .with_value("0");
+        for (label, seconds) in [
+            ("Now", "0"),
+            ("30 seconds", "30"),
+            ("60 seconds", "60"),
+            ("90 seconds", "90"),
+            ("2 minutes", "120"),
+            ("3 minutes", "180"),
+            ("5 minutes", "300"),
+        ] {
+            delay = delay.with_option(Some(label), seconds);
+        }
+        form.fields.push(delay);
+        form.fields
+            .push(Field::new("announcement", FieldType::TextMulti).with_label("Announcement"));
+        form
+    }
+
+    fn execute(
+        &self,
+        _requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError> {
+        let form = &submitted[0];
+        let delay = form
+            .value("delay")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let announcement = form.values("announcement").join("\n");
+        let announcement = (!announcement.is_empty()).then_some(announcement);
+
+        context.shutdown(Duration::from_secs(delay), announcement);
+        Ok(completed(&format!("Shutting down in {} seconds", delay)))
+    }
+}
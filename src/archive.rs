@@ -0,0 +1,264 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::SystemTime,
+};
+
+use crate::{jid_bare, new_stream_id, Error, Message, StanzaError};
+
+/// A message as stored by the server, together with its edit history.
+#[derive(Debug, Clone)]
+pub struct ArchivedMessage {
+    /// Stanza id assigned by the archive (XEP-0359), the same in the archive of both parties
+    pub id: String,
+    /// Id chosen by the sender, which corrections, retractions and reactions refer to
+    pub origin_id: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub body: Option<String>,
+    /// Previous bodies, oldest first, the last one being the retracted body of a retracted
+    /// message
+    pub edits: Vec<String>,
+    /// Retracted messages keep their history and are served as tombstones without a body
+    pub retracted: bool,
+    pub timestamp: SystemTime,
+    pub edited_at: Option<SystemTime>,
+    /// Latest reactions of each sender
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+impl ArchivedMessage {
+    /// Number of senders that reacted with each emoji.
+    pub fn reaction_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for reaction in self.reactions.values().flatten() {
+            *counts.entry(reaction.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The other party of the conversation, for the archive of `jid`.
+    fn peer(&self, jid: &str) -> &str {
+        if self.from == jid {
+            jid_bare(&self.to)
+        } else {
+            &self.from
+        }
+    }
+}
+
+/// In-memory message archive (XEP-0313 style) that applies corrections, retractions and
+/// reactions to the messages they refer to.
+///
+/// Each user has their own archive, keyed by bare JID, that keeps at most `retention`
+/// messages and drops the oldest ones first.
+pub struct Archive {
+    users: HashMap<String, VecDeque<ArchivedMessage>>,
+    retention: usize,
+}
+
+impl Archive {
+    pub fn new(retention: usize) -> Self {
+        Self {
+            users: HashMap::new(),
+            retention,
+        }
+    }
+
+    /// Stores `message` sent by `from` in the archive of both parties, or applies it to an
+    /// earlier message if it is a correction, a retraction or a reaction. Messages without a
+    /// body such as chat states are not stored. Returns the stanza id of a stored message.
+    ///
+    /// `from` must be the authenticated sender, never an address the peer claims: messages
+    /// belong to its bare JID, which alone may correct or retract them.
+    pub fn apply(&mut self, from: &str, message: &Message) -> Result<Option<String>, Error> {
+        let from = jid_bare(from);
+        if let Some(retract) = &message.retract {
+            let original = self.find_own(from, &retract.id)?;
+            self.update(from, &original, |original| {
+                original.retracted = true;
+                if let Some(body) = original.body.take() {
+                    original.edits.push(body);
+                }
+                original.reactions.clear();
+                original.edited_at = Some(SystemTime::now());
+            });
+            return Ok(None);
+        }
+
+        if let Some(reactions) = &message.reactions {
+            // Only participants of the conversation have it in their archive
+            let original = self
+                .users
+                .get(from)
+                .and_then(|messages| {
+                    messages
+                        .iter()
+                        .rev()
+                        .find(|message| message.origin_id.as_deref() == Some(&reactions.id))
+                })
+                .ok_or_else(|| not_found(&reactions.id))?;
+            if original.retracted {
+                return Err(retracted(&reactions.id));
+            }
+
+            // Each reaction message replaces the previous reactions of its sender
+            let original = original.id.clone();
+            self.update(from, &original, |original| {
+                if reactions.reactions.is_empty() {
+                    original.reactions.remove(from);
+                } else {
+                    original
+                        .reactions
+                        .insert(from.to_string(), reactions.reactions.clone());
+                }
+            });
+            return Ok(None);
+        }
+
+        let Some(body) = &message.body else {
+            return Ok(None);
+        };
+
+        if let Some(replace) = &message.replace {
+            let original = self.find_own(from, &replace.id)?;
+            self.update(from, &original, |original| {
+                if let Some(previous) = original.body.replace(body.clone()) {
+                    original.edits.push(previous);
+                }
+                original.edited_at = Some(SystemTime::now());
+            });
+            return Ok(None);
+        }
+
+        let archived = ArchivedMessage {
+            id: new_stream_id(),
+            origin_id: message.id.clone(),
+            from: from.to_string(),
+            to: message.to.clone().unwrap_or_default(),
+            body: Some(body.clone()),
+            edits: Vec::new(),
+            retracted: false,
+            timestamp: SystemTime::now(),
+            edited_at: None,
+            reactions: BTreeMap::new(),
+        };
+        let id = archived.id.clone();
+        let to = jid_bare(&archived.to).to_string();
+        if !to.is_empty() && to != from {
+            self.push(&to, archived.clone());
+        }
+        self.push(from, archived);
+        Ok(Some(id))
+    }
+
+    /// Message `id` in the archive of `jid`.
+    pub fn get(&self, jid: &str, id: &str) -> Option<&ArchivedMessage> {
+        self.messages_for(jid).find(|message| message.id == id)
+    }
+
+    /// Messages sent or received by `jid`, oldest first.
+    pub fn messages_for(&self, jid: &str) -> impl DoubleEndedIterator<Item = &ArchivedMessage> {
+        self.users.get(jid_bare(jid)).into_iter().flatten()
+    }
+
+    /// Page of at most `max` messages of `jid`, oldest first, only those exchanged with `with`
+    /// if set. Retracted messages are tombstones without a body. The page starts right after
+    /// the message `after` and ends right before the message `before`. With only `before`, it
+    /// takes the last messages, all of them when `before` is empty; otherwise the first ones.
+    /// Also tells whether the page reaches the end of the archive in the direction of paging.
+    /// Fails with `item-not-found` when `before` or `after` is not in the archive.
+    pub fn page<'a>(
+        &'a self,
+        jid: &str,
+        with: Option<&str>,
+        after: Option<&str>,
+        before: Option<&str>,
+        max: usize,
+    ) -> Result<(Vec<&'a ArchivedMessage>, bool), Error> {
+        let jid = jid_bare(jid);
+        let messages: Vec<&ArchivedMessage> = self
+            .messages_for(jid)
+            .filter(|message| with.is_none_or(|with| message.peer(jid) == jid_bare(with)))
+            .collect();
+        let position = |id: &str| {
+            messages
+                .iter()
+                .position(|message| message.id == id)
+                .ok_or_else(|| not_found(id))
+        };
+
+        let start = match after {
+            Some(after) => position(after)? + 1,
+            None => 0,
+        };
+        let end = match before {
+            Some("") | None => messages.len(),
+            Some(before) => position(before)?,
+        };
+        let end = end.max(start);
+        if before.is_some() && after.is_none() {
+            let start = end.saturating_sub(max);
+            Ok((messages[start..end].to_vec(), start == 0))
+        } else {
+            let end = end.min(start + max);
+            Ok((messages[start..end].to_vec(), end == messages.len()))
+        }
+    }
+
+    fn push(&mut self, jid: &str, message: ArchivedMessage) {
+        let messages = self.users.entry(jid.to_string()).or_default();
+        messages.push_back(message);
+        while messages.len() > self.retention {
+            messages.pop_front();
+        }
+    }
+
+    /// Stanza id of message `id` sent by `from`, only the original sender may correct or
+    /// retract a message.
+    fn find_own(&self, from: &str, id: &str) -> Result<String, Error> {
+        let original = self
+            .messages_for(from)
+            .rev()
+            .find(|message| message.origin_id.as_deref() == Some(id) && message.from == from)
+            .ok_or_else(|| not_found(id))?;
+        if original.retracted {
+            return Err(retracted(id));
+        }
+        Ok(original.id.clone())
+    }
+
+    /// Applies `change` to message `id` in the archive of `jid` and of the other party.
+    fn update(&mut self, jid: &str, id: &str, change: impl Fn(&mut ArchivedMessage)) {
+        let Some(peer) = self
+            .get(jid, id)
+            .map(|message| message.peer(jid).to_string())
+        else {
+            return;
+        };
+        let mut owners = vec![jid];
+        if peer != jid {
+            owners.push(&peer);
+        }
+        for owner in owners {
+            let message = self
+                .users
+                .get_mut(owner)
+                .and_then(|messages| messages.iter_mut().find(|message| message.id == id));
+            if let Some(message) = message {
+                change(message);
+            }
+        }
+    }
+}
+
+fn not_found(id: &str) -> Error {
+    let mut error = StanzaError::new("cancel", "item-not-found");
+    error.text = Some(format!("no message {} in the archive", id));
+    Error::Stanza(error)
+}
+
+fn retracted(id: &str) -> Error {
+    let mut error = StanzaError::new("cancel", "not-allowed");
+    error.text = Some(format!("message {} is retracted", id));
+    Error::Stanza(error)
+}
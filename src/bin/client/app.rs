@@ -0,0 +1,940 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant, SystemTime},
+};
+
+use color_eyre::eyre;
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use mini_jabber::*;
+
+/// Minimum time between two standalone chat state notifications to a contact
+const CHAT_STATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Body for clients that do not understand XEP-0424 retractions
+const RETRACT_FALLBACK: &str =
+    "This person attempted to retract a previous message, but it's unsupported by your client.";
+/// Archived messages fetched at once when scrolling back
+const HISTORY_PAGE: usize = 20;
+/// Lines moved by page up and page down
+const SCROLL_STEP: usize = 10;
+
+const HELP: &[&str] = &[
+    "/msg <jid> [text]  opens a conversation",
+    "/join <room> [nick]  joins a group chat",
+    "/nick <nick>  changes your nick, in the current group chat if any",
+    "/status avail|away|xa|dnd|chat [text]  changes your presence",
+    "/add <jid>  adds a contact and asks for their presence",
+    "/close  closes the conversation",
+    "/edit <text> corrects and /retract removes your last message",
+    "/passwd <password> changes your password and /unregister removes your account",
+    "/cmd <node> runs a command, /cmd next|prev|complete|cancel [var=value ...] continues it",
+    "/quit",
+    "tab and shift+tab switch conversations, page up and page down scroll",
+];
+
+/// A line of a conversation.
+pub struct Entry {
+    pub id: Option<String>,
+    /// `HH:MM` in UTC
+    pub time: String,
+    /// `None` for notices of the client itself
+    pub from: Option<String>,
+    pub text: String,
+    pub reactions: Vec<String>,
+}
+
+impl Entry {
+    fn notice(text: &str) -> Self {
+        Self {
+            id: None,
+            time: now_time(),
+            from: None,
+            text: text.to_string(),
+            reactions: Vec::new(),
+        }
+    }
+}
+
+/// A tab, either a chat with a contact or a group chat.
+pub struct Conversation {
+    /// Bare JID of the contact or the room, empty for the console
+    pub jid: String,
+    /// Our nick when this is a group chat
+    pub nick: Option<String>,
+    pub entries: Vec<Entry>,
+    pub unread: usize,
+    /// Lines scrolled up from the bottom
+    pub scroll: usize,
+    pub chat_state: Option<ChatState>,
+    /// Archive id of the oldest message we have
+    oldest: Option<String>,
+    /// Whether the archive has nothing older
+    complete: bool,
+    last_sent: Option<String>,
+}
+
+impl Conversation {
+    fn new(jid: &str, nick: Option<&str>) -> Self {
+        Self {
+            jid: jid.to_string(),
+            nick: nick.map(str::to_string),
+            entries: Vec::new(),
+            unread: 0,
+            scroll: 0,
+            chat_state: None,
+            oldest: None,
+            // Group chats have no archive on our server
+            complete: nick.is_some(),
+            last_sent: None,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match &self.nick {
+            _ if self.jid.is_empty() => "console".to_string(),
+            Some(_) => format!("#{}", local_part(&self.jid)),
+            None => local_part(&self.jid).to_string(),
+        }
+    }
+
+    pub fn is_chat(&self) -> bool {
+        !self.jid.is_empty() && self.nick.is_none()
+    }
+
+    fn entry_mut(&mut self, id: &str) -> Option<&mut Entry> {
+        self.entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.id.as_deref() == Some(id))
+    }
+}
+
+#[derive(Default)]
+pub struct Contact {
+    /// Last presence received, `None` until the contact shares it
+    pub presence: Option<Presence>,
+}
+
+impl Contact {
+    pub fn status(&self) -> &str {
+        match &self.presence {
+            Some(presence) if presence.presence_type.is_none() => {
+                presence.show.as_deref().unwrap_or("available")
+            }
+            _ => "offline",
+        }
+    }
+}
+
+pub struct App {
+    pub jid: String,
+    pub nick: String,
+    /// Contacts by bare JID
+    pub roster: BTreeMap<String, Contact>,
+    /// The console with notices comes first
+    pub tabs: Vec<Conversation>,
+    pub current: usize,
+    pub input: String,
+    pub quit: bool,
+    /// Our presence, without recipient
+    pub presence: Presence,
+    notifier: ChatStateNotifier,
+    last_keystroke: Option<Instant>,
+    /// Last response of the ad-hoc command being executed
+    running_command: Option<Command>,
+    /// Archive queries in flight by query id, with the conversation and the results so far
+    history: HashMap<String, (String, Vec<Entry>)>,
+    /// Stanzas for the main loop to send
+    outbox: Vec<Stanza>,
+}
+
+impl App {
+    pub fn new(jid: &str) -> Self {
+        let mut app = Self {
+            jid: jid_bare(jid).to_string(),
+            nick: local_part(jid).to_string(),
+            roster: BTreeMap::new(),
+            tabs: vec![Conversation::new("", None)],
+            current: 0,
+            input: String::new(),
+            quit: false,
+            presence: Presence::default(),
+            notifier: ChatStateNotifier::new(CHAT_STATE_INTERVAL),
+            last_keystroke: None,
+            running_command: None,
+            history: HashMap::new(),
+            outbox: Vec::new(),
+        };
+        app.console(&format!("connected as {}, /help lists the commands", jid));
+        app.send(Presence::default());
+        app
+    }
+
+    pub fn take_outbox(&mut self) -> Vec<Stanza> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub fn terminal_event(&mut self, event: Event, now: Instant) {
+        let Event::Key(key) = event else {
+            return;
+        };
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if control => self.quit = true,
+            KeyCode::Char('w') if control => self.close_tab(),
+            KeyCode::Char('u') if control => self.input.clear(),
+            KeyCode::Char(c) if !control => {
+                self.input.push(c);
+                self.typing(now);
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                self.typing(now);
+            }
+            KeyCode::Enter => {
+                self.last_keystroke = None;
+                let line = std::mem::take(&mut self.input);
+                self.submit(line.trim(), now);
+            }
+            KeyCode::Tab => self.switch(self.current + 1),
+            KeyCode::BackTab => self.switch(self.current + self.tabs.len() - 1),
+            KeyCode::PageUp => self.scroll_up(),
+            KeyCode::PageDown => {
+                let tab = &mut self.tabs[self.current];
+                tab.scroll = tab.scroll.saturating_sub(SCROLL_STEP);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn client_event(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::Message(message) => self.incoming_message(message),
+            ClientEvent::Presence(presence) => self.incoming_presence(presence),
+            ClientEvent::Iq(iq) => self.incoming_iq(iq),
+            ClientEvent::Disconnected(reason) => self.console(&format!("disconnected: {}", reason)),
+            ClientEvent::Reconnecting { attempt, delay } => self.console(&format!(
+                "reconnecting in {:.1}s (attempt {})",
+                delay.as_secs_f32(),
+                attempt
+            )),
+            ClientEvent::Reconnected => {
+                self.console("reconnected");
+                self.share_presence();
+            }
+        }
+    }
+
+    /// Moves the chat state of the current conversation along as the user stops typing.
+    pub fn tick(&mut self, now: Instant) {
+        let (Some(contact), Some(keystroke)) = (self.current_chat(), self.last_keystroke) else {
+            return;
+        };
+        // Composing again in case the notification was throttled while typing
+        let chat_state = ChatState::after_idle(now.duration_since(keystroke));
+        if chat_state == ChatState::Inactive {
+            self.last_keystroke = None;
+        }
+        self.chat_state(&contact, chat_state, now);
+    }
+
+    /// Lets everyone we talked to know we left.
+    pub fn leave(&mut self, now: Instant) {
+        for contact in self.notifier.active_contacts() {
+            self.chat_state(&contact, ChatState::Gone, now);
+        }
+        self.presence = Presence {
+            presence_type: Some("unavailable".to_string()),
+            ..Default::default()
+        };
+        self.share_presence();
+    }
+
+    fn send(&mut self, stanza: impl Into<Stanza>) {
+        self.outbox.push(stanza.into());
+    }
+
+    /// Adds a notice to the current conversation.
+    fn notice(&mut self, text: &str) {
+        self.add_entry(self.current, Entry::notice(text));
+    }
+
+    fn console(&mut self, text: &str) {
+        self.add_entry(0, Entry::notice(text));
+    }
+
+    fn add_entry(&mut self, index: usize, entry: Entry) {
+        let tab = &mut self.tabs[index];
+        tab.entries.push(entry);
+        if index != self.current {
+            tab.unread += 1;
+        }
+        // Keep the view where it is when scrolled up
+        if tab.scroll > 0 {
+            tab.scroll += 1;
+        }
+    }
+
+    fn switch(&mut self, index: usize) {
+        self.current = index % self.tabs.len();
+        self.tabs[self.current].unread = 0;
+    }
+
+    /// JID of the contact of the current conversation, unless it is a group chat.
+    fn current_chat(&self) -> Option<String> {
+        let tab = &self.tabs[self.current];
+        tab.is_chat().then(|| tab.jid.clone())
+    }
+
+    fn find_tab(&self, jid: &str) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.jid == jid)
+    }
+
+    /// Index of the conversation with `jid`, opened if needed. Chats with contacts start
+    /// with their history and put the contact in the roster.
+    fn open(&mut self, jid: &str, nick: Option<&str>) -> usize {
+        let jid = jid_bare(jid);
+        if let Some(index) = self.find_tab(jid) {
+            return index;
+        }
+
+        self.tabs.push(Conversation::new(jid, nick));
+        let index = self.tabs.len() - 1;
+        if nick.is_none() {
+            self.add_contact(jid);
+            self.request_history(index);
+        }
+        index
+    }
+
+    fn close_tab(&mut self) {
+        if self.current == 0 {
+            self.notice("the console stays open");
+            return;
+        }
+
+        let tab = self.tabs.remove(self.current);
+        if let Some(nick) = tab.nick {
+            self.send(Presence {
+                to: Some(format!("{}/{}", tab.jid, nick)),
+                presence_type: Some("unavailable".to_string()),
+                ..Default::default()
+            });
+        }
+        self.switch(self.current - 1);
+    }
+
+    fn scroll_up(&mut self) {
+        let tab = &mut self.tabs[self.current];
+        tab.scroll = (tab.scroll + SCROLL_STEP).min(tab.entries.len());
+        if tab.scroll + SCROLL_STEP >= tab.entries.len() {
+            self.request_history(self.current);
+        }
+    }
+
+    /// Asks the archive for the page before the oldest message we have.
+    fn request_history(&mut self, index: usize) {
+        let tab = &self.tabs[index];
+        let in_flight = self.history.values().any(|(jid, _)| *jid == tab.jid);
+        if tab.complete || in_flight {
+            return;
+        }
+
+        let id = next_stanza_id();
+        let set = ResultSet {
+            max: Some(HISTORY_PAGE),
+            before: Some(tab.oldest.clone().unwrap_or_default()),
+            ..Default::default()
+        };
+        let query = MamQuery::new(&id, Some(&tab.jid), set);
+        self.history
+            .insert(id.clone(), (tab.jid.clone(), Vec::new()));
+        self.send(Iq::new(IqType::Set, id, Some(IqPayload::MamQuery(query))));
+    }
+
+    fn typing(&mut self, now: Instant) {
+        self.last_keystroke = Some(now);
+        if self.input.starts_with('/') {
+            return;
+        }
+        if let Some(contact) = self.current_chat() {
+            self.chat_state(&contact, ChatState::Composing, now);
+        }
+    }
+
+    /// Sends a standalone chat state notification unless the notifier suppresses it.
+    fn chat_state(&mut self, contact: &str, chat_state: ChatState, now: Instant) {
+        let Some(chat_state) = self.notifier.notify(contact, chat_state, now) else {
+            return;
+        };
+        self.send(Message {
+            to: Some(contact.to_string()),
+            message_type: Some("chat".to_string()),
+            chat_state: Some(chat_state),
+            ..Default::default()
+        });
+    }
+
+    fn submit(&mut self, line: &str, now: Instant) {
+        if line.is_empty() {
+            return;
+        }
+        let result = match line.starts_with('/') {
+            true => self.command(line, now),
+            false => self.say(line, now),
+        };
+        if let Err(e) = result {
+            self.notice(&e.to_string());
+        }
+    }
+
+    fn say(&mut self, body: &str, now: Instant) -> eyre::Result<()> {
+        let tab = &self.tabs[self.current];
+        if tab.jid.is_empty() {
+            eyre::bail!("pick a conversation with /msg <jid> first");
+        }
+
+        let id = next_stanza_id();
+        let mut message = Message {
+            id: Some(id.clone()),
+            to: Some(tab.jid.clone()),
+            body: Some(body.to_string()),
+            ..Default::default()
+        };
+        if tab.nick.is_some() {
+            // The room echoes our messages back to us
+            message.message_type = Some("groupchat".to_string());
+            self.send(message);
+            return Ok(());
+        }
+
+        let contact = tab.jid.clone();
+        message.message_type = Some("chat".to_string());
+        message.chat_state = self.notifier.outgoing_message(&contact, now);
+        self.send(message);
+
+        self.tabs[self.current].last_sent = Some(id.clone());
+        let entry = Entry {
+            id: Some(id),
+            time: now_time(),
+            from: Some(self.nick.clone()),
+            text: body.to_string(),
+            reactions: Vec::new(),
+        };
+        self.add_entry(self.current, entry);
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str, now: Instant) -> eyre::Result<()> {
+        let (name, arguments) = line.split_once(' ').unwrap_or((line, ""));
+        let arguments = arguments.trim();
+        let (first, rest) = arguments.split_once(' ').unwrap_or((arguments, ""));
+        let rest = rest.trim();
+
+        match name {
+            "/quit" => self.quit = true,
+            "/help" => {
+                for line in HELP {
+                    self.notice(line);
+                }
+            }
+            "/msg" => {
+                if first.is_empty() {
+                    eyre::bail!("usage: /msg <jid> [text]");
+                }
+                let index = self.open(first, None);
+                self.switch(index);
+                if !rest.is_empty() {
+                    self.say(rest, now)?;
+                }
+            }
+            "/join" => {
+                if first.is_empty() {
+                    eyre::bail!("usage: /join <room> [nick]");
+                }
+                let nick = match rest {
+                    "" => self.nick.clone(),
+                    nick => nick.to_string(),
+                };
+                let index = self.open(first, Some(&nick));
+                self.switch(index);
+                self.send(Presence {
+                    to: Some(format!("{}/{}", jid_bare(first), nick)),
+                    ..self.presence.clone()
+                });
+            }
+            "/nick" => {
+                if arguments.is_empty() {
+                    eyre::bail!("usage: /nick <nick>");
+                }
+                let tab = &mut self.tabs[self.current];
+                match &mut tab.nick {
+                    Some(nick) => {
+                        *nick = arguments.to_string();
+                        let to = format!("{}/{}", tab.jid, arguments);
+                        self.send(Presence {
+                            to: Some(to),
+                            ..self.presence.clone()
+                        });
+                    }
+                    None => {
+                        self.nick = arguments.to_string();
+                        self.notice(&format!("you are now {}", arguments));
+                    }
+                }
+            }
+            "/status" => {
+                let show = match first {
+                    "avail" | "available" => None,
+                    "away" | "xa" | "dnd" | "chat" => Some(first.to_string()),
+                    _ => eyre::bail!("usage: /status avail|away|xa|dnd|chat [text]"),
+                };
+                self.presence = Presence {
+                    show,
+                    status: (!rest.is_empty()).then(|| rest.to_string()),
+                    ..Default::default()
+                };
+                self.share_presence();
+                self.notice(&format!("you are {}", first));
+            }
+            "/add" => {
+                if arguments.is_empty() {
+                    eyre::bail!("usage: /add <jid>");
+                }
+                let jid = jid_bare(arguments).to_string();
+                for presence_type in ["subscribe", "subscribed"] {
+                    self.send(Presence {
+                        to: Some(jid.clone()),
+                        presence_type: Some(presence_type.to_string()),
+                        ..Default::default()
+                    });
+                }
+                self.add_contact(&jid);
+                self.notice(&format!("added {}", jid));
+            }
+            "/close" => self.close_tab(),
+            "/edit" => {
+                // Corrections always point at the original message
+                let tab = &self.tabs[self.current];
+                let (Some(id), true) = (tab.last_sent.clone(), tab.is_chat()) else {
+                    eyre::bail!("nothing to edit");
+                };
+                self.send(Message {
+                    id: Some(next_stanza_id()),
+                    to: Some(tab.jid.clone()),
+                    message_type: Some("chat".to_string()),
+                    body: Some(arguments.to_string()),
+                    replace: Some(Replace { id: id.clone() }),
+                    ..Default::default()
+                });
+                if let Some(entry) = self.tabs[self.current].entry_mut(&id) {
+                    entry.text = format!("{} (edited)", arguments);
+                }
+            }
+            "/retract" => {
+                let tab = &mut self.tabs[self.current];
+                let (Some(id), true) = (tab.last_sent.take(), tab.is_chat()) else {
+                    eyre::bail!("nothing to retract");
+                };
+                let to = tab.jid.clone();
+                if let Some(entry) = tab.entry_mut(&id) {
+                    entry.text = "(retracted)".to_string();
+                }
+                self.send(Message {
+                    id: Some(next_stanza_id()),
+                    to: Some(to),
+                    message_type: Some("chat".to_string()),
+                    body: Some(RETRACT_FALLBACK.to_string()),
+                    retract: Some(Retract { id }),
+                    ..Default::default()
+                });
+            }
+            "/passwd" => {
+                if arguments.is_empty() {
+                    eyre::bail!("usage: /passwd <password>");
+                }
+                self.send_register(RegisterQuery {
+                    username: Some(local_part(&self.jid).to_string()),
+                    password: Some(arguments.to_string()),
+                    ..Default::default()
+                });
+            }
+            "/unregister" => self.send_register(RegisterQuery {
+                remove: true,
+                ..Default::default()
+            }),
+            "/cmd" => {
+                let command = command_request(arguments, self.running_command.as_ref())?;
+                let payload = Some(IqPayload::Command(command));
+                self.send(Iq::new(IqType::Set, next_stanza_id(), payload));
+            }
+            _ => eyre::bail!("unknown command {}, /help lists them", name),
+        }
+        Ok(())
+    }
+
+    /// Puts `jid` in the roster, sending it our presence the first time.
+    fn add_contact(&mut self, jid: &str) {
+        if self.roster.contains_key(jid) {
+            return;
+        }
+        self.roster.insert(jid.to_string(), Contact::default());
+        self.send(Presence {
+            to: Some(jid.to_string()),
+            ..self.presence.clone()
+        });
+    }
+
+    fn send_register(&mut self, query: RegisterQuery) {
+        let payload = Some(IqPayload::Register(query));
+        self.send(Iq::new(IqType::Set, next_stanza_id(), payload));
+    }
+
+    /// Sends our presence to the server, every contact and every room, as the server has no
+    /// roster to broadcast it to.
+    fn share_presence(&mut self) {
+        let mut recipients: Vec<String> = self.roster.keys().cloned().collect();
+        for tab in &self.tabs {
+            if let Some(nick) = &tab.nick {
+                recipients.push(format!("{}/{}", tab.jid, nick));
+            }
+        }
+
+        self.send(self.presence.clone());
+        for to in recipients {
+            self.send(Presence {
+                to: Some(to),
+                ..self.presence.clone()
+            });
+        }
+    }
+
+    fn incoming_message(&mut self, message: Message) {
+        if let Some(archived) = message.archived {
+            self.archived_message(archived);
+            return;
+        }
+        let Some(from) = message.from.clone() else {
+            return;
+        };
+
+        if message.message_type.as_deref() == Some("groupchat") {
+            let (room, nick) = from.split_once('/').unwrap_or((&from, ""));
+            let (Some(index), Some(body)) = (self.find_tab(room), message.body) else {
+                return;
+            };
+            let entry = Entry {
+                id: message.id,
+                time: now_time(),
+                from: Some(nick.to_string()),
+                text: body,
+                reactions: Vec::new(),
+            };
+            self.add_entry(index, entry);
+            return;
+        }
+
+        let contact = jid_bare(&from).to_string();
+        self.notifier.incoming_message(&contact, &message);
+        match MessageEvent::from_message(&message) {
+            Some(MessageEvent::Chat { id, body, .. }) => {
+                let index = self.open(&contact, None);
+                self.tabs[index].chat_state = message.chat_state;
+                let entry = Entry {
+                    id,
+                    time: now_time(),
+                    from: Some(local_part(&contact).to_string()),
+                    text: body,
+                    reactions: Vec::new(),
+                };
+                self.add_entry(index, entry);
+            }
+            Some(MessageEvent::Correction { id, body, .. }) => {
+                if let Some(entry) = self.entry_mut(&contact, &id) {
+                    entry.text = format!("{} (edited)", body);
+                }
+            }
+            Some(MessageEvent::Retraction { id, .. }) => {
+                if let Some(entry) = self.entry_mut(&contact, &id) {
+                    entry.text = "(retracted)".to_string();
+                    entry.reactions.clear();
+                }
+            }
+            Some(MessageEvent::Reaction { id, reactions, .. }) => {
+                if let Some(entry) = self.entry_mut(&contact, &id) {
+                    entry.reactions = reactions;
+                }
+            }
+            Some(MessageEvent::ChatState { chat_state, .. }) => {
+                if let Some(index) = self.find_tab(&contact) {
+                    self.tabs[index].chat_state = Some(chat_state);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn entry_mut(&mut self, contact: &str, id: &str) -> Option<&mut Entry> {
+        let index = self.find_tab(contact)?;
+        self.tabs[index].entry_mut(id)
+    }
+
+    fn archived_message(&mut self, archived: MamResult) {
+        let Some((_, entries)) = archived
+            .query_id
+            .as_ref()
+            .and_then(|query_id| self.history.get_mut(query_id))
+        else {
+            return;
+        };
+        let Some(body) = archived.message.body else {
+            return;
+        };
+
+        let from = archived.message.from.unwrap_or_default();
+        let name = match jid_bare(&from) == self.jid {
+            true => self.nick.clone(),
+            false => local_part(&from).to_string(),
+        };
+        let time = archived
+            .stamp
+            .as_deref()
+            .and_then(|stamp| stamp.get(11..16))
+            .unwrap_or_default();
+        // Corrections and reactions refer to the id the sender chose
+        entries.push(Entry {
+            id: archived.message.id.or(Some(archived.id)),
+            time: time.to_string(),
+            from: Some(name),
+            text: body,
+            reactions: Vec::new(),
+        });
+    }
+
+    /// Puts a page of history in front of its conversation, without the messages that
+    /// arrived live in the meantime.
+    fn history_done(&mut self, query_id: &str, fin: MamFin) {
+        let Some((jid, mut entries)) = self.history.remove(query_id) else {
+            return;
+        };
+        let Some(index) = self.find_tab(&jid) else {
+            return;
+        };
+
+        let tab = &mut self.tabs[index];
+        tab.complete = fin.complete;
+        if fin.set.first.is_some() {
+            tab.oldest = fin.set.first;
+        }
+        let known: HashSet<String> = tab.entries.iter().filter_map(|e| e.id.clone()).collect();
+        entries.retain(|entry| entry.id.as_ref().is_none_or(|id| !known.contains(id)));
+        entries.append(&mut tab.entries);
+        tab.entries = entries;
+    }
+
+    fn incoming_presence(&mut self, presence: Presence) {
+        let Some(from) = presence.from.clone() else {
+            return;
+        };
+
+        if let Some((room, nick)) = from.split_once('/') {
+            if let Some(index) = self.find_tab(room).filter(|&i| self.tabs[i].nick.is_some()) {
+                let text = match presence.presence_type.as_deref() {
+                    Some("unavailable") => format!("{} left", nick),
+                    _ => format!("{} is here", nick),
+                };
+                self.add_entry(index, Entry::notice(&text));
+                return;
+            }
+        }
+
+        match presence.presence_type.as_deref() {
+            Some("subscribe") => self.console(&format!(
+                "{} wants to see your presence, /add {} to accept",
+                from, from
+            )),
+            Some("subscribed") => self.console(&format!("{} accepted your request", from)),
+            None | Some("unavailable") => {
+                let jid = jid_bare(&from).to_string();
+                let contact = self.roster.entry(jid.clone()).or_default();
+                let was_offline = contact.status() == "offline";
+                contact.presence = Some(presence);
+                // Without a roster on the server, contacts coming online learn our presence
+                // from us
+                if was_offline && contact.status() != "offline" {
+                    self.send(Presence {
+                        to: Some(jid),
+                        ..self.presence.clone()
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn incoming_iq(&mut self, iq: Iq) {
+        match iq {
+            Iq {
+                payload: Some(IqPayload::MamFin(fin)),
+                id,
+                ..
+            } => self.history_done(&id, fin),
+            Iq {
+                error: Some(error),
+                id,
+                ..
+            } => {
+                if let Some((jid, _)) = self.history.remove(&id) {
+                    // Without an archive there is no history to scroll back to
+                    if let Some(index) = self.find_tab(&jid) {
+                        self.tabs[index].complete = true;
+                    }
+                    return;
+                }
+                let text = error.text.map(|t| format!(" ({})", t)).unwrap_or_default();
+                self.notice(&format!("request failed: {}{}", error.condition, text));
+            }
+            Iq {
+                payload: Some(IqPayload::Command(command)),
+                ..
+            } => {
+                self.show_command(&command);
+                let executing = command.status == Some(CommandStatus::Executing);
+                self.running_command = executing.then_some(command);
+            }
+            Iq {
+                iq_type: IqType::Result,
+                id,
+                ..
+            } => self.notice(&format!("request {} done", id)),
+            // We don't answer requests from other entities
+            iq => self.send(iq.error(StanzaError::new("cancel", "service-unavailable"))),
+        }
+    }
+
+    fn show_command(&mut self, command: &Command) {
+        let status = command
+            .status
+            .map(|status| status.name())
+            .unwrap_or_default();
+        self.notice(&format!("{} {}", command.node, status));
+
+        for note in &command.notes {
+            self.notice(&format!("  [{}] {}", note.note_type, note.text));
+        }
+
+        if let Some(form) = &command.form {
+            if let Some(title) = &form.title {
+                self.notice(&format!("  {}", title));
+            }
+            for instructions in &form.instructions {
+                self.notice(&format!("  {}", instructions));
+            }
+            for field in &form.fields {
+                if field.kind() == FieldType::Hidden {
+                    continue;
+                }
+                let var = field.var.as_deref().unwrap_or_default();
+                let label = field.label.as_deref().unwrap_or(var);
+                let required = if field.required { " (required)" } else { "" };
+                let options = field
+                    .options
+                    .iter()
+                    .map(|option| option.value.as_str())
+                    .collect::<Vec<_>>();
+                let options = match options.is_empty() {
+                    true => String::new(),
+                    false => format!(" [{}]", options.join("|")),
+                };
+                self.notice(&format!(
+                    "  {} <{}>{}{}: {}",
+                    var,
+                    label,
+                    required,
+                    options,
+                    field.values.join(", ")
+                ));
+            }
+        }
+
+        if !command.actions.is_empty() {
+            let actions = command
+                .actions
+                .iter()
+                .map(|action| action.name())
+                .collect::<Vec<_>>();
+            self.notice(&format!("  actions: {}", actions.join(", ")));
+        }
+    }
+}
+
+/// Builds a command request from `/cmd` arguments. Values of `var=value` pairs run until the
+/// next pair, repeating a var gives it multiple values.
+fn command_request(arguments: &str, running: Option<&Command>) -> eyre::Result<Command> {
+    let mut words = arguments.split_whitespace();
+    let first = words.next().ok_or(eyre::eyre!("usage: /cmd <node>"))?;
+
+    let Some(action) = CommandAction::from_name(first.as_bytes()) else {
+        // Admin commands can be named by their short name
+        let node = match first.contains(':') {
+            true => first.to_string(),
+            false => format!("{}#{}", ADMIN_NS, first),
+        };
+        return Ok(Command::execute(&node));
+    };
+
+    let running = running.ok_or(eyre::eyre!("no command is running"))?;
+    let mut request = Command {
+        action: Some(action),
+        status: None,
+        actions: Vec::new(),
+        notes: Vec::new(),
+        form: None,
+        ..running.clone()
+    };
+    if action == CommandAction::Cancel || action == CommandAction::Prev {
+        return Ok(request);
+    }
+
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for word in words {
+        match (word.split_once('='), pairs.last_mut()) {
+            (Some((var, value)), _) => pairs.push((var.to_string(), value.to_string())),
+            (None, Some((_, value))) => {
+                value.push(' ');
+                value.push_str(word);
+            }
+            (None, None) => eyre::bail!("expected var=value, got {}", word),
+        }
+    }
+
+    let form = running
+        .form
+        .as_ref()
+        .ok_or(eyre::eyre!("the command has no form"))?;
+    let mut submission = form.submission();
+    let vars: HashSet<&str> = pairs.iter().map(|(var, _)| var.as_str()).collect();
+    for var in vars {
+        let values = pairs
+            .iter()
+            .filter(|(name, _)| name == var)
+            .map(|(_, value)| value.clone())
+            .collect();
+        submission.set_values(var, values);
+    }
+    request.form = Some(submission);
+    Ok(request)
+}
+
+fn local_part(jid: &str) -> &str {
+    let bare = jid_bare(jid);
+    bare.split_once('@').map(|(local, _)| local).unwrap_or(bare)
+}
+
+/// Current time as `HH:MM` in UTC.
+fn now_time() -> String {
+    datetime(SystemTime::now())[11..16].to_string()
+}
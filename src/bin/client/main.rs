@@ -0,0 +1,187 @@
+mod app;
+mod ui;
+
+use std::{
+    fs::File,
+    io::{stdout, Stdout},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    event::{self, Event},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures_util::StreamExt;
+use mini_jabber::*;
+use ratatui::{backend::CrosstermBackend, Terminal};
+use tokio::sync::mpsc;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use app::App;
+
+#[tokio::main]
+async fn main() {
+    let mut jid = "zet@localhost".to_string();
+    let mut password = "password".to_string();
+    let mut server = format!("ws://127.0.0.1:{}", CLIENT_PORT);
+    let mut invite: Option<String> = None;
+    let mut register = false;
+    let mut log: Option<String> = None;
+    let mut log_level = LogLevel::Info;
+
+    // client [register] [--jid <jid>] [--password <password>] [--invite <token>]
+    //        [--server <ws[s]://host:port>] [--log <file>] [--log-level <level>]
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "register" => register = true,
+            "--jid" => jid = args.next().expect("missing jid"),
+            "--password" => password = args.next().expect("missing password"),
+            "--invite" => invite = Some(args.next().expect("missing invite token")),
+            "--server" => server = args.next().expect("missing server address"),
+            "--log" => log = Some(args.next().expect("missing log file")),
+            "--log-level" => {
+                log_level = args
+                    .next()
+                    .expect("missing log level")
+                    .parse()
+                    .expect("invalid log level");
+            }
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    // The terminal belongs to the UI, logs go to a file
+    if let Some(path) = log {
+        let file = File::create(&path).expect("failed to create the log file");
+        tracing_subscriber::fmt()
+            .with_max_level(LevelFilter::from(log_level))
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .finish()
+            .with(log_level.targets())
+            .init();
+    }
+
+    let builder = Client::builder(&jid, &password)
+        .server(&server)
+        .tls(tls_policy(&server));
+    if register {
+        run_register(builder.register(invite.as_deref())).await;
+    } else {
+        run_client(builder).await;
+    }
+}
+
+/// Passwords go in clear only to a server on the same host, like the server accepts them.
+fn tls_policy(server: &str) -> TlsPolicy {
+    let loopback = url::Url::parse(server).is_ok_and(|url| match url.host() {
+        Some(url::Host::Domain(domain)) => domain == "localhost",
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    });
+    match loopback {
+        true => TlsPolicy::Optional,
+        false => TlsPolicy::Required,
+    }
+}
+
+/// Creates the account and exits.
+async fn run_register(builder: ClientBuilder) {
+    match builder.connect().await {
+        Ok(client) => {
+            println!("account {} is ready", client.jid());
+            client.disconnect().await.ok();
+        }
+        Err(e) => println!("{}", e),
+    }
+}
+
+async fn run_client(builder: ClientBuilder) {
+    let mut client = match builder.connect().await {
+        Ok(client) => client,
+        Err(e) => {
+            println!("failed to connect: {}", e);
+            return;
+        }
+    };
+    let mut app = App::new(client.jid());
+
+    let mut tui = match Tui::enter() {
+        Ok(tui) => tui,
+        Err(e) => {
+            println!("failed to set up the terminal: {}", e);
+            return;
+        }
+    };
+    let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || read_input(input_tx));
+    let mut idle_check = tokio::time::interval(Duration::from_secs(1));
+    let mut closed = false;
+
+    while !app.quit {
+        if let Err(e) = tui.0.draw(|frame| ui::draw(frame, &app)) {
+            drop(tui);
+            println!("failed to draw: {}", e);
+            return;
+        }
+
+        tokio::select! {
+            Some(event) = input_rx.recv() => app.terminal_event(event, Instant::now()),
+            event = client.next() => match event {
+                Some(event) => app.client_event(event),
+                None => {
+                    closed = true;
+                    break;
+                }
+            },
+            _ = idle_check.tick() => app.tick(Instant::now()),
+        }
+
+        for stanza in app.take_outbox() {
+            client.send(stanza).await.ok();
+        }
+    }
+
+    app.leave(Instant::now());
+    for stanza in app.take_outbox() {
+        client.send(stanza).await.ok();
+    }
+    client.disconnect().await.ok();
+    drop(tui);
+    if closed {
+        println!("connection closed");
+    }
+}
+
+/// Full-screen terminal in raw mode, restored when dropped.
+struct Tui(Terminal<CrosstermBackend<Stdout>>);
+
+impl Tui {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        Ok(Self(Terminal::new(CrosstermBackend::new(stdout()))?))
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+        execute!(self.0.backend_mut(), LeaveAlternateScreen).ok();
+        self.0.show_cursor().ok();
+    }
+}
+
+/// Terminal events are read on their own thread, crossterm blocks while waiting for them.
+fn read_input(tx: mpsc::UnboundedSender<Event>) {
+    while let Ok(event) = event::read() {
+        if tx.send(event).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use mini_jabber::ChatState;
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Tabs},
+    Frame,
+};
+
+use crate::app::{App, Conversation, Entry};
+
+/// Width of the roster pane
+const ROSTER_WIDTH: u16 = 30;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(ROSTER_WIDTH), Constraint::Min(10)])
+        .split(rows[0]);
+    let conversation = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(2)])
+        .split(columns[1]);
+
+    draw_roster(frame, app, columns[0]);
+    draw_tabs(frame, app, conversation[0]);
+    draw_conversation(frame, &app.tabs[app.current], app, conversation[1]);
+    draw_input(frame, app, rows[1]);
+}
+
+fn draw_roster(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .roster
+        .iter()
+        .map(|(jid, contact)| {
+            let status = contact.status();
+            let mut spans = vec![
+                Span::styled("● ", Style::default().fg(status_color(status))),
+                Span::raw(jid.clone()),
+            ];
+
+            if let Some(tab) = app.tabs.iter().find(|tab| tab.jid == *jid) {
+                if tab.chat_state == Some(ChatState::Composing) {
+                    spans.push(Span::styled(" …", Style::default().fg(Color::Gray)));
+                }
+                if tab.unread > 0 {
+                    spans.push(Span::styled(
+                        format!(" ({})", tab.unread),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ));
+                }
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let own_status = app.presence.show.as_deref().unwrap_or("available");
+    let title = Line::from(vec![
+        Span::styled("● ", Style::default().fg(status_color(own_status))),
+        Span::raw(app.jid.clone()),
+    ]);
+    let roster = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_widget(roster, area);
+}
+
+fn draw_tabs(frame: &mut Frame, app: &App, area: Rect) {
+    let titles: Vec<Line> = app
+        .tabs
+        .iter()
+        .map(|tab| match tab.unread {
+            0 => Line::from(tab.title()),
+            unread => Line::from(vec![
+                Span::raw(tab.title()),
+                Span::styled(
+                    format!(" ({})", unread),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+            ]),
+        })
+        .collect();
+
+    let tabs = Tabs::new(titles).select(app.current).highlight_style(
+        Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD),
+    );
+    frame.render_widget(tabs, area);
+}
+
+fn draw_conversation(frame: &mut Frame, tab: &Conversation, app: &App, area: Rect) {
+    let mut title = vec![Span::raw(tab.title())];
+    if tab.is_chat() {
+        let status = app
+            .roster
+            .get(&tab.jid)
+            .map(|contact| contact.status())
+            .unwrap_or("offline");
+        title.push(Span::styled(
+            format!(" ({})", status),
+            Style::default().fg(status_color(status)),
+        ));
+    }
+    if let Some(nick) = &tab.nick {
+        title.push(Span::raw(format!(" as {}", nick)));
+    }
+    match tab.chat_state {
+        Some(ChatState::Composing) => title.push(Span::raw(" is typing…")),
+        Some(ChatState::Paused) => title.push(Span::raw(" stopped typing")),
+        Some(ChatState::Gone) => title.push(Span::raw(" left the conversation")),
+        _ => {}
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Line::from(title));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let width = inner.width as usize;
+    let lines: Vec<Line> = tab
+        .entries
+        .iter()
+        .flat_map(|entry| entry_lines(entry, width))
+        .collect();
+
+    // Scrolling counts lines up from the bottom
+    let height = inner.height as usize;
+    let scroll = tab.scroll.min(lines.len().saturating_sub(height));
+    let end = lines.len() - scroll;
+    let start = end.saturating_sub(height);
+    frame.render_widget(Paragraph::new(lines[start..end].to_vec()), inner);
+}
+
+/// Wraps an entry to the width of the pane, with the time and sender on the first line.
+fn entry_lines(entry: &Entry, width: usize) -> Vec<Line<'static>> {
+    let Some(from) = &entry.from else {
+        let text = format!("{} -- {}", entry.time, entry.text);
+        let style = Style::default().fg(Color::DarkGray);
+        return wrap(&text, width)
+            .into_iter()
+            .map(|line| Line::styled(line, style))
+            .collect();
+    };
+
+    let mut text = entry.text.clone();
+    if !entry.reactions.is_empty() {
+        text = format!("{} [{}]", text, entry.reactions.join(" "));
+    }
+    let prefix = format!("{} {}: ", entry.time, from);
+    let mut lines = wrap(&format!("{}{}", prefix, text), width).into_iter();
+
+    let mut result = Vec::new();
+    if let Some(first) = lines.next() {
+        let prefix_length = prefix.chars().count();
+        match first.char_indices().nth(prefix_length) {
+            Some((split, _)) if first.chars().count() > prefix_length => {
+                let rest = first[split..].to_string();
+                result.push(Line::from(vec![
+                    Span::styled(entry.time.clone(), Style::default().fg(Color::DarkGray)),
+                    Span::raw(" "),
+                    Span::styled(
+                        format!("{}:", from),
+                        Style::default()
+                            .fg(nick_color(from))
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" "),
+                    Span::raw(rest),
+                ]));
+            }
+            _ => result.push(Line::raw(first)),
+        }
+    }
+    result.extend(lines.map(Line::raw));
+    result
+}
+
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let title = match app.input.starts_with('/') {
+        true => "command",
+        false => "message",
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+
+    // Keep the end of long inputs in view
+    let width = (inner.width as usize).saturating_sub(1);
+    let length = app.input.chars().count();
+    let visible: String = app
+        .input
+        .chars()
+        .skip(length.saturating_sub(width))
+        .collect();
+    let cursor = visible.chars().count() as u16;
+
+    frame.render_widget(Paragraph::new(visible).block(block), area);
+    frame.set_cursor(inner.x + cursor, inner.y);
+}
+
+/// Splits a text in lines of at most `width` characters, at least one line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width.max(1))
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+fn status_color(status: &str) -> Color {
+    match status {
+        "available" | "chat" => Color::Green,
+        "away" | "xa" => Color::Yellow,
+        "dnd" => Color::Red,
+        _ => Color::DarkGray,
+    }
+}
+
+/// Stable color for each nick so that speakers are easy to tell apart.
+fn nick_color(nick: &str) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Cyan,
+        Color::Magenta,
+        Color::Blue,
+        Color::Green,
+        Color::Yellow,
+        Color::LightRed,
+    ];
+    let hash = nick.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    });
+    COLORS[hash % COLORS.len()]
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use mini_jabber::*;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+type Writer = SplitSink<PeerStream, WsMessage>;
+
+/// Serverless chat with the peers on the local network (XEP-0174).
+#[tokio::main]
+async fn main() {
+    let mut name = "zet@localhost".to_string();
+    let mut port = LINK_LOCAL_PORT;
+    let mut interface: Option<String> = None;
+    let mut nick: Option<String> = None;
+
+    // peer [--name <user@machine>] [--port <port>] [--interface <name>] [--nick <nick>]
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => name = args.next().expect("missing name"),
+            "--port" => {
+                port = args
+                    .next()
+                    .expect("missing port")
+                    .parse()
+                    .expect("invalid port");
+            }
+            "--interface" => interface = Some(args.next().expect("missing interface")),
+            "--nick" => nick = Some(args.next().expect("missing nick")),
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .expect("failed to bind");
+    let mut presence = PeerPresence {
+        nick,
+        ..Default::default()
+    };
+    let link_local = LinkLocal::advertise(&name, port, &presence, interface.as_deref())
+        .expect("failed to advertise");
+    let mut events = link_local.browse().expect("failed to browse");
+
+    println!(":: link-local peer {} on port {} ::", name, port);
+    println!("use /to <peer> to pick a peer, /peers to list them and /quit to exit");
+    println!("/status avail|away|dnd [message] changes your presence");
+
+    let mut peers: HashMap<String, Peer> = HashMap::new();
+    let mut streams: HashMap<String, Writer> = HashMap::new();
+    let mut current: Option<String> = None;
+
+    // Stanzas from every stream end up here, with the name of the peer that sent them
+    let (incoming_tx, mut incoming) = mpsc::unbounded_channel::<(String, Option<String>)>();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => match event {
+                PeerEvent::Available(peer) => {
+                    let status = peer.presence.status.name();
+                    match &peer.presence.msg {
+                        Some(msg) => println!("+ {} ({}: {})", peer.name, status, msg),
+                        None => println!("+ {} ({})", peer.name, status),
+                    }
+                    peers.insert(peer.name.clone(), peer);
+                }
+                PeerEvent::Unavailable(peer) => {
+                    println!("- {}", peer);
+                    peers.remove(&peer);
+                    streams.remove(&peer);
+                }
+            },
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else { continue };
+                match accept_peer(&name, stream).await {
+                    Ok((peer, stream)) => {
+                        let (writer, reader) = stream.split();
+                        streams.insert(peer.clone(), writer);
+                        tokio::spawn(forward_incoming(peer, reader, incoming_tx.clone()));
+                    }
+                    Err(e) => println!("failed to accept a peer: {}", e),
+                }
+            }
+            Some((peer, stanza)) = incoming.recv() => {
+                let Some(stanza) = stanza else {
+                    streams.remove(&peer);
+                    continue;
+                };
+                if let Ok(message) = mini_jabber::Message::from_string(&stanza) {
+                    if let Some(body) = message.body {
+                        println!("{}: {}", peer, body);
+                    }
+                }
+            }
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else { break };
+                let line = line.trim();
+
+                if line == "/quit" {
+                    break;
+                } else if line == "/peers" {
+                    for peer in peers.values() {
+                        println!("  {} ({})", peer.name, peer.presence.status.name());
+                    }
+                } else if let Some(peer) = line.strip_prefix("/to ") {
+                    current = Some(peer.trim().to_string());
+                } else if let Some(status) = line.strip_prefix("/status ") {
+                    let (status, msg) = status.split_once(' ').unwrap_or((status, ""));
+                    let Some(status) = PeerStatus::from_name(status) else {
+                        println!("unknown status {}", status);
+                        continue;
+                    };
+                    presence.status = status;
+                    presence.msg = (!msg.is_empty()).then(|| msg.to_string());
+                    if let Err(e) = link_local.update_presence(&presence) {
+                        println!("failed to update presence: {}", e);
+                    }
+                } else if !line.is_empty() {
+                    let Some(to) = current.clone() else {
+                        println!("pick a peer with /to <peer> first");
+                        continue;
+                    };
+
+                    if !streams.contains_key(&to) {
+                        let Some(peer) = peers.get(&to) else {
+                            println!("{} is not around", to);
+                            continue;
+                        };
+                        match connect_peer(&name, peer).await {
+                            Ok(stream) => {
+                                let (writer, reader) = stream.split();
+                                streams.insert(to.clone(), writer);
+                                tokio::spawn(forward_incoming(
+                                    to.clone(),
+                                    reader,
+                                    incoming_tx.clone(),
+                                ));
+                            }
+                            Err(e) => {
+                                println!("failed to connect to {}: {}", to, e);
+                                continue;
+                            }
+                        }
+                    }
+
+                    let message = mini_jabber::Message {
+                        from: Some(name.clone()),
+                        to: Some(to.clone()),
+                        message_type: Some("chat".to_string()),
+                        body: Some(line.to_string()),
+                        ..Default::default()
+                    };
+                    let writer = streams.get_mut(&to).expect("stream was just opened");
+                    if writer
+                        .send(WsMessage::Text(message.into_string()))
+                        .await
+                        .is_err()
+                    {
+                        println!("{} closed the stream", to);
+                        streams.remove(&to);
+                    }
+                }
+            }
+        }
+    }
+
+    for (_, mut writer) in streams {
+        writer.close().await.ok();
+    }
+    link_local.shutdown();
+}
+
+/// Passes the stanzas of a stream to the main loop, then `None` once it closes.
+async fn forward_incoming(
+    peer: String,
+    mut reader: futures_util::stream::SplitStream<PeerStream>,
+    incoming: mpsc::UnboundedSender<(String, Option<String>)>,
+) {
+    while let Some(stanza) = reader.get_next_text().await {
+        if incoming.send((peer.clone(), Some(stanza))).is_err() {
+            return;
+        }
+    }
+    incoming.send((peer, None)).ok();
+}
@@ -1,119 +1,1924 @@
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    fmt,
+    io::IsTerminal,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::eyre;
 use futures_util::{
     stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
+    SinkExt, Stream, StreamExt,
 };
 use mini_jabber::*;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch, Notify},
+    task::JoinSet,
+};
+use tokio_rustls::rustls::Certificate;
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+use tracing::{debug, error, field::display, info, level_filters::LevelFilter, warn, Span};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Time given to clients and components to close their streams when the server stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State shared by every connection.
+struct ServerState {
+    archive: Mutex<Archive>,
+    /// Domains served here, each with its own users
+    hosts: HashMap<String, Host>,
+    /// Bare JIDs allowed to run administration commands for their domain
+    admins: HashSet<String>,
+    /// Bare JIDs allowed to run administration commands for every domain
+    server_admins: HashSet<String>,
+    /// Sessions of the connected users, by stream id. A user may have several, there are no
+    /// resources so each of them gets the stanzas addressed to the user.
+    sessions: Mutex<HashMap<String, LocalSession>>,
+    commands: Mutex<CommandRegistry<ServerState>>,
+    shutdown: Arc<Notify>,
+    /// Set once the server stops, open streams are closed with a `system-shutdown` error
+    stopping: watch::Sender<bool>,
+    federation: Federation,
+    /// Outgoing streams to other servers, by local and remote domain
+    remote: Mutex<HashMap<(String, String), mpsc::UnboundedSender<String>>>,
+    /// Shared secrets of the components allowed to bind a subdomain
+    component_secrets: HashMap<String, String>,
+    /// Outgoing stanzas of each connected component, by domain
+    components: Mutex<HashMap<String, mpsc::UnboundedSender<String>>>,
+    /// Modules enabled globally, hosts may change some of them
+    modules: Modules,
+    limits: Limits,
+    rate_limits: RateLimits,
+    /// Rate of each user, by bare JID, shared by their connections
+    user_rates: Mutex<HashMap<String, RateLimiter>>,
+    /// Number of open client connections by IP address
+    connections: Mutex<HashMap<IpAddr, usize>>,
+    /// Failed authentications by IP address and bare JID tried
+    auth_failures: Mutex<HashMap<(IpAddr, String), AuthFailures>>,
+}
+
+/// A domain served by this server.
+struct Host {
+    accounts: Mutex<Accounts>,
+    /// Where accounts are saved, when they outlive the server
+    accounts_file: Option<PathBuf>,
+    modules: Modules,
+}
+
+impl Host {
+    fn save_accounts(&self) {
+        let Some(path) = &self.accounts_file else {
+            return;
+        };
+        if let Err(e) = self.accounts.lock().unwrap().save(path) {
+            error!("failed to save accounts to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// A bound client session and the channel its outgoing stanzas are queued on.
+#[derive(Clone)]
+struct LocalSession {
+    session: Session,
+    outgoing: mpsc::UnboundedSender<String>,
+    /// Notified when the account is deleted, which ends the session
+    ended: Arc<Notify>,
+}
+
+impl ServerState {
+    /// Queues `stanza` for every session of `jid`, returns false if they are not online.
+    fn deliver(&self, jid: &str, stanza: String) -> bool {
+        let mut delivered = false;
+        for local in self.sessions_of(jid) {
+            delivered |= local.outgoing.send(stanza.clone()).is_ok();
+        }
+        delivered
+    }
+
+    /// Sessions of the user `jid`.
+    fn sessions_of(&self, jid: &str) -> Vec<LocalSession> {
+        let jid = jid_bare(jid);
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .values()
+            .filter(|local| local.session.jid.as_deref() == Some(jid))
+            .cloned()
+            .collect()
+    }
+
+    /// Stores a message sent by `from` when the host of either party keeps an archive, and
+    /// tells the recipient its stanza id (XEP-0359). Stanza ids claiming to come from the
+    /// recipient's archive are removed, only the archive may set them.
+    fn archive(&self, from: &str, message: &mut mini_jabber::Message) {
+        let Some(to) = message.to.as_deref().map(jid_bare) else {
+            return;
+        };
+        message.payloads.retain(|payload| {
+            !payload.is("stanza-id", STANZA_ID_NS) || payload.attr("by") != Some(to)
+        });
+        let archived = [from, to]
+            .into_iter()
+            .filter_map(|jid| self.hosts.get(jid_domain(jid)))
+            .any(|host| host.modules.archive);
+        if !archived {
+            return;
+        }
+        match self.archive.lock().unwrap().apply(from, message) {
+            Ok(Some(id)) => {
+                let stanza_id = Element::new("stanza-id", STANZA_ID_NS)
+                    .with_attr("id", &id)
+                    .with_attr("by", to);
+                message.payloads.push(stanza_id);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to archive message: {}", e),
+        }
+    }
+
+    /// Counts a client connection from `ip`, `None` when it has too many open already.
+    fn open_connection(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionSlot> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        if *count >= self.rate_limits.max_connections_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionSlot {
+            state: self.clone(),
+            ip,
+        })
+    }
+
+    fn is_locked_out(&self, ip: IpAddr, jid: &str) -> bool {
+        self.auth_failures
+            .lock()
+            .unwrap()
+            .get(&(ip, jid.to_string()))
+            .is_some_and(|failures| failures.is_locked())
+    }
+
+    /// Counts a failed authentication as `jid` from `ip`, or forgets the previous ones on
+    /// success. Those of other accounts tried from the same address are kept.
+    fn authenticated(&self, ip: IpAddr, jid: &str, success: bool) {
+        let mut auth_failures = self.auth_failures.lock().unwrap();
+        let key = (ip, jid.to_string());
+        if success {
+            auth_failures.remove(&key);
+            return;
+        }
+        if !auth_failures.contains_key(&key) {
+            auth_failures.retain(|_, failures| !failures.is_expired(self.rate_limits.lockout));
+        }
+        auth_failures
+            .entry(key)
+            .or_default()
+            .fail(self.rate_limits.max_failed_auth, self.rate_limits.lockout);
+    }
+}
+
+/// A client connection counted against the limit of its IP address until it is dropped.
+struct ConnectionSlot {
+    state: Arc<ServerState>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut connections = self.state.connections.lock().unwrap();
+        if let Entry::Occupied(mut count) = connections.entry(self.ip) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+}
+
+/// Sends `stanza` from `from` to `to`, which may be a user of any of our domains, a component
+/// or on another server.
+fn route(state: &Arc<ServerState>, from: &str, to: &str, stanza: String) -> bool {
+    let domain = jid_domain(to);
+    if state.hosts.contains_key(domain) {
+        return state.deliver(to, stanza);
+    }
+    if let Some(component) = state.components.lock().unwrap().get(domain) {
+        return component.send(stanza).is_ok();
+    }
+    if state.component_secrets.contains_key(domain) {
+        // The component is not connected
+        return false;
+    }
+    // Each of our domains has its own streams to other servers
+    let local = jid_domain(from);
+    if !state.modules.federation || !state.hosts.contains_key(local) {
+        return false;
+    }
+
+    let key = (local.to_string(), domain.to_string());
+    let mut remote = state.remote.lock().unwrap();
+    let stanza = match remote.get(&key) {
+        Some(stream) => match stream.send(stanza) {
+            Ok(()) => return true,
+            Err(mpsc::error::SendError(stanza)) => stanza,
+        },
+        None => stanza,
+    };
+
+    // Stanzas wait in the channel while the stream is negotiated
+    let (stream, queued) = mpsc::unbounded_channel();
+    stream.send(stanza).expect("receiver is alive");
+    remote.insert(key, stream);
+    let (local, domain) = (local.to_string(), domain.to_string());
+    tokio::spawn(connect_s2s(local, domain, queued, state.clone()));
+    true
+}
+
+impl ServerAdmin for ServerState {
+    fn is_admin(&self, jid: &str) -> bool {
+        self.admins.contains(jid) || self.is_server_admin(jid)
+    }
+
+    fn is_server_admin(&self, jid: &str) -> bool {
+        self.server_admins.contains(jid)
+    }
+
+    fn accounts(&self, domain: &str) -> Option<&Mutex<Accounts>> {
+        self.hosts.get(domain).map(|host| &host.accounts)
+    }
+
+    fn online_users(&self, domain: &str) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let users: BTreeSet<String> = sessions
+            .values()
+            .filter_map(|local| local.session.jid.clone())
+            .filter(|jid| jid_domain(jid) == domain)
+            .collect();
+        users.into_iter().collect()
+    }
+
+    fn announce(&self, domain: &str, message: mini_jabber::Message) {
+        for jid in self.online_users(domain) {
+            let message = mini_jabber::Message {
+                to: Some(jid.clone()),
+                ..message.clone()
+            };
+            self.deliver(&jid, message.into_string());
+        }
+    }
+
+    fn disconnect(&self, jid: &str) {
+        for local in self.sessions_of(jid) {
+            local.ended.notify_one();
+        }
+    }
+
+    fn shutdown(&self, delay: Duration, announcement: Option<String>) {
+        if let Some(announcement) = announcement {
+            let message = mini_jabber::Message {
+                message_type: Some("headline".to_string()),
+                body: Some(announcement),
+                ..Default::default()
+            };
+            for domain in self.hosts.keys() {
+                self.announce(domain, message.clone());
+            }
+        }
+
+        let shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            shutdown.notify_one();
+        });
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    run_server().await;
-}
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut config = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => {
+            let path = args.get(i + 1).expect("missing config path");
+            ServerConfig::load(Path::new(path)).unwrap_or_else(|e| exit_with(e))
+        }
+        None => ServerConfig::default(),
+    };
+    let (mut tls_cert, mut tls_key, mut tls_ca) = (None, None, None);
+    let mut domains_given = false;
+
+    // --config <toml>, then flags override the file:
+    // --registration open|closed|invite-only, --invite <token> (repeatable),
+    // --admin <jid> (repeatable), --server-admin <jid> (repeatable),
+    // --domain <domain> (repeatable), --port <port>, --tls-port <port>, --s2s-port <port>,
+    // --host <domain>=<host:port> (repeatable), --tls-cert <pem> --tls-key <pem> --tls-ca <pem>,
+    // --dialback-secret <secret>, --component <domain>=<secret> (repeatable),
+    // --component-port <port>
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                args.next();
+            }
+            "--registration" => {
+                let value = args.next().expect("missing registration policy");
+                config.auth.registration = value.parse().expect("invalid registration policy");
+            }
+            "--invite" => config
+                .auth
+                .invites
+                .push(args.next().expect("missing invite token")),
+            "--admin" => config
+                .auth
+                .admins
+                .push(args.next().expect("missing admin jid")),
+            "--server-admin" => config
+                .auth
+                .server_admins
+                .push(args.next().expect("missing admin jid")),
+            "--domain" => {
+                // The first domain replaces those of the configuration
+                if !domains_given {
+                    config.hosts.clear();
+                    domains_given = true;
+                }
+                config
+                    .hosts
+                    .push(HostConfig::new(&args.next().expect("missing domain")));
+            }
+            "--port" => {
+                let value = args.next().expect("missing port");
+                config
+                    .listeners
+                    .c2s
+                    .set_port(value.parse().expect("invalid port"));
+            }
+            "--tls-port" => {
+                let value = args.next().expect("missing TLS port");
+                config
+                    .listeners
+                    .c2s_tls
+                    .set_port(value.parse().expect("invalid TLS port"));
+            }
+            "--s2s-port" => {
+                let value = args.next().expect("missing s2s port");
+                config
+                    .listeners
+                    .s2s
+                    .set_port(value.parse().expect("invalid s2s port"));
+            }
+            "--host" => {
+                let value = args.next().expect("missing host");
+                let (domain, address) = value
+                    .split_once('=')
+                    .expect("expected host as domain=host:port");
+                config
+                    .routes
+                    .insert(domain.to_string(), address.to_string());
+            }
+            "--tls-cert" => tls_cert = Some(args.next().expect("missing certificate")),
+            "--tls-key" => tls_key = Some(args.next().expect("missing key")),
+            "--tls-ca" => tls_ca = Some(args.next().expect("missing authority")),
+            "--dialback-secret" => {
+                config.dialback_secret = Some(args.next().expect("missing dialback secret"));
+            }
+            "--component" => {
+                let value = args.next().expect("missing component");
+                let (domain, secret) = value
+                    .split_once('=')
+                    .expect("expected component as domain=secret");
+                config
+                    .components
+                    .insert(domain.to_string(), secret.to_string());
+            }
+            "--component-port" => {
+                let value = args.next().expect("missing component port");
+                let port = value.parse().expect("invalid component port");
+                config.listeners.component.set_port(port);
+            }
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    match (tls_cert, tls_key, tls_ca) {
+        (Some(cert), Some(key), Some(ca)) => config.tls = Some(TlsFiles { cert, key, ca }),
+        (None, None, None) => {}
+        _ => panic!("--tls-cert, --tls-key and --tls-ca go together"),
+    }
+    if let Err(e) = config.validate() {
+        exit_with(e);
+    }
+    let logs = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from(config.log_level))
+        .with_ansi(std::io::stdout().is_terminal());
+    let targets = config.log_level.targets();
+    match config.log_format {
+        LogFormat::Text => logs.finish().with(targets).init(),
+        LogFormat::Json => logs.json().finish().with(targets).init(),
+    }
 
-async fn run_server() {
-    println!(":: websocket server ::");
-    let address = "127.0.0.1:9292";
+    let mut hosts = HashMap::new();
+    for host_config in &config.hosts {
+        let domain = &host_config.domain;
+        let registration = config.host_registration(host_config);
+        let accounts_file = match config.auth.backend {
+            AuthBackend::Memory => None,
+            AuthBackend::File => {
+                let directory = config.storage.as_ref().expect("validated").join(domain);
+                std::fs::create_dir_all(&directory)
+                    .expect("failed to create the storage directory");
+                Some(directory.join("accounts"))
+            }
+        };
+        let mut accounts = match &accounts_file {
+            Some(path) => Accounts::load(registration, path).unwrap_or_else(|e| {
+                exit_with(format!("failed to load the accounts of {}: {}", domain, e))
+            }),
+            None => Accounts::new(registration),
+        };
+        if registration == RegistrationPolicy::InviteOnly {
+            for invite in config.auth.invites.iter().cloned() {
+                accounts.add_invite(invite);
+            }
+        }
 
-    let tcp_socket = TcpListener::bind(address).await.expect("Failed to bind");
-    println!("listening on {}", address);
+        let host = Host {
+            accounts: Mutex::new(accounts),
+            accounts_file,
+            modules: config.host_modules(host_config),
+        };
+        hosts.insert(domain.clone(), host);
+    }
+
+    let mut commands = CommandRegistry::new();
+    register_admin_commands(&mut commands);
 
-    while let Ok((stream, _)) = tcp_socket.accept().await {
-        tokio::spawn(accept_connection(stream));
+    let tls = config.tls.as_ref().map(|tls| {
+        let mut identity =
+            TlsIdentity::load(&tls.cert, &tls.key, &tls.ca).expect("failed to load TLS files");
+        for host in &config.hosts {
+            if let Some(certificate) = &host.certificate {
+                identity
+                    .add_host(&host.domain, &certificate.cert, &certificate.key)
+                    .expect("failed to load TLS files");
+            }
+        }
+        identity
+    });
+    let mut routes = HostMap::new();
+    for (domain, address) in &config.routes {
+        routes.insert(domain, address);
+    }
+    let mut federation = Federation::new(routes, tls, config.dialback_secret.clone());
+    federation.max_stanza_size = Some(config.limits.max_stanza_size);
+
+    let state = ServerState {
+        archive: Mutex::new(Archive::new(config.limits.max_archived_messages)),
+        hosts,
+        admins: config.auth.admins.iter().cloned().collect(),
+        server_admins: config.auth.server_admins.iter().cloned().collect(),
+        sessions: Mutex::new(HashMap::new()),
+        commands: Mutex::new(commands),
+        shutdown: Arc::new(Notify::new()),
+        stopping: watch::channel(false).0,
+        federation,
+        remote: Mutex::new(HashMap::new()),
+        component_secrets: config.components.clone(),
+        components: Mutex::new(HashMap::new()),
+        modules: config.modules,
+        limits: config.limits,
+        rate_limits: config.rate_limits,
+        user_rates: Mutex::new(HashMap::new()),
+        connections: Mutex::new(HashMap::new()),
+        auth_failures: Mutex::new(HashMap::new()),
+    };
+
+    let state = Arc::new(state);
+    run_server(state.clone(), config.listeners).await;
+    for host in state.hosts.values() {
+        host.save_accounts();
     }
 }
 
-async fn accept_connection(stream: TcpStream) {
-    let addr = stream
-        .peer_addr()
-        .expect("connected streams should have a peer address");
-    println!("peer address: {}", addr);
+/// Reports an invalid configuration and stops.
+fn exit_with(error: impl fmt::Display) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1);
+}
 
-    let ws_stream = tokio_tungstenite::accept_async(stream)
+async fn run_server(state: Arc<ServerState>, listeners: Listeners) {
+    info!(":: websocket server ::");
+    let tcp_socket = TcpListener::bind(listeners.c2s)
         .await
-        .expect("error during the websocket handshake occurred");
+        .expect("Failed to bind");
+    info!("listening on {}", listeners.c2s);
 
-    println!("new websocket connection: {}", addr);
+    let tls_socket = match state.federation.has_tls() {
+        true => {
+            let socket = TcpListener::bind(listeners.c2s_tls)
+                .await
+                .expect("Failed to bind");
+            info!("listening over TLS on {}", listeners.c2s_tls);
+            Some(socket)
+        }
+        false => None,
+    };
 
-    let (mut writer, mut reader) = ws_stream.split();
+    let s2s_socket = match state.modules.federation {
+        true => {
+            let socket = TcpListener::bind(listeners.s2s)
+                .await
+                .expect("Failed to bind");
+            info!("listening for servers on {}", listeners.s2s);
+            Some(socket)
+        }
+        false => None,
+    };
+    let component_socket = match state.modules.components {
+        true => {
+            let socket = TcpListener::bind(listeners.component)
+                .await
+                .expect("Failed to bind");
+            info!("listening for components on {}", listeners.component);
+            Some(socket)
+        }
+        false => None,
+    };
+    for (domain, host) in &state.hosts {
+        let policy = host.accounts.lock().unwrap().policy();
+        info!("serving {}, registration is {:?}", domain, policy);
+    }
+
+    // Client and component connections, which are waited for when stopping. Server streams
+    // are dropped, peers connect again when they have something to send.
+    let mut connections = JoinSet::new();
+    let terminated = termination_signal();
+    tokio::pin!(terminated);
+
+    loop {
+        tokio::select! {
+            accepted = tcp_socket.accept() => {
+                let Ok((stream, addr)) = accepted else { break };
+                connections.spawn(accept_connection(stream, addr, false, state.clone()));
+            }
+            accepted = accept(&tls_socket) => {
+                let Ok((stream, addr)) = accepted else { break };
+                connections.spawn(accept_connection(stream, addr, true, state.clone()));
+            }
+            accepted = accept(&s2s_socket) => {
+                let Ok((stream, addr)) = accepted else { break };
+                tokio::spawn(accept_s2s(stream, addr, state.clone()));
+            }
+            accepted = accept(&component_socket) => {
+                let Ok((stream, addr)) = accepted else { break };
+                connections.spawn(accept_component(stream, addr, state.clone()));
+            }
+            Some(_) = connections.join_next() => {}
+            _ = state.shutdown.notified() => {
+                info!("shutting down");
+                break;
+            }
+            _ = &mut terminated => {
+                info!("terminated, shutting down");
+                break;
+            }
+        }
+    }
 
-    handshake(&mut reader, &mut writer).await.unwrap();
+    drop((tcp_socket, tls_socket, s2s_socket, component_socket));
+    state.stopping.send_replace(true);
+    let drained = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            "closing {} connections that are still open",
+            connections.len()
+        );
+    }
+}
 
-    while let Some(message) = reader.get_next_text().await {
-        println!("< {}", message);
+/// Resolves on SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn termination_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
 
-        writer
-            .send(Message::Text("ack".to_string()))
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination_signal() {
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// Resolves once the server is stopping.
+async fn stopped(stopping: &mut watch::Receiver<bool>) {
+    stopping.wait_for(|stopping| *stopping).await.ok();
+}
+
+/// Accepts on a listener of an optional module, never returning when it is disabled.
+async fn accept(listener: &Option<TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Serves a client connection, over TLS when `tls` is set.
+#[tracing::instrument(name = "c2s", skip_all, fields(peer = %addr, stream_id, jid))]
+async fn accept_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    tls: bool,
+    state: Arc<ServerState>,
+) {
+    let mut session = Session::new(SessionKind::Client, addr);
+    Span::current().record("stream_id", display(&session.stream_id));
+    info!("connected");
+
+    // Counted before the WebSocket handshake, which is work done for anyone connecting
+    let Some(_slot) = state.open_connection(addr.ip()) else {
+        warn!("too many connections from {}", addr.ip());
+        return;
+    };
+
+    let stream: Box<dyn AsyncStream> = match tls {
+        true => match state.federation.accept_tls(stream, &mut session).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("TLS handshake failed: {}", e);
+                return;
+            }
+        },
+        false => Box::new(stream),
+    };
+    let ws_stream =
+        match tokio_tungstenite::accept_async_with_config(stream, Some(state.limits.websocket()))
             .await
-            .expect("failed to send ack");
+        {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                warn!("WebSocket handshake failed: {}", e);
+                return;
+            }
+        };
+
+    let (mut writer, mut reader) = Traced(ws_stream).split();
+    let mut rate = RateLimiter::new(&state.rate_limits);
+    let mut stopping = state.stopping.subscribe();
 
-        println!("> ack");
+    let handshake = tokio::select! {
+        result = handshake(&mut reader, &mut writer, &mut session, &mut rate, &state) => result,
+        _ = stopped(&mut stopping) => Err(StreamError::new("system-shutdown").into()),
+    };
+    let jid = match handshake {
+        Ok(jid) => jid,
+        Err(e) => {
+            warn!("handshake failed: {}", e);
+            if let Some(error) = stream_error(&e) {
+                writer.send(Message::Text(error.into_string())).await.ok();
+            }
+            writer.close().await.ok();
+            return;
+        }
+    };
+    info!("handshake done");
+    let host = &state.hosts[jid_domain(&jid)];
+
+    let (queue, mut outgoing) = mpsc::unbounded_channel();
+    let ended = Arc::new(Notify::new());
+    let local = LocalSession {
+        session: session.clone(),
+        outgoing: queue,
+        ended: ended.clone(),
+    };
+    let stream_id = session.stream_id.clone();
+    state
+        .sessions
+        .lock()
+        .unwrap()
+        .insert(stream_id.clone(), local);
+
+    loop {
+        let message = tokio::select! {
+            incoming = read_stanza(&mut reader, &state.limits) => {
+                let incoming = match incoming {
+                    Ok(Some(message)) => throttle(&state, &mut rate, Some(&jid), &message)
+                        .await
+                        .map(|_| Some(message)),
+                    incoming => incoming,
+                };
+                match incoming {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(error) => {
+                        warn!("{}", error);
+                        writer.send(Message::Text(error.into_string())).await.ok();
+                        writer.close().await.ok();
+                        break;
+                    }
+                }
+            },
+            Some(stanza) = outgoing.recv() => {
+                if writer.send(Message::Text(stanza)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ = stopped(&mut stopping) => {
+                let error = StreamError::new("system-shutdown");
+                writer.send(Message::Text(error.into_string())).await.ok();
+                writer.close().await.ok();
+                break;
+            }
+            _ = ended.notified() => {
+                info!("account deleted");
+                let mut error = StreamError::new("not-authorized");
+                error.text = Some("the account was deleted".to_string());
+                writer.send(Message::Text(error.into_string())).await.ok();
+                writer.close().await.ok();
+                break;
+            }
+        };
+
+        if let Ok(mut iq) = Iq::from_string(&message) {
+            // Requests for components and other servers are theirs to answer
+            if let Some(to) = iq
+                .to
+                .clone()
+                .filter(|to| !state.hosts.contains_key(jid_domain(to)))
+            {
+                iq.from = Some(jid.clone());
+                if !route(&state, &jid, &to, iq.into_string()) && iq.iq_type.is_request() {
+                    let error = iq.error(StanzaError::new("cancel", "service-unavailable"));
+                    if writer
+                        .send(Message::Text(error.into_string()))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            if let (Some(IqPayload::MamQuery(query)), IqType::Set, true) =
+                (&iq.payload, iq.iq_type, host.modules.archive)
+            {
+                let stanzas = match query_archive(&state, &jid, query) {
+                    Ok((results, fin)) => {
+                        let response = iq.result(Some(IqPayload::MamFin(fin)));
+                        let mut stanzas: Vec<String> =
+                            results.iter().map(|result| result.into_string()).collect();
+                        stanzas.push(response.into_string());
+                        stanzas
+                    }
+                    Err(error) => vec![iq.error(error).into_string()],
+                };
+                if send_all(&mut writer, stanzas).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            let Some((response, removed)) = handle_iq(&iq, Some(&jid), host, &state).await else {
+                continue;
+            };
+            if writer
+                .send(Message::Text(response.into_string()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+
+            if removed {
+                // The account is gone, so is its session
+                writer.close().await.ok();
+                break;
+            }
+            continue;
+        }
+
+        if let Ok(mut message) = mini_jabber::Message::from_string(&message) {
+            message.from = Some(jid.clone());
+            state.archive(&jid, &mut message);
+            if let Some(to) = message.to.clone() {
+                if !route(&state, &jid, &to, message.into_string()) {
+                    info!("{} is not online", to);
+                }
+            }
+            continue;
+        }
+
+        // Directed presence only, there is no roster to broadcast to
+        if let Ok(mut presence) = Presence::from_string(&message) {
+            presence.from = Some(jid.clone());
+            if let Some(to) = presence.to.clone() {
+                route(&state, &jid, &to, presence.into_string());
+            }
+            continue;
+        }
+
+        if writer.send(Message::Text("ack".to_string())).await.is_err() {
+            break;
+        }
+    }
+
+    state.sessions.lock().unwrap().remove(&stream_id);
+    info!("disconnected");
+}
+
+type Reader = SplitStream<Traced<WebSocketStream<Box<dyn AsyncStream>>>>;
+type Writer = SplitSink<Traced<WebSocketStream<Box<dyn AsyncStream>>>, Message>;
+
+/// Sends `stanzas` in order, flushing once at the end.
+async fn send_all(writer: &mut Writer, stanzas: Vec<String>) -> Result<(), tungstenite::Error> {
+    for stanza in stanzas {
+        writer.feed(Message::Text(stanza)).await?;
+    }
+    writer.flush().await
+}
+
+/// Reads the next stanza of a client, `None` when the stream is closed. Stanzas over the
+/// limits of the server or outside of restricted XML are refused with a stream error.
+async fn read_stanza<R>(reader: &mut R, limits: &Limits) -> Result<Option<String>, StreamError>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    match reader.next().await {
+        // The WebSocket refuses frames over the size limit before we see them
+        Some(Err(tungstenite::Error::Capacity(_))) => Err(limits.stanza_too_large()),
+        Some(Ok(message)) if !message.is_close() => match message.into_text() {
+            Ok(text) => limits.check(&text).map(|_| Some(text)),
+            Err(_) => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Reads the next stanza of the handshake, which can't end before it is done.
+async fn next_stanza<R>(
+    reader: &mut R,
+    rate: &mut RateLimiter,
+    state: &ServerState,
+) -> eyre::Result<String>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let stanza = read_stanza(reader, &state.limits)
+        .await?
+        .ok_or(eyre::eyre!("connection closed"))?;
+    throttle(state, rate, None, &stanza).await?;
+    Ok(stanza)
+}
+
+/// Stream error to send before closing a stream that failed with `e`: malformed or misplaced
+/// elements, broken limits.
+fn stream_error(e: &eyre::Report) -> Option<&StreamError> {
+    match e.downcast_ref::<Error>() {
+        Some(Error::Stream(error)) => Some(error),
+        _ => e.downcast_ref::<StreamError>(),
+    }
+}
+
+fn policy_violation(text: &str) -> StreamError {
+    StreamError {
+        condition: "policy-violation".to_string(),
+        text: Some(text.to_string()),
     }
 }
 
-type Reader = SplitStream<WebSocketStream<TcpStream>>;
-type Writer = SplitSink<WebSocketStream<TcpStream>, Message>;
+/// Holds `stanza` back while the connection, or the user `jid`, sends faster than their rate,
+/// or refuses it when the server disconnects such clients.
+async fn throttle(
+    state: &ServerState,
+    rate: &mut RateLimiter,
+    jid: Option<&str>,
+    stanza: &str,
+) -> Result<(), StreamError> {
+    let mut wait = rate.take(stanza.len());
+    if let Some(jid) = jid {
+        let mut user_rates = state.user_rates.lock().unwrap();
+        let bare = jid_bare(jid);
+        if !user_rates.contains_key(bare) {
+            // A full bucket is the same as a new one, forget those before adding another
+            user_rates.retain(|_, rate| !rate.is_full());
+        }
+        let user_rate = user_rates
+            .entry(bare.to_string())
+            .or_insert_with(|| RateLimiter::new(&state.rate_limits));
+        wait = wait.max(user_rate.take(stanza.len()));
+    }
+
+    let Some(wait) = wait else {
+        return Ok(());
+    };
+    match state.rate_limits.exceeded {
+        RateExceeded::Queue => {
+            tokio::time::sleep(wait).await;
+            Ok(())
+        }
+        RateExceeded::Disconnect => Err(policy_violation("rate limit exceeded")),
+    }
+}
 
-async fn handshake(reader: &mut Reader, writer: &mut Writer) -> color_eyre::Result<()> {
+/// Negotiates the stream and returns the authenticated JID.
+async fn handshake(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    session: &mut Session,
+    rate: &mut RateLimiter,
+    state: &ServerState,
+) -> eyre::Result<String> {
     // Read initial header
-    let initial_header = reader.get_next_text().await.expect("failed to get header");
-    let initial_header =
-        StreamHeader::from_string(&initial_header).expect("failed to parse header");
+    let initial_header = next_stanza(reader, rate, state).await?;
+    let initial_header = StreamHeader::from_string(&initial_header)?;
+    let domain = initial_header.to.clone();
+    let xmlns = initial_header.xmlns.clone();
 
     // Append id to header
-    let id = "++123456789++".to_string();
-    let mut response_header = initial_header.into_response(id);
-    response_header.xmlns = "jabber:server".to_string();
-    let response_header = response_header.into_string();
+    let id = session.stream_id.clone();
+    let response_header = initial_header.into_response(id).into_string();
 
     // Send response header
-    writer
-        .send(Message::Text(response_header))
-        .await
-        .expect("failed to send hello message");
+    writer.send(Message::Text(response_header)).await?;
 
-    // Send features header
+    if xmlns != CLIENT_NS {
+        return Err(invalid_namespace("stream:stream", &xmlns, CLIENT_NS).into());
+    }
+    let Some(host) = state.hosts.get(&domain) else {
+        let error = StreamError::new("host-unknown");
+        writer.send(Message::Text(error.into_string())).await.ok();
+        writer.close().await.ok();
+        eyre::bail!("host-unknown: {}", domain);
+    };
+
+    // Passwords only go over encrypted connections or ones that never leave the host. There
+    // is no STARTTLS over WebSocket (RFC 7395), encryption comes from the transport.
+    let confidential = session.features.tls || session.peer.ip().is_loopback();
+    let mechanisms = match confidential {
+        true => vec![Mechanism("PLAIN".into())],
+        false => Vec::new(),
+    };
     let features = StreamFeatures {
         mechanisms: Some(Mechanisms {
             xmlns: "urn:ietf:params:xml:ns:xmpp-sasl".to_string(),
-            mechanisms: vec![Mechanism("PLAIN".into())],
-        }),
-        start_tls: Some(StartTls {
-            xmlns: "urn:ietf:params:xml:ns:xmpp-tls".to_string(),
-            required: true,
+            mechanisms,
         }),
+        start_tls: None,
+        dialback: false,
     };
-    let features = features.into_string();
+    writer.send(Message::Text(features.into_string())).await?;
+
+    // Clients may register an account before authenticating
+    let username = loop {
+        let request = next_stanza(reader, rate, state).await?;
+
+        if let Ok(iq) = Iq::from_string(&request) {
+            let response = match (&iq.payload, iq.iq_type) {
+                (Some(IqPayload::Register(_)), IqType::Set) if !confidential => {
+                    let mut error = StanzaError::new("cancel", "policy-violation");
+                    error.text = Some("registration needs an encrypted connection".to_string());
+                    iq.error(error)
+                }
+                _ => match handle_iq(&iq, None, host, state).await {
+                    Some((response, _)) => response,
+                    None => continue,
+                },
+            };
+            writer.send(Message::Text(response.into_string())).await?;
+            continue;
+        }
+
+        let auth = SaslAuth::from_string(&request)?;
+        let credentials = match (auth.mechanism.as_str(), sasl_plain_decode(&auth.data)) {
+            ("PLAIN", Ok(credentials)) => Some(credentials),
+            _ => None,
+        };
+        let ip = session.peer.ip();
+        let tried = format!(
+            "{}@{}",
+            credentials.as_ref().map_or("", |(username, _)| username),
+            domain
+        );
+        if state.is_locked_out(ip, &tried) {
+            return Err(policy_violation("too many failed authentications").into());
+        }
+        if !confidential {
+            let failure = SaslFailure {
+                condition: "encryption-required".to_string(),
+            };
+            writer.send(Message::Text(failure.into_string())).await?;
+            continue;
+        }
+        let username = match credentials {
+            Some((username, password)) => {
+                let hash = host.accounts.lock().unwrap().password(&username);
+                // Argon2 is slow on purpose, keep it off the executor
+                let verified =
+                    tokio::task::spawn_blocking(move || verify_password(hash.as_ref(), &password))
+                        .await?;
+                verified.then_some(username)
+            }
+            _ => None,
+        };
+
+        state.authenticated(ip, &tried, username.is_some());
+        match username {
+            Some(username) => {
+                writer
+                    .send(Message::Text(SaslSuccess().into_string()))
+                    .await?;
+                break username;
+            }
+            None => {
+                let failure = SaslFailure {
+                    condition: "not-authorized".to_string(),
+                };
+                writer.send(Message::Text(failure.into_string())).await?;
+            }
+        }
+    };
+
+    // Restart the stream after authentication
+    let initial_header = next_stanza(reader, rate, state).await?;
+    let initial_header = StreamHeader::from_string(&initial_header)?;
+    if initial_header.to != domain {
+        eyre::bail!(
+            "stream restarted for {} instead of {}",
+            initial_header.to,
+            domain
+        );
+    }
+    let id = session.restart();
+    debug!(stream_id = %id, "stream restarted");
+    let response_header = initial_header.into_response(id);
     writer
-        .send(Message::Text(features))
-        .await
-        .expect("failed to send features");
+        .send(Message::Text(response_header.into_string()))
+        .await?;
+
+    let features = StreamFeatures {
+        mechanisms: None,
+        start_tls: None,
+        dialback: false,
+    };
+    writer.send(Message::Text(features.into_string())).await?;
 
-    // Get features back and send proceed message
-    let tls_response = reader
+    session.authenticate("PLAIN", &username);
+    let jid = format!("{}@{}", username, domain);
+    session.bind(&jid);
+    Span::current().record("jid", display(&jid));
+    Ok(jid)
+}
+
+type S2sReader = SplitStream<Traced<S2sStream>>;
+type S2sWriter = SplitSink<Traced<S2sStream>, Message>;
+
+/// Sends the header of our domain `from` to `domain` and reads back the stream id and
+/// features.
+async fn start_s2s_stream(
+    reader: &mut S2sReader,
+    writer: &mut S2sWriter,
+    from: &str,
+    domain: &str,
+) -> eyre::Result<(String, StreamFeatures)> {
+    let header = StreamHeader {
+        from: Some(from.to_string()),
+        to: domain.to_string(),
+        version: "1.0".to_string(),
+        xml_lang: "en".to_string(),
+        xmlns: SERVER_NS.to_string(),
+        xmlns_stream: "http://etherx.jabber.org/streams".to_string(),
+    };
+    writer.send(Message::Text(header.into_string())).await?;
+
+    let response = reader
+        .get_next_text()
+        .await
+        .ok_or(eyre::eyre!("connection closed"))?;
+    let response = StreamHeaderResponse::from_string(&response)?;
+    let features = reader
         .get_next_text()
         .await
-        .expect("failed to get tls response");
-    StartTls::from_string(&tls_response).expect("failed to parse tls repsonse");
+        .ok_or(eyre::eyre!("connection closed"))?;
+    Ok((response.id, StreamFeatures::from_string(&features)?))
+}
 
-    let tls_proceed = StartTlsProceed().into_string();
-    writer
-        .send(Message::Text(tls_proceed))
+/// Opens an authenticated stream from our domain `local` to `domain`, with SASL EXTERNAL when
+/// the other server accepts our certificate and dialback otherwise.
+async fn authenticate_s2s(
+    local: &str,
+    domain: &str,
+    state: &ServerState,
+) -> eyre::Result<(S2sWriter, S2sReader)> {
+    let (mut writer, mut reader) = Traced(state.federation.connect(local, domain).await?).split();
+    let (id, features) = start_s2s_stream(&mut reader, &mut writer, local, domain).await?;
+
+    let external = features
+        .mechanisms
+        .as_ref()
+        .is_some_and(|mechanisms| mechanisms.mechanisms.iter().any(|m| m.0 == "EXTERNAL"));
+    if external && state.federation.has_tls() {
+        let auth = SaslAuth {
+            xmlns: "urn:ietf:params:xml:ns:xmpp-sasl".to_string(),
+            mechanism: "EXTERNAL".to_string(),
+            data: sasl_external_encode(Some(local)),
+        };
+        writer.send(Message::Text(auth.into_string())).await?;
+
+        let response = reader
+            .get_next_text()
+            .await
+            .ok_or(eyre::eyre!("connection closed"))?;
+        match SaslResponse::from_string(&response)? {
+            SaslResponse::Success(_) => {
+                start_s2s_stream(&mut reader, &mut writer, local, domain).await?;
+                return Ok((writer, reader));
+            }
+            SaslResponse::Failure(failure) if !features.dialback => {
+                eyre::bail!("SASL EXTERNAL failed: {}", failure.condition)
+            }
+            SaslResponse::Failure(_) => {}
+        }
+    }
+
+    if !features.dialback {
+        eyre::bail!("{} offers no way to authenticate", domain)
+    }
+    let request = DialbackResult {
+        from: local.to_string(),
+        to: domain.to_string(),
+        key: Some(state.federation.dialback_key(local, domain, &id)),
+        result_type: None,
+    };
+    writer.send(Message::Text(request.into_string())).await?;
+
+    let response = reader
+        .get_next_text()
         .await
-        .expect("failed to send proceed message");
-
-    // Start connection again
-    let initial_header = reader.get_next_text().await.expect("failed to get header");
-    let initial_header =  StreamHeader::from_string(&initial_header).expect("failed to parse header");
-    let id = "++98765321++".to_string();
-    let mut response_header = initial_header.into_response(id);
-    response_header.xmlns = "jabber:server".to_string();
-    let response_header = response_header.into_string();
-    writer
-        .send(Message::Text(response_header))
+        .ok_or(eyre::eyre!("connection closed"))?;
+    match DialbackResult::from_string(&response)?.result_type {
+        Some(DialbackType::Valid) => Ok((writer, reader)),
+        _ => eyre::bail!("{} refused our dialback key", domain),
+    }
+}
+
+/// Sends the stanzas queued from `local` for `domain` until either side closes the stream.
+#[tracing::instrument(name = "s2s_out", skip_all, fields(from = %local, to = %domain))]
+async fn connect_s2s(
+    local: String,
+    domain: String,
+    mut queued: mpsc::UnboundedReceiver<String>,
+    state: Arc<ServerState>,
+) {
+    match authenticate_s2s(&local, &domain, &state).await {
+        Ok((mut writer, mut reader)) => {
+            info!("s2s stream from {} to {} is ready", local, domain);
+            loop {
+                tokio::select! {
+                    stanza = queued.recv() => {
+                        let Some(stanza) = stanza else { break };
+                        if writer.send(Message::Text(stanza)).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Stanzas only flow our way, the other server may only close
+                    incoming = reader.get_next_text() => {
+                        if incoming.is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+            writer.close().await.ok();
+        }
+        Err(e) => warn!("s2s stream from {} to {} failed: {}", local, domain, e),
+    }
+
+    // Stanzas still queued are dropped, the next one opens a new stream
+    state.remote.lock().unwrap().remove(&(local, domain));
+}
+
+/// Asks the authoritative server of `originating` whether it generated `key` for the
+/// stream `id` it opened to our domain `receiving`.
+async fn verify_dialback(
+    receiving: &str,
+    originating: &str,
+    id: &str,
+    key: &str,
+    state: &ServerState,
+) -> eyre::Result<bool> {
+    let stream = state.federation.connect(receiving, originating).await?;
+    let (mut writer, mut reader) = Traced(stream).split();
+    start_s2s_stream(&mut reader, &mut writer, receiving, originating).await?;
+
+    let request = DialbackVerify {
+        from: receiving.to_string(),
+        to: originating.to_string(),
+        id: id.to_string(),
+        key: Some(key.to_string()),
+        verify_type: None,
+    };
+    writer.send(Message::Text(request.into_string())).await?;
+
+    let response = reader
+        .get_next_text()
         .await
-        .expect("failed to send hello message");
+        .ok_or(eyre::eyre!("connection closed"))?;
+    let response = DialbackVerify::from_string(&response)?;
+    writer.close().await.ok();
+    Ok(response.id == id && response.verify_type == Some(DialbackType::Valid))
+}
+
+#[tracing::instrument(name = "s2s", skip_all, fields(peer = %addr, stream_id, jid))]
+async fn accept_s2s(stream: TcpStream, addr: SocketAddr, state: Arc<ServerState>) {
+    let mut session = Session::new(SessionKind::Server, addr);
+    Span::current().record("stream_id", display(&session.stream_id));
+    info!("connected");
+
+    let (stream, certificate) = match state.federation.accept(stream, &mut session).await {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            warn!("connection failed: {}", e);
+            return;
+        }
+    };
+
+    let (mut writer, mut reader) = Traced(stream).split();
+    let served = serve_s2s(&mut reader, &mut writer, certificate, &mut session, &state).await;
+    if let Err(e) = served {
+        warn!("stream failed: {}", e);
+        if let Some(error) = stream_error(&e) {
+            writer.send(Message::Text(error.into_string())).await.ok();
+        }
+    }
+    writer.close().await.ok();
+    info!("disconnected");
+}
+
+/// Handles a stream opened by another server: authentication, dialback requests and the
+/// stanzas it routes to our users.
+async fn serve_s2s(
+    reader: &mut S2sReader,
+    writer: &mut S2sWriter,
+    certificate: Option<Certificate>,
+    session: &mut Session,
+    state: &ServerState,
+) -> eyre::Result<()> {
+    // Other servers are held to the limits and rates of clients
+    let mut rate = RateLimiter::new(&state.rate_limits);
+    let header = next_stanza(reader, &mut rate, state).await?;
+    let header = StreamHeader::from_string(&header)?;
+    if header.xmlns != SERVER_NS {
+        return Err(invalid_namespace("stream:stream", &header.xmlns, SERVER_NS).into());
+    }
+    // The stream is for one of our domains, chosen by the other server
+    let domain = &header.to.clone();
+    if !state.hosts.contains_key(domain) {
+        let error = StreamError::new("host-unknown");
+        writer.send(Message::Text(error.into_string())).await?;
+        eyre::bail!("host-unknown: {}", header.to)
+    }
+    let originating = header.from.clone().ok_or(eyre::eyre!("from"))?;
+
+    let response = StreamHeaderResponse {
+        id: session.stream_id.clone(),
+        from: domain.clone(),
+        to: originating.clone(),
+        ..header.into_response(String::new())
+    };
+    writer.send(Message::Text(response.into_string())).await?;
+
+    // SASL EXTERNAL only makes sense if the certificate is the one of the peer
+    let external = certificate
+        .as_ref()
+        .is_some_and(|certificate| certificate_matches(certificate, &originating));
+    let features = StreamFeatures {
+        start_tls: None,
+        mechanisms: external.then(|| Mechanisms {
+            xmlns: "urn:ietf:params:xml:ns:xmpp-sasl".to_string(),
+            mechanisms: vec![Mechanism("EXTERNAL".into())],
+        }),
+        dialback: true,
+    };
+    writer.send(Message::Text(features.into_string())).await?;
+
+    // Domains the peer proved it may send stanzas for
+    let mut authorized = HashSet::new();
+
+    while let Some(request) = read_stanza(reader, &state.limits).await? {
+        throttle(state, &mut rate, None, &request).await?;
+        if let Ok(auth) = SaslAuth::from_string(&request) {
+            let authzid = sasl_external_decode(&auth.data).unwrap_or(None);
+            let accepted = auth.mechanism == "EXTERNAL"
+                && external
+                && authzid
+                    .as_ref()
+                    .is_none_or(|authzid| *authzid == originating);
+            if !accepted {
+                let failure = SaslFailure {
+                    condition: "not-authorized".to_string(),
+                };
+                writer.send(Message::Text(failure.into_string())).await?;
+                continue;
+            }
+            writer
+                .send(Message::Text(SaslSuccess().into_string()))
+                .await?;
+
+            // Restart the stream after authentication
+            let header = next_stanza(reader, &mut rate, state).await?;
+            let header = StreamHeader::from_string(&header)?;
+            let response = StreamHeaderResponse {
+                id: session.restart(),
+                from: domain.clone(),
+                to: originating.clone(),
+                ..header.into_response(String::new())
+            };
+            writer.send(Message::Text(response.into_string())).await?;
+            let features = StreamFeatures {
+                start_tls: None,
+                mechanisms: None,
+                dialback: false,
+            };
+            writer.send(Message::Text(features.into_string())).await?;
+
+            debug!(stream_id = %session.stream_id, "stream restarted");
+            session.authenticate("EXTERNAL", &originating);
+            session.bind(&originating);
+            Span::current().record("jid", display(&originating));
+            authorized.insert(originating.clone());
+            continue;
+        }
+
+        if let Ok(request) = DialbackResult::from_string(&request) {
+            let valid = match &request.key {
+                Some(key) if &request.to == domain => {
+                    verify_dialback(domain, &request.from, &session.stream_id, key, state)
+                        .await
+                        .unwrap_or_else(|e| {
+                            warn!("dialback verification of {} failed: {}", request.from, e);
+                            false
+                        })
+                }
+                _ => false,
+            };
+            if valid {
+                session.authenticate("dialback", &request.from);
+                session.bind(&request.from);
+                Span::current().record("jid", display(&request.from));
+                authorized.insert(request.from.clone());
+            }
+
+            let response = DialbackResult {
+                from: request.to,
+                to: request.from,
+                key: None,
+                result_type: Some(if valid {
+                    DialbackType::Valid
+                } else {
+                    DialbackType::Invalid
+                }),
+            };
+            writer.send(Message::Text(response.into_string())).await?;
+            continue;
+        }
+
+        // We are the authoritative server, asked about a key we supposedly sent
+        if let Ok(request) = DialbackVerify::from_string(&request) {
+            let valid = &request.to == domain
+                && request.key.as_deref().is_some_and(|key| {
+                    state
+                        .federation
+                        .verify_dialback_key(domain, &request.from, &request.id, key)
+                });
+            let response = DialbackVerify {
+                from: request.to,
+                to: request.from,
+                id: request.id,
+                key: None,
+                verify_type: Some(if valid {
+                    DialbackType::Valid
+                } else {
+                    DialbackType::Invalid
+                }),
+            };
+            writer.send(Message::Text(response.into_string())).await?;
+            continue;
+        }
+
+        if let Ok(mut message) = mini_jabber::Message::from_string(&request) {
+            let (Some(from), Some(to)) = (message.from.clone(), message.to.clone()) else {
+                continue;
+            };
+            if !authorized.contains(jid_domain(&from)) || jid_domain(&to) != domain {
+                warn!("dropping stanza from {} to {}", from, to);
+                continue;
+            }
+
+            state.archive(&from, &mut message);
+            if !state.deliver(&to, message.into_string()) {
+                info!("{} is not online", to);
+            }
+            continue;
+        }
+
+        if let Ok(presence) = Presence::from_string(&request) {
+            let (Some(from), Some(to)) = (presence.from.clone(), presence.to.clone()) else {
+                continue;
+            };
+            if !authorized.contains(jid_domain(&from)) || jid_domain(&to) != domain {
+                warn!("dropping stanza from {} to {}", from, to);
+                continue;
+            }
+            state.deliver(&to, presence.into_string());
+            continue;
+        }
+
+        warn!("ignoring s2s stanza");
+    }
 
-    println!("handshake done");
     Ok(())
 }
+
+#[tracing::instrument(name = "component", skip_all, fields(peer = %addr, stream_id, jid))]
+async fn accept_component(stream: TcpStream, addr: SocketAddr, state: Arc<ServerState>) {
+    let mut session = Session::new(SessionKind::Component, addr);
+    Span::current().record("stream_id", display(&session.stream_id));
+    info!("connected");
+
+    let websocket = Some(state.limits.websocket());
+    let stream: Box<dyn AsyncStream> = Box::new(stream);
+    let ws_stream = match tokio_tungstenite::accept_async_with_config(stream, websocket).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            warn!("connection failed: {}", e);
+            return;
+        }
+    };
+    let (mut writer, mut reader) = Traced(ws_stream).split();
+
+    let mut rate = RateLimiter::new(&state.rate_limits);
+    let (domain, queue, mut outgoing) = match component_handshake(
+        &mut reader,
+        &mut writer,
+        &mut session,
+        &mut rate,
+        &state,
+    )
+    .await
+    {
+        Ok(bound) => bound,
+        Err(e) => {
+            warn!("handshake failed: {}", e);
+            if let Some(error) = stream_error(&e) {
+                writer.send(Message::Text(error.into_string())).await.ok();
+            }
+            writer.close().await.ok();
+            return;
+        }
+    };
+    info!("bound");
+
+    let mut stopping = state.stopping.subscribe();
+    loop {
+        let text = tokio::select! {
+            incoming = read_stanza(&mut reader, &state.limits) => {
+                let incoming = match incoming {
+                    Ok(Some(text)) => throttle(&state, &mut rate, None, &text)
+                        .await
+                        .map(|_| Some(text)),
+                    incoming => incoming,
+                };
+                match incoming {
+                    Ok(Some(text)) => text,
+                    Ok(None) => break,
+                    Err(error) => {
+                        warn!("{}", error);
+                        writer.send(Message::Text(error.into_string())).await.ok();
+                        writer.close().await.ok();
+                        break;
+                    }
+                }
+            },
+            Some(stanza) = outgoing.recv() => {
+                if writer.send(Message::Text(stanza)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ = stopped(&mut stopping) => {
+                let error = StreamError::new("system-shutdown");
+                writer.send(Message::Text(error.into_string())).await.ok();
+                writer.close().await.ok();
+                break;
+            }
+        };
+
+        let Ok(mut stanza) = Stanza::from_string(&text) else {
+            warn!(stanza = %Redacted(&text), "ignoring component stanza");
+            continue;
+        };
+        let (Some(from), Some(to)) = (stanza.from(), stanza.to()) else {
+            continue;
+        };
+        let (from, to) = (from.to_string(), to.to_string());
+        // Components may only speak for their own domain
+        if jid_domain(&from) != domain {
+            warn!("dropping stanza from {} sent by {}", from, domain);
+            continue;
+        }
+
+        if let Stanza::Message(message) = &mut stanza {
+            state.archive(&from, message);
+        }
+        if !route(&state, &from, &to, stanza.into_string()) {
+            info!("{} is not reachable", to);
+        }
+    }
+
+    let mut components = state.components.lock().unwrap();
+    if components
+        .get(&domain)
+        .is_some_and(|current| current.same_channel(&queue))
+    {
+        components.remove(&domain);
+    }
+    info!("disconnected");
+}
+
+/// Checks the handshake of a component and binds its domain, answering failures with a
+/// stream error.
+async fn component_handshake(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    session: &mut Session,
+    rate: &mut RateLimiter,
+    state: &ServerState,
+) -> eyre::Result<(
+    String,
+    mpsc::UnboundedSender<String>,
+    mpsc::UnboundedReceiver<String>,
+)> {
+    let header = next_stanza(reader, rate, state).await?;
+    let header = StreamHeader::from_string(&header)?;
+    let domain = header.to.clone();
+    // Components are subdomains of one of our hosts
+    let parent = state
+        .hosts
+        .keys()
+        .find(|host| domain.ends_with(&format!(".{}", host)))
+        .cloned()
+        .unwrap_or_default();
+
+    let response = StreamHeaderResponse {
+        id: session.stream_id.clone(),
+        from: domain.clone(),
+        to: parent,
+        ..header.into_response(String::new())
+    };
+    writer.send(Message::Text(response.into_string())).await?;
+
+    let error = if response.xmlns != COMPONENT_NS {
+        Some(invalid_namespace(
+            "stream:stream",
+            &response.xmlns,
+            COMPONENT_NS,
+        ))
+    } else if !state.component_secrets.contains_key(&domain) {
+        Some(StreamError::new("host-unknown"))
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        return Err(error.into());
+    }
+
+    let handshake = next_stanza(reader, rate, state).await?;
+    let digest = ComponentHandshake::from_string(&handshake)?.digest;
+    let secret = &state.component_secrets[&domain];
+    let valid = digest
+        .as_deref()
+        .is_some_and(|digest| verify_component_digest(&session.stream_id, secret, digest));
+    if !valid {
+        return Err(StreamError::new("not-authorized").into());
+    }
+
+    let (queue, outgoing) = mpsc::unbounded_channel();
+    let bound = match state.components.lock().unwrap().entry(domain.clone()) {
+        Entry::Occupied(_) => false,
+        Entry::Vacant(entry) => {
+            entry.insert(queue.clone());
+            true
+        }
+    };
+    if !bound {
+        return Err(StreamError::new("conflict").into());
+    }
+
+    writer
+        .send(Message::Text(
+            ComponentHandshake { digest: None }.into_string(),
+        ))
+        .await?;
+    session.authenticate("handshake", &domain);
+    session.bind(&domain);
+    Span::current().record("jid", display(&domain));
+    Ok((domain, queue, outgoing))
+}
+
+/// Messages of `jid` matching an archive query (XEP-0313) as they are sent back, and the
+/// `<fin/>` that ends them. Malformed queries are a `bad-request`, an unknown `before` or
+/// `after` id is `item-not-found`.
+fn query_archive(
+    state: &ServerState,
+    jid: &str,
+    query: &MamQuery,
+) -> Result<(Vec<mini_jabber::Message>, MamFin), StanzaError> {
+    let bad_request = |text: &str| {
+        let mut error = StanzaError::new("modify", "bad-request");
+        error.text = Some(text.to_string());
+        error
+    };
+    if let Some(form) = &query.form {
+        if form.form_type_value() != Some(MAM_NS) {
+            return Err(bad_request("the form is not an archive query"));
+        }
+        let known = |var: Option<&str>| matches!(var, Some(FORM_TYPE | "with"));
+        if let Some(field) = form
+            .fields
+            .iter()
+            .find(|field| !known(field.var.as_deref()))
+        {
+            let var = field.var.as_deref().unwrap_or_default();
+            return Err(bad_request(&format!("unsupported field {}", var)));
+        }
+    }
+    let set = query.set.clone().unwrap_or_default();
+    if set.after.as_deref() == Some("") {
+        return Err(bad_request("after needs the id of a message"));
+    }
+
+    let limit = state.limits.max_archive_page;
+    let max = set.max.unwrap_or(limit).min(limit);
+    let archive = state.archive.lock().unwrap();
+    let (page, complete) = archive
+        .page(
+            jid,
+            query.with(),
+            set.after.as_deref(),
+            set.before.as_deref(),
+            max,
+        )
+        .map_err(|e| match e {
+            mini_jabber::Error::Stanza(error) => error,
+            e => bad_request(&e.to_string()),
+        })?;
+
+    let results = page
+        .iter()
+        .map(|archived| {
+            // Retracted messages are tombstones, the retraction without the body
+            let payloads = match archived.edited_at.filter(|_| archived.retracted) {
+                Some(stamp) => vec![Element::new("retracted", MESSAGE_RETRACT_NS)
+                    .with_attr("stamp", &datetime(stamp))],
+                None => Vec::new(),
+            };
+            mini_jabber::Message {
+                to: Some(jid.to_string()),
+                archived: Some(MamResult {
+                    query_id: query.query_id.clone(),
+                    id: archived.id.clone(),
+                    stamp: Some(datetime(archived.timestamp)),
+                    message: Box::new(mini_jabber::Message {
+                        id: archived.origin_id.clone(),
+                        from: Some(archived.from.clone()),
+                        to: Some(archived.to.clone()),
+                        message_type: Some("chat".to_string()),
+                        body: archived.body.clone(),
+                        payloads,
+                        ..Default::default()
+                    }),
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let fin = MamFin {
+        complete,
+        set: ResultSet {
+            first: page.first().map(|archived| archived.id.clone()),
+            last: page.last().map(|archived| archived.id.clone()),
+            ..Default::default()
+        },
+    };
+    Ok((results, fin))
+}
+
+/// Answers an IQ request, `None` for results and errors. `jid` is set once the stream is
+/// authenticated. The returned flag tells whether the user removed their account.
+async fn handle_iq(
+    iq: &Iq,
+    jid: Option<&str>,
+    host: &Host,
+    state: &ServerState,
+) -> Option<(Iq, bool)> {
+    // Responses are never answered, not even with an error, or two entities could bounce
+    // errors forever (RFC 6120 section 8.2.3)
+    if !iq.iq_type.is_request() {
+        return None;
+    }
+    let accounts = &host.accounts;
+    let username = jid.map(|jid| jid.split_once('@').map(|(local, _)| local).unwrap_or(jid));
+
+    let response = match (&iq.payload, iq.iq_type) {
+        (Some(IqPayload::Command(command)), IqType::Set) if host.modules.commands => {
+            let Some(jid) = jid else {
+                return Some((iq.error(StanzaError::new("auth", "not-authorized")), false));
+            };
+            // Commands may hash passwords, which blocks
+            let response = tokio::task::block_in_place(|| {
+                state.commands.lock().unwrap().handle(jid, command, state)
+            });
+            // Commands may have changed accounts
+            host.save_accounts();
+            match response {
+                Ok(response) => (iq.result(Some(IqPayload::Command(response))), false),
+                Err(error) => (iq.error(error), false),
+            }
+        }
+        (Some(IqPayload::DiscoItems(query)), IqType::Get)
+            if host.modules.commands && query.node.as_deref() == Some(COMMANDS_NS) =>
+        {
+            let Some(jid) = jid else {
+                return Some((iq.error(StanzaError::new("auth", "not-authorized")), false));
+            };
+            let items = state
+                .commands
+                .lock()
+                .unwrap()
+                .disco_items(jid_domain(jid), jid, state);
+            (iq.result(Some(IqPayload::DiscoItems(items))), false)
+        }
+        (Some(IqPayload::Register(_)), IqType::Get) if host.modules.register => (
+            iq.result(Some(registration_form(username, accounts))),
+            false,
+        ),
+        (Some(IqPayload::Register(query)), IqType::Set) if host.modules.register => {
+            if let Some(form) = &query.form {
+                let expected = registration_data_form(accounts.lock().unwrap().policy());
+                if let Err(e) = expected.validate_submission(form) {
+                    let mut error = StanzaError::new("modify", "bad-request");
+                    error.text = Some(e.to_string());
+                    return Some((iq.error(error), false));
+                }
+            }
+            let query = &query.clone().with_form_values();
+            // Argon2 is slow on purpose, hash away from the executor and the accounts lock
+            let password = match (&query.password, query.remove) {
+                (Some(password), false) => {
+                    let password = password.clone();
+                    tokio::task::spawn_blocking(move || HashedPassword::new(&password))
+                        .await
+                        .ok()
+                }
+                _ => None,
+            };
+            let mut accounts = accounts.lock().unwrap();
+
+            let result = match (username, query, password) {
+                (Some(username), RegisterQuery { remove: true, .. }, _) => {
+                    accounts.remove(username).map(|_| true)
+                }
+                // Authenticated users may only change their own password
+                (
+                    Some(username),
+                    RegisterQuery {
+                        username: Some(requested),
+                        ..
+                    },
+                    Some(password),
+                ) if requested == username => password
+                    .and_then(|password| accounts.change_password(username, password))
+                    .map(|_| false),
+                (Some(_), _, _) => Err(AccountError::NotAllowed),
+                (
+                    None,
+                    RegisterQuery {
+                        username: Some(requested),
+                        ..
+                    },
+                    Some(password),
+                ) => password
+                    .and_then(|password| {
+                        accounts.register(requested, password, query.key.as_deref())
+                    })
+                    .map(|_| false),
+                (None, _, _) => {
+                    return Some((iq.error(StanzaError::new("modify", "bad-request")), false));
+                }
+            };
+            drop(accounts);
+            if result.is_ok() {
+                host.save_accounts();
+            }
+
+            match result {
+                Ok(removed) => (iq.result(None), removed),
+                Err(e) => (iq.error(e.stanza_error()), false),
+            }
+        }
+        _ => (
+            iq.error(StanzaError::new("cancel", "service-unavailable")),
+            false,
+        ),
+    };
+    Some(response)
+}
+
+/// Tells the client which fields registration needs, or that it is already registered.
+fn registration_form(username: Option<&str>, accounts: &Mutex<Accounts>) -> IqPayload {
+    let accounts = accounts.lock().unwrap();
+
+    let query = match username {
+        Some(username) => RegisterQuery {
+            registered: true,
+            username: Some(username.to_string()),
+            password: Some(String::new()),
+            ..Default::default()
+        },
+        None => match accounts.policy() {
+            RegistrationPolicy::Open => RegisterQuery {
+                instructions: Some("Choose a username and password.".to_string()),
+                username: Some(String::new()),
+                password: Some(String::new()),
+                ..Default::default()
+            },
+            RegistrationPolicy::InviteOnly => RegisterQuery {
+                instructions: Some(
+                    "Choose a username and password, and enter your invitation.".to_string(),
+                ),
+                username: Some(String::new()),
+                password: Some(String::new()),
+                key: Some(String::new()),
+                ..Default::default()
+            },
+            RegistrationPolicy::Closed => RegisterQuery {
+                instructions: Some("Registration is closed.".to_string()),
+                ..Default::default()
+            },
+        },
+    };
+
+    IqPayload::Register(RegisterQuery {
+        form: username
+            .is_none()
+            .then(|| registration_data_form(accounts.policy())),
+        ..query
+    })
+}
+
+/// The registration form offered to clients that understand data forms.
+fn registration_data_form(policy: RegistrationPolicy) -> DataForm {
+    let mut form = DataForm::with_form_type(FormType::Form, REGISTER_NS);
+    form.title = Some("Account registration".to_string());
+
+    if policy == RegistrationPolicy::Closed {
+        form.instructions
+            .push("Registration is closed.".to_string());
+        return form;
+    }
+
+    form.instructions
+        .push("Choose a username and password.".to_string());
+    form.fields.push(
+        Field::new("username", FieldType::TextSingle)
+            .with_label("Username")
+            .required(),
+    );
+    form.fields.push(
+        Field::new("password", FieldType::TextPrivate)
+            .with_label("Password")
+            .required(),
+    );
+    if policy == RegistrationPolicy::InviteOnly {
+        form.fields.push(
+            Field::new("key", FieldType::TextSingle)
+                .with_label("Invitation")
+                .required(),
+        );
+    }
+    form
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, Stream, StreamExt,
+};
+use rand::Rng;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+};
+use tracing::Instrument;
+
+use crate::{
+    sasl_plain_encode, Error, GetNextTrait, Iq, IqPayload, IqType, Message, Presence,
+    RegisterQuery, SaslAuth, SaslResponse, Stanza, StartTls, StartTlsResponse, StreamError,
+    StreamFeatures, StreamHeader, StreamHeaderResponse, Traced, XmlCustomDeserialize,
+    XmlCustomSerialize, CLIENT_NS, STREAM_NS,
+};
+
+pub const CLIENT_PORT: u16 = 9292;
+/// Port of the server's `wss://` listener
+pub const CLIENT_TLS_PORT: u16 = 9293;
+
+/// How long `disconnect` waits for the server to close its side of the stream
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `request` waits for a response, unless the builder says otherwise
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Socket = Traced<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+type Writer = SplitSink<Socket, WsMessage>;
+type Reader = SplitStream<Socket>;
+type PendingRequests = Arc<Mutex<HashMap<String, oneshot::Sender<Iq>>>>;
+
+/// Whether the client negotiates STARTTLS and needs an encrypted connection to authenticate.
+/// Over WebSocket, STARTTLS leaves the connection as it is (RFC 7395), only a `wss://` server
+/// encrypts it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsPolicy {
+    /// Negotiate when the server offers it, and send the password even if the connection is
+    /// not encrypted, which is only safe with a server on the same host
+    Optional,
+    /// Refuse to authenticate unless the connection is encrypted, that is over `wss://`
+    #[default]
+    Required,
+    /// Never negotiate, fail when the server requires it
+    Disabled,
+}
+
+/// How the client gets back online when the connection drops. There is no stream management
+/// on the server, so every reconnection is a new session: requests in flight fail and what was
+/// sent to us in between is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// `None` keeps trying forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// Doubles with every attempt up to `max_delay`, then picks a random point in its upper
+    /// half so that clients dropped together don't come back together.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+/// Something the server sent us that nobody was waiting for, or a change of the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Message(Message),
+    Presence(Presence),
+    /// Requests from other entities, and responses to requests not made through
+    /// `Client::request`
+    Iq(Iq),
+    /// The connection dropped, or a reconnection attempt failed
+    Disconnected(String),
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Back online, with our last presence sent again
+    Reconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    jid: String,
+    password: String,
+    server: String,
+    tls: TlsPolicy,
+    reconnect: ReconnectPolicy,
+    request_timeout: Duration,
+    register: Option<RegisterQuery>,
+}
+
+impl ClientBuilder {
+    pub fn new(jid: &str, password: &str) -> Self {
+        Self {
+            jid: jid.to_string(),
+            password: password.to_string(),
+            server: format!("ws://127.0.0.1:{}", CLIENT_PORT),
+            tls: TlsPolicy::default(),
+            reconnect: ReconnectPolicy::default(),
+            request_timeout: REQUEST_TIMEOUT,
+            register: None,
+        }
+    }
+
+    /// WebSocket URL of the server, `ws://127.0.0.1:9292` by default. `wss://` encrypts the
+    /// connection, which the default `TlsPolicy` needs to authenticate.
+    pub fn server(mut self, url: &str) -> Self {
+        self.server = url.to_string();
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsPolicy) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// How long `Client::request` waits for a response before failing with `Error::Timeout`,
+    /// 30 seconds by default.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Creates the account through in-band registration right before authenticating, with
+    /// the invite token if the server needs one.
+    pub fn register(mut self, invite: Option<&str>) -> Self {
+        self.register = Some(RegisterQuery {
+            key: invite.map(str::to_string),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Connects, negotiates the stream and authenticates.
+    #[tracing::instrument(name = "client", skip_all, fields(jid = %self.jid))]
+    pub async fn connect(mut self) -> Result<Client, Error> {
+        let (writer, reader) = self.open().await?;
+        // The account exists now, reconnections only log in
+        self.register = None;
+
+        let pending = PendingRequests::default();
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let connection = Connection {
+            pending: pending.clone(),
+            events: events_tx,
+            outgoing: outgoing_rx,
+            queue: VecDeque::new(),
+            presence: None,
+        };
+        let jid = self.jid.clone();
+        let request_timeout = self.request_timeout;
+        // The connection outlives this call, it keeps its span
+        let span = tracing::Span::current();
+        let task = tokio::spawn(connection.run(self, writer, reader).instrument(span));
+
+        Ok(Client {
+            jid,
+            request_timeout,
+            outgoing,
+            pending,
+            events,
+            task,
+        })
+    }
+
+    async fn open(&self) -> Result<(Writer, Reader), Error> {
+        let (username, domain) = self
+            .jid
+            .split_once('@')
+            .ok_or_else(|| Error::invalid_value("jid", &self.jid))?;
+
+        let url = url::Url::parse(&self.server)
+            .map_err(|_| Error::invalid_value("server", &self.server))?;
+        let (stream, _) = connect_async(url).await?;
+        let secured = matches!(stream.get_ref(), MaybeTlsStream::Rustls(_));
+        let (mut writer, mut reader) = Traced(stream).split();
+
+        let register = self.register.clone().map(|query| RegisterQuery {
+            username: Some(username.to_string()),
+            password: Some(self.password.clone()),
+            ..query
+        });
+        let credentials = Credentials {
+            username,
+            domain,
+            password: &self.password,
+        };
+        handshake(
+            &mut reader,
+            &mut writer,
+            &credentials,
+            self.tls,
+            secured,
+            register.as_ref(),
+        )
+        .await?;
+
+        Ok((writer, reader))
+    }
+}
+
+/// An authenticated session. Incoming stanzas are read from the client as a `Stream` of
+/// `ClientEvent`, which ends once the connection is gone for good.
+pub struct Client {
+    jid: String,
+    request_timeout: Duration,
+    /// Stanzas for the connection task, which holds them while reconnecting
+    outgoing: mpsc::UnboundedSender<Stanza>,
+    /// Requests waiting for their response, by id
+    pending: PendingRequests,
+    events: mpsc::UnboundedReceiver<ClientEvent>,
+    task: JoinHandle<()>,
+}
+
+impl Client {
+    pub fn builder(jid: &str, password: &str) -> ClientBuilder {
+        ClientBuilder::new(jid, password)
+    }
+
+    pub fn jid(&self) -> &str {
+        &self.jid
+    }
+
+    /// Queues a stanza, which waits for the connection to come back if it dropped.
+    pub async fn send(&self, stanza: Stanza) -> Result<(), Error> {
+        self.outgoing
+            .send(stanza)
+            .map_err(|_| Error::connection_closed())
+    }
+
+    /// Sends a `get` or `set` request and waits for the response with the same id. Error
+    /// responses are returned like any other, no response in time is `Error::Timeout`.
+    pub async fn request(&self, iq: Iq) -> Result<Iq, Error> {
+        let id = iq.id.clone();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+
+        if let Err(e) = self.send(Stanza::Iq(iq)).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        match tokio::time::timeout(self.request_timeout, rx).await {
+            Ok(response) => response.map_err(|_| Error::connection_closed()),
+            Err(_) => {
+                // A late response is then an event like any other
+                self.pending.lock().unwrap().remove(&id);
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    /// Closes the stream and waits a bit for the server to close its side.
+    pub async fn disconnect(self) -> Result<(), Error> {
+        let Client {
+            outgoing, mut task, ..
+        } = self;
+        // The connection task stops once nobody can send anymore
+        drop(outgoing);
+        if tokio::time::timeout(CLOSE_TIMEOUT, &mut task)
+            .await
+            .is_err()
+        {
+            task.abort();
+        }
+        Ok(())
+    }
+}
+
+impl Stream for Client {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ClientEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Ids only need to be unique per sender so that responses, corrections and retractions can
+/// refer to them.
+pub fn next_stanza_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock is before epoch")
+        .as_millis();
+    format!("{}-{}", started, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// State of the task that owns the socket.
+struct Connection {
+    pending: PendingRequests,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    outgoing: mpsc::UnboundedReceiver<Stanza>,
+    /// Stanzas to send once we are connected again
+    queue: VecDeque<Stanza>,
+    /// Last presence we broadcast, sent again after reconnecting
+    presence: Option<Presence>,
+}
+
+impl Connection {
+    async fn run(mut self, builder: ClientBuilder, mut writer: Writer, mut reader: Reader) {
+        loop {
+            let error = match self.serve(&mut writer, &mut reader).await {
+                Ok(()) => return,
+                Err(e) => e,
+            };
+
+            // Dropping the senders fails the requests that will never be answered
+            self.pending.lock().unwrap().clear();
+            self.events
+                .send(ClientEvent::Disconnected(error.to_string()))
+                .ok();
+
+            match self.reconnect(&builder).await {
+                Some((new_writer, new_reader)) => (writer, reader) = (new_writer, new_reader),
+                None => return,
+            }
+            if let Some(presence) = &self.presence {
+                self.queue.push_front(Stanza::Presence(presence.clone()));
+            }
+            self.events.send(ClientEvent::Reconnected).ok();
+        }
+    }
+
+    /// Moves stanzas both ways until the connection drops, or returns `Ok` once the client
+    /// disconnected and the server closed its side.
+    async fn serve(&mut self, writer: &mut Writer, reader: &mut Reader) -> Result<(), Error> {
+        while let Some(stanza) = self.queue.pop_front() {
+            if let Err(e) = writer.send(WsMessage::Text(stanza.into_string())).await {
+                self.queue.push_front(stanza);
+                return Err(e.into());
+            }
+        }
+
+        loop {
+            tokio::select! {
+                text = reader.get_next_text() => match text {
+                    Some(text) => {
+                        // The server tells why it closes the stream, as when it shuts down
+                        if let Ok(error) = StreamError::from_string(&text) {
+                            return Err(error.into());
+                        }
+                        self.incoming(&text)
+                    }
+                    None => return Err(Error::connection_closed()),
+                },
+                stanza = self.outgoing.recv() => {
+                    let Some(stanza) = stanza else {
+                        writer.close().await.ok();
+                        while reader.get_next_text().await.is_some() {}
+                        return Ok(());
+                    };
+                    if let Stanza::Presence(presence) = &stanza {
+                        if presence.to.is_none() {
+                            self.presence = Some(presence.clone());
+                        }
+                    }
+                    if let Err(e) = writer.send(WsMessage::Text(stanza.into_string())).await {
+                        self.queue.push_back(stanza);
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands responses to whoever waits for them and everything else to the event stream.
+    fn incoming(&self, text: &str) {
+        // The server acknowledges stanzas it does not know with plain text
+        let Ok(stanza) = Stanza::from_string(text) else {
+            return;
+        };
+        let event = match stanza {
+            Stanza::Message(message) => ClientEvent::Message(message),
+            Stanza::Presence(presence) => ClientEvent::Presence(presence),
+            Stanza::Iq(iq) => {
+                let is_response = matches!(iq.iq_type, IqType::Result | IqType::Error);
+                let waiting = match is_response {
+                    true => self.pending.lock().unwrap().remove(&iq.id),
+                    false => None,
+                };
+                match waiting {
+                    Some(tx) => {
+                        tx.send(iq).ok();
+                        return;
+                    }
+                    None => ClientEvent::Iq(iq),
+                }
+            }
+        };
+        // Keep reading even when nobody listens, requests still need their responses
+        self.events.send(event).ok();
+    }
+
+    /// Tries again until the policy gives up or the client disconnects. Stanzas sent in the
+    /// meantime are queued.
+    async fn reconnect(&mut self, builder: &ClientBuilder) -> Option<(Writer, Reader)> {
+        let policy = builder.reconnect;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if policy.max_attempts.is_some_and(|max| attempt > max) {
+                return None;
+            }
+
+            let delay = policy.delay(attempt);
+            self.events
+                .send(ClientEvent::Reconnecting { attempt, delay })
+                .ok();
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    stanza = self.outgoing.recv() => match stanza {
+                        Some(stanza) => self.queue.push_back(stanza),
+                        None => return None,
+                    },
+                }
+            }
+
+            match builder.open().await {
+                Ok(connection) => return Some(connection),
+                Err(e) => {
+                    self.events
+                        .send(ClientEvent::Disconnected(e.to_string()))
+                        .ok();
+                }
+            }
+        }
+    }
+}
+
+struct Credentials<'a> {
+    username: &'a str,
+    domain: &'a str,
+    password: &'a str,
+}
+
+enum HandshakeState {
+    Header,
+    Features,
+    Done,
+}
+
+/// Negotiates the stream and authenticates, `secured` telling whether the connection is
+/// encrypted. When `register` is set, the account is created through in-band registration
+/// right before authenticating with it.
+async fn handshake(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    credentials: &Credentials<'_>,
+    tls: TlsPolicy,
+    secured: bool,
+    register: Option<&RegisterQuery>,
+) -> Result<(), Error> {
+    let mut state = HandshakeState::Header;
+
+    let initial_header = StreamHeader {
+        from: Some(format!("{}@{}", credentials.username, credentials.domain)),
+        to: credentials.domain.to_string(),
+        version: "1.0".to_string(),
+        xml_lang: "en".to_string(),
+        xmlns: CLIENT_NS.to_string(),
+        xmlns_stream: STREAM_NS.to_string(),
+    };
+
+    loop {
+        match state {
+            HandshakeState::Header => {
+                writer
+                    .send(WsMessage::Text(initial_header.into_string()))
+                    .await?;
+                let response_header = next_text(reader).await?;
+                StreamHeaderResponse::from_string(&response_header)?;
+
+                state = HandshakeState::Features;
+            }
+            HandshakeState::Features => {
+                // A server that does not serve the domain answers with a stream error
+                let features = next_text(reader).await?;
+                if let Ok(error) = StreamError::from_string(&features) {
+                    return Err(error.into());
+                }
+                let features = StreamFeatures::from_string(&features)?;
+
+                // If features are empty, negotiation is over
+                if features.mechanisms.is_none() && features.start_tls.is_none() {
+                    state = HandshakeState::Done;
+                    continue;
+                }
+
+                if let Some(start_tls) = features.start_tls {
+                    if tls == TlsPolicy::Disabled && start_tls.required {
+                        return Err(Error::Tls("the server requires it".to_string()));
+                    }
+                    if tls != TlsPolicy::Disabled {
+                        let request = StartTls {
+                            xmlns: "urn:ietf:params:xml:ns:xmpp-tls".to_string(),
+                            required: false,
+                        };
+                        writer.send(WsMessage::Text(request.into_string())).await?;
+
+                        let response = next_text(reader).await?;
+                        match StartTlsResponse::from_string(&response)? {
+                            StartTlsResponse::Proceed(_) => {}
+                            StartTlsResponse::Failure(_) => {
+                                return Err(Error::Tls("the server refused it".to_string()))
+                            }
+                        }
+
+                        // The connection stays as it was, the stream restarts all the same
+                        state = HandshakeState::Header;
+                        continue;
+                    }
+                }
+                // Credentials never go over a connection in clear
+                if tls == TlsPolicy::Required && !secured {
+                    return Err(Error::Tls("the connection is not encrypted".to_string()));
+                }
+
+                let mechanisms = features
+                    .mechanisms
+                    .map(|ms| ms.mechanisms.into_iter().map(|m| m.0).collect::<Vec<_>>())
+                    .unwrap_or_default();
+                if !mechanisms.iter().any(|m| m == "PLAIN") {
+                    return Err(Error::Auth("the server does not support PLAIN".to_string()));
+                }
+
+                if let Some(query) = register {
+                    let request = Iq::new(
+                        IqType::Set,
+                        next_stanza_id(),
+                        Some(IqPayload::Register(query.clone())),
+                    );
+                    writer.send(WsMessage::Text(request.into_string())).await?;
+
+                    let response = Iq::from_string(&next_text(reader).await?)?;
+                    if let Some(error) = response.error {
+                        return Err(error.into());
+                    }
+                }
+
+                let auth = SaslAuth {
+                    xmlns: "urn:ietf:params:xml:ns:xmpp-sasl".to_string(),
+                    mechanism: "PLAIN".to_string(),
+                    data: sasl_plain_encode(credentials.username, credentials.password),
+                };
+                writer.send(WsMessage::Text(auth.into_string())).await?;
+
+                match SaslResponse::from_string(&next_text(reader).await?)? {
+                    SaslResponse::Success(_) => {}
+                    SaslResponse::Failure(failure) => {
+                        return Err(Error::Auth(failure.condition));
+                    }
+                }
+
+                // Restart the stream as an authenticated user
+                state = HandshakeState::Header;
+            }
+            HandshakeState::Done => return Ok(()),
+        }
+    }
+}
+
+async fn next_text(reader: &mut Reader) -> Result<String, Error> {
+    reader
+        .get_next_text()
+        .await
+        .ok_or_else(Error::connection_closed)
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::RngCore;
+
+use crate::{
+    Command, CommandAction, CommandNote, CommandStatus, DataForm, DiscoItem, DiscoItems, FormType,
+    StanzaError, COMMANDS_NS,
+};
+
+/// What a command returns once all of its stages are done.
+#[derive(Debug, Default)]
+pub struct CommandOutcome {
+    pub note: Option<CommandNote>,
+    pub form: Option<DataForm>,
+}
+
+/// An ad-hoc command (XEP-0050) that runs against a context `C`, typically the server state.
+pub trait AdHocCommand<C>: Send + Sync {
+    fn node(&self) -> &str;
+
+    fn name(&self) -> &str;
+
+    fn is_allowed(&self, _requester: &str, _context: &C) -> bool {
+        true
+    }
+
+    /// Number of forms the requester has to fill, zero runs the command right away.
+    fn stage_count(&self) -> usize;
+
+    /// Form of `stage`, which may depend on the forms submitted in earlier stages.
+    fn form(&self, stage: usize, submitted: &[DataForm], context: &C) -> DataForm;
+
+    fn execute(
+        &self,
+        requester: &str,
+        submitted: &[DataForm],
+        context: &C,
+    ) -> Result<CommandOutcome, StanzaError>;
+}
+
+struct CommandSession {
+    node: String,
+    requester: String,
+    submitted: Vec<DataForm>,
+    last_active: Instant,
+}
+
+/// Available commands and the multi-stage sessions that are executing them.
+pub struct CommandRegistry<C> {
+    commands: Vec<Box<dyn AdHocCommand<C>>>,
+    sessions: HashMap<String, CommandSession>,
+    session_timeout: Duration,
+}
+
+impl<C> Default for CommandRegistry<C> {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            sessions: HashMap::new(),
+            session_timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+fn bad_request(reason: &str) -> StanzaError {
+    let mut error = StanzaError::new("modify", "bad-request");
+    error.text = Some(reason.to_string());
+    error
+}
+
+impl<C> CommandRegistry<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, command: Box<dyn AdHocCommand<C>>) {
+        self.commands.push(command);
+    }
+
+    /// Nodes and names of the commands `requester` may execute.
+    pub fn commands_for(&self, requester: &str, context: &C) -> Vec<(String, String)> {
+        self.commands
+            .iter()
+            .filter(|command| command.is_allowed(requester, context))
+            .map(|command| (command.node().to_string(), command.name().to_string()))
+            .collect()
+    }
+
+    /// Commands `requester` may execute as the items of the commands node of `service`, the
+    /// answer to a disco#items query for that node.
+    pub fn disco_items(&self, service: &str, requester: &str, context: &C) -> DiscoItems {
+        let items = self
+            .commands_for(requester, context)
+            .into_iter()
+            .map(|(node, name)| DiscoItem {
+                jid: service.to_string(),
+                node: Some(node),
+                name: Some(name),
+            })
+            .collect();
+        DiscoItems {
+            node: Some(COMMANDS_NS.to_string()),
+            items,
+        }
+    }
+
+    /// Handles a `<command/>` request from `requester` and returns the response payload.
+    pub fn handle(
+        &mut self,
+        requester: &str,
+        request: &Command,
+        context: &C,
+    ) -> Result<Command, StanzaError> {
+        let now = Instant::now();
+        let timeout = self.session_timeout;
+        self.sessions
+            .retain(|_, session| now.duration_since(session.last_active) < timeout);
+
+        let command = self
+            .commands
+            .iter()
+            .find(|command| command.node() == request.node)
+            .ok_or(StanzaError::new("cancel", "item-not-found"))?;
+        if !command.is_allowed(requester, context) {
+            return Err(StanzaError::new("auth", "forbidden"));
+        }
+
+        let mut action = request.action.unwrap_or(CommandAction::Execute);
+        if request
+            .form
+            .as_ref()
+            .is_some_and(|form| form.form_type == FormType::Cancel)
+        {
+            action = CommandAction::Cancel;
+        }
+
+        let Some(session_id) = &request.session_id else {
+            if action != CommandAction::Execute {
+                return Err(bad_request("bad-action"));
+            }
+            let session_id = new_session_id();
+
+            if command.stage_count() == 0 {
+                let outcome = command.execute(requester, &[], context)?;
+                return Ok(completed(&request.node, session_id, outcome));
+            }
+
+            let response = executing(command.as_ref(), &session_id, &[], context);
+            self.sessions.insert(
+                session_id,
+                CommandSession {
+                    node: request.node.clone(),
+                    requester: requester.to_string(),
+                    submitted: Vec::new(),
+                    last_active: now,
+                },
+            );
+            return Ok(response);
+        };
+
+        let session = self
+            .sessions
+            .get_mut(session_id)
+            .filter(|session| session.node == request.node && session.requester == requester)
+            .ok_or(bad_request("bad-sessionid"))?;
+        session.last_active = now;
+        let stage = session.submitted.len();
+        let is_last = stage + 1 == command.stage_count();
+
+        match action {
+            CommandAction::Cancel => {
+                self.sessions.remove(session_id);
+                Ok(Command {
+                    status: Some(CommandStatus::Canceled),
+                    session_id: Some(session_id.clone()),
+                    action: None,
+                    ..Command::execute(&request.node)
+                })
+            }
+            CommandAction::Prev => {
+                if session.submitted.pop().is_none() {
+                    return Err(bad_request("bad-action"));
+                }
+                Ok(executing(
+                    command.as_ref(),
+                    session_id,
+                    &session.submitted,
+                    context,
+                ))
+            }
+            CommandAction::Complete if !is_last => Err(bad_request("bad-action")),
+            CommandAction::Next if is_last => Err(bad_request("bad-action")),
+            CommandAction::Execute | CommandAction::Next | CommandAction::Complete => {
+                let submitted = request.form.clone().ok_or(bad_request("bad-payload"))?;
+                let expected = command.form(stage, &session.submitted, context);
+                expected
+                    .validate_submission(&submitted)
+                    .map_err(|e| bad_request(&e.to_string()))?;
+                session.submitted.push(submitted);
+
+                if !is_last {
+                    return Ok(executing(
+                        command.as_ref(),
+                        session_id,
+                        &session.submitted,
+                        context,
+                    ));
+                }
+
+                let session = self.sessions.remove(session_id).unwrap();
+                let outcome = command.execute(requester, &session.submitted, context)?;
+                Ok(completed(&request.node, session_id.clone(), outcome))
+            }
+        }
+    }
+}
+
+/// Response asking the requester to fill the form of the next stage.
+fn executing<C>(
+    command: &dyn AdHocCommand<C>,
+    session_id: &str,
+    submitted: &[DataForm],
+    context: &C,
+) -> Command {
+    let stage = submitted.len();
+    let mut actions = if stage + 1 < command.stage_count() {
+        vec![CommandAction::Next]
+    } else {
+        vec![CommandAction::Complete]
+    };
+    if stage > 0 {
+        actions.push(CommandAction::Prev);
+    }
+
+    Command {
+        session_id: Some(session_id.to_string()),
+        action: None,
+        status: Some(CommandStatus::Executing),
+        actions,
+        form: Some(command.form(stage, submitted, context)),
+        ..Command::execute(command.node())
+    }
+}
+
+fn completed(node: &str, session_id: String, outcome: CommandOutcome) -> Command {
+    Command {
+        session_id: Some(session_id),
+        action: None,
+        status: Some(CommandStatus::Completed),
+        notes: outcome.note.into_iter().collect(),
+        form: outcome.form,
+        ..Command::execute(node)
+    }
+}
+
+fn new_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
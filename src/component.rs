@@ -0,0 +1,101 @@
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    component_digest, ComponentHandshake, Error, GetNextTrait, Stanza, StreamError, StreamHeader,
+    StreamHeaderResponse, XmlCustomDeserialize, XmlCustomSerialize, COMPONENT_NS, STREAM_NS,
+};
+
+pub const COMPONENT_PORT: u16 = 5347;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// An external component (XEP-0114) connected to a server, receiving every stanza sent to
+/// its domain.
+pub struct Component {
+    domain: String,
+    writer: SplitSink<Socket, WsMessage>,
+    reader: SplitStream<Socket>,
+}
+
+impl Component {
+    /// Connects to the component port at `address`, a `ws://` URL, and authenticates as
+    /// `domain` with the secret shared with the server.
+    pub async fn connect(address: &str, domain: &str, secret: &str) -> Result<Self, Error> {
+        let url = url::Url::parse(address).map_err(|_| Error::invalid_value("address", address))?;
+        let (stream, _) = connect_async(url).await?;
+        let (mut writer, mut reader) = stream.split();
+
+        let header = StreamHeader {
+            from: None,
+            to: domain.to_string(),
+            version: "1.0".to_string(),
+            xml_lang: "en".to_string(),
+            xmlns: COMPONENT_NS.to_string(),
+            xmlns_stream: STREAM_NS.to_string(),
+        };
+        writer.send(WsMessage::Text(header.into_string())).await?;
+
+        let response = next_text(&mut reader).await?;
+        let response = StreamHeaderResponse::from_string(&response)?;
+
+        let handshake = ComponentHandshake {
+            digest: Some(component_digest(&response.id, secret)),
+        };
+        writer
+            .send(WsMessage::Text(handshake.into_string()))
+            .await?;
+
+        // The server answers with an empty handshake, or an error before closing the stream
+        let reply = next_text(&mut reader).await?;
+        if ComponentHandshake::from_string(&reply).is_err() {
+            return Err(StreamError::from_string(&reply)?.into());
+        }
+
+        Ok(Self {
+            domain: domain.to_string(),
+            writer,
+            reader,
+        })
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Sends a stanza, whose `from` has to be in our domain.
+    pub async fn send(&mut self, stanza: &impl XmlCustomSerialize) -> Result<(), Error> {
+        self.writer
+            .send(WsMessage::Text(stanza.into_string()))
+            .await?;
+        Ok(())
+    }
+
+    /// Next stanza for our domain, `None` once the server closes the stream. Stanzas we
+    /// don't know are skipped.
+    pub async fn recv(&mut self) -> Option<Stanza> {
+        loop {
+            let text = self.reader.get_next_text().await?;
+            if let Ok(stanza) = Stanza::from_string(&text) {
+                return Some(stanza);
+            }
+        }
+    }
+
+    pub async fn close(mut self) {
+        self.writer.close().await.ok();
+    }
+}
+
+async fn next_text(reader: &mut SplitStream<Socket>) -> Result<String, Error> {
+    reader
+        .get_next_text()
+        .await
+        .ok_or_else(Error::connection_closed)
+}
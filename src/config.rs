@@ -0,0 +1,878 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use toml::{Table, Value};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+
+use crate::{
+    check_restricted_xml, Error, RegistrationPolicy, StreamError, CLIENT_PORT, CLIENT_TLS_PORT,
+    COMPONENT_PORT, MAX_ELEMENT_DEPTH, S2S_PORT,
+};
+
+/// Server settings, read from a TOML file with `ServerConfig::load`.
+///
+/// Every section is optional, missing keys keep the defaults of `ServerConfig::default`.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Domains served by this server, each with its own users
+    pub hosts: Vec<HostConfig>,
+    pub listeners: Listeners,
+    pub tls: Option<TlsFiles>,
+    /// Secret the dialback keys are derived from, random when missing
+    pub dialback_secret: Option<String>,
+    /// Addresses of other servers, used instead of DNS SRV lookups
+    pub routes: HashMap<String, String>,
+    /// Shared secrets of the components allowed to bind a subdomain
+    pub components: HashMap<String, String>,
+    pub modules: Modules,
+    pub auth: AuthConfig,
+    /// Directory of the on-disk data
+    pub storage: Option<PathBuf>,
+    pub limits: Limits,
+    pub rate_limits: RateLimits,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+}
+
+/// A domain served by this server.
+#[derive(Debug, Clone)]
+pub struct HostConfig {
+    pub domain: String,
+    /// Certificate presented to peers asking for this domain with SNI
+    pub certificate: Option<HostCertificate>,
+    /// Replaces the `register`, `archive` and `commands` modules enabled globally
+    pub modules: Option<Modules>,
+    /// Replaces `auth.registration`
+    pub registration: Option<RegistrationPolicy>,
+}
+
+impl HostConfig {
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            certificate: None,
+            modules: None,
+            registration: None,
+        }
+    }
+}
+
+/// Certificate and PKCS#8 key of a single host, PEM files.
+#[derive(Debug, Clone)]
+pub struct HostCertificate {
+    pub cert: String,
+    pub key: String,
+}
+
+/// Addresses the server listens on.
+#[derive(Debug, Clone, Copy)]
+pub struct Listeners {
+    pub c2s: SocketAddr,
+    /// WebSocket over TLS, opened when TLS is set up
+    pub c2s_tls: SocketAddr,
+    pub s2s: SocketAddr,
+    pub component: SocketAddr,
+}
+
+/// Certificate, PKCS#8 key and trusted authorities, all PEM files.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: String,
+    pub key: String,
+    pub ca: String,
+}
+
+/// Optional features of the server, all enabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modules {
+    /// In-band registration and password changes (XEP-0077)
+    pub register: bool,
+    /// Message archive and its queries (XEP-0313)
+    pub archive: bool,
+    /// Ad-hoc administration commands (XEP-0050)
+    pub commands: bool,
+    /// Streams with other servers
+    pub federation: bool,
+    /// External components (XEP-0114)
+    pub components: bool,
+}
+
+impl Modules {
+    pub const NAMES: [&'static str; 5] = [
+        "register",
+        "archive",
+        "commands",
+        "federation",
+        "components",
+    ];
+    /// Modules that can differ between hosts, the others open listeners
+    pub const HOST_NAMES: [&'static str; 3] = ["register", "archive", "commands"];
+
+    fn none() -> Self {
+        Self {
+            register: false,
+            archive: false,
+            commands: false,
+            federation: false,
+            components: false,
+        }
+    }
+
+    fn enable(&mut self, name: &str) -> bool {
+        match name {
+            "register" => self.register = true,
+            "archive" => self.archive = true,
+            "commands" => self.commands = true,
+            "federation" => self.federation = true,
+            "components" => self.components = true,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        let mut modules = Self::none();
+        for name in Self::NAMES {
+            modules.enable(name);
+        }
+        modules
+    }
+}
+
+/// Where accounts are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuthBackend {
+    /// Accounts are lost when the server stops
+    #[default]
+    Memory,
+    /// Accounts are saved in the `accounts` file of the storage directory
+    File,
+}
+
+impl FromStr for AuthBackend {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "memory" => Ok(AuthBackend::Memory),
+            "file" => Ok(AuthBackend::File),
+            _ => Err(Error::config(format!("unknown auth backend {:?}", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub backend: AuthBackend,
+    pub registration: RegistrationPolicy,
+    pub invites: Vec<String>,
+    /// Bare JIDs allowed to run administration commands for the users of their domain
+    pub admins: Vec<String>,
+    /// Bare JIDs allowed to run administration commands that affect every domain
+    pub server_admins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Largest stanza accepted on any stream, in bytes
+    pub max_stanza_size: usize,
+    /// Largest page of archived messages sent for a single query
+    pub max_archive_page: usize,
+    /// Most messages kept in the archive of each user, the oldest are dropped first
+    pub max_archived_messages: usize,
+    /// Deepest nesting of elements in a stanza, up to `MAX_ELEMENT_DEPTH`
+    pub max_depth: usize,
+    /// Most attributes on a single element, namespace declarations included
+    pub max_attributes: usize,
+}
+
+impl Limits {
+    /// WebSocket limits enforcing the stanza size, stanzas are sent one per message.
+    pub fn websocket(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: Some(self.max_stanza_size),
+            max_frame_size: Some(self.max_stanza_size),
+            ..Default::default()
+        }
+    }
+
+    /// Stream error for a stanza over `max_stanza_size`.
+    pub fn stanza_too_large(&self) -> StreamError {
+        StreamError {
+            condition: "policy-violation".to_string(),
+            text: Some(format!("stanza larger than {} bytes", self.max_stanza_size)),
+        }
+    }
+
+    /// Checks a stanza read from a stream against these limits and RFC 6120 restricted XML.
+    pub fn check(&self, stanza: &str) -> Result<(), StreamError> {
+        if stanza.len() > self.max_stanza_size {
+            return Err(self.stanza_too_large());
+        }
+        check_restricted_xml(stanza, self.max_depth, self.max_attributes)
+    }
+}
+
+/// What happens to a client sending faster than its rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateExceeded {
+    /// Its stanzas wait, and the connection is not read meanwhile
+    Queue,
+    /// The stream is closed with a `policy-violation` error
+    Disconnect,
+}
+
+impl FromStr for RateExceeded {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "queue" => Ok(RateExceeded::Queue),
+            "disconnect" => Ok(RateExceeded::Disconnect),
+            _ => Err(Error::config(format!(
+                "unknown action {:?}, expected queue or disconnect",
+                value
+            ))),
+        }
+    }
+}
+
+/// Rates of client streams, enforced for each connection and each user.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub bytes_per_second: usize,
+    /// Bytes that can be sent at once after a pause, at least `max_stanza_size`
+    pub burst_bytes: usize,
+    pub stanzas_per_second: usize,
+    pub burst_stanzas: usize,
+    pub exceeded: RateExceeded,
+    /// Open client connections allowed from a single IP address
+    pub max_connections_per_ip: usize,
+    /// Failed authentications in a row to an account before it is locked out for the IP address
+    pub max_failed_auth: usize,
+    pub lockout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    /// Every stanza, with secrets redacted
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(Error::config(format!("unknown log level {:?}", value))),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+impl LogLevel {
+    /// Logs of this crate and its binaries at this level. Dependencies stop at `info`, their
+    /// traces dump raw frames, secrets included.
+    pub fn targets(self) -> Targets {
+        let level = LevelFilter::from(self);
+        Targets::new()
+            .with_target("mini_jabber", level)
+            .with_target("server", level)
+            .with_target("client", level)
+            .with_default(level.min(LevelFilter::INFO))
+    }
+}
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the fields of its spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::config(format!(
+                "unknown log format {:?}, expected text or json",
+                value
+            ))),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let localhost = |port| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        Self {
+            hosts: vec![HostConfig::new("localhost")],
+            listeners: Listeners {
+                c2s: localhost(CLIENT_PORT),
+                c2s_tls: localhost(CLIENT_TLS_PORT),
+                s2s: localhost(S2S_PORT),
+                component: localhost(COMPONENT_PORT),
+            },
+            tls: None,
+            dialback_secret: None,
+            routes: HashMap::new(),
+            components: HashMap::new(),
+            modules: Modules::default(),
+            auth: AuthConfig {
+                backend: AuthBackend::Memory,
+                registration: RegistrationPolicy::Open,
+                invites: Vec::new(),
+                admins: Vec::new(),
+                server_admins: Vec::new(),
+            },
+            storage: None,
+            limits: Limits {
+                max_stanza_size: 64 * 1024,
+                max_archive_page: 100,
+                max_archived_messages: 10_000,
+                max_depth: 32,
+                max_attributes: 32,
+            },
+            rate_limits: RateLimits {
+                bytes_per_second: 16 * 1024,
+                burst_bytes: 128 * 1024,
+                stanzas_per_second: 20,
+                burst_stanzas: 50,
+                exceeded: RateExceeded::Queue,
+                max_connections_per_ip: 16,
+                max_failed_auth: 5,
+                lockout: Duration::from_secs(60),
+            },
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads and validates the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::config(format!("failed to read {}: {}", path.display(), e)))?;
+        Self::from_toml(&text).map_err(|e| Error::config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parses a configuration, errors start with the key they are about.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let root: Table = text.parse().map_err(Error::config)?;
+        let mut config = Self::default();
+        let root = Section::root(&root);
+        root.allow(&[
+            "hosts",
+            "listeners",
+            "tls",
+            "federation",
+            "components",
+            "modules",
+            "auth",
+            "storage",
+            "limits",
+            "rate_limits",
+            "logging",
+        ])?;
+
+        if let Some(hosts) = root.array("hosts")? {
+            config.hosts.clear();
+            for (i, host) in hosts.iter().enumerate() {
+                let host = root.item("hosts", i, host)?;
+                config.hosts.push(host_config(&host, &config.hosts)?);
+            }
+        }
+
+        if let Some(listeners) = root.table("listeners")? {
+            listeners.allow(&["c2s", "c2s_tls", "s2s", "component"])?;
+            for (key, address) in [
+                ("c2s", &mut config.listeners.c2s),
+                ("c2s_tls", &mut config.listeners.c2s_tls),
+                ("s2s", &mut config.listeners.s2s),
+                ("component", &mut config.listeners.component),
+            ] {
+                if let Some(value) = listeners.parse(key)? {
+                    *address = value;
+                }
+            }
+        }
+
+        if let Some(tls) = root.table("tls")? {
+            tls.allow(&["cert", "key", "ca"])?;
+            config.tls = Some(TlsFiles {
+                cert: tls.required_string("cert")?.to_string(),
+                key: tls.required_string("key")?.to_string(),
+                ca: tls.required_string("ca")?.to_string(),
+            });
+        }
+
+        if let Some(federation) = root.table("federation")? {
+            federation.allow(&["dialback_secret", "routes"])?;
+            config.dialback_secret = federation.string("dialback_secret")?.map(String::from);
+            if let Some(routes) = federation.table("routes")? {
+                for key in routes.keys() {
+                    let address = routes.required_string(key)?;
+                    config.routes.insert(key.to_string(), address.to_string());
+                }
+            }
+        }
+
+        if let Some(components) = root.table("components")? {
+            for key in components.keys() {
+                let secret = components.required_string(key)?;
+                config
+                    .components
+                    .insert(key.to_string(), secret.to_string());
+            }
+        }
+
+        if let Some(modules) = root.table("modules")? {
+            modules.allow(&["enabled"])?;
+            if let Some(enabled) = modules.strings("enabled")? {
+                config.modules = Modules::none();
+                for (i, name) in enabled.iter().enumerate() {
+                    if !config.modules.enable(name) {
+                        return Err(modules.error_at(
+                            "enabled",
+                            i,
+                            format!(
+                                "unknown module {:?}, expected one of {}",
+                                name,
+                                Modules::NAMES.join(", ")
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(auth) = root.table("auth")? {
+            auth.allow(&[
+                "backend",
+                "registration",
+                "invites",
+                "admins",
+                "server_admins",
+            ])?;
+            if let Some(backend) = auth.parse("backend")? {
+                config.auth.backend = backend;
+            }
+            if let Some(registration) = auth.parse("registration")? {
+                config.auth.registration = registration;
+            }
+            if let Some(invites) = auth.strings("invites")? {
+                config.auth.invites = invites;
+            }
+            if let Some(admins) = auth.strings("admins")? {
+                config.auth.admins = admins;
+            }
+            if let Some(admins) = auth.strings("server_admins")? {
+                config.auth.server_admins = admins;
+            }
+        }
+
+        if let Some(storage) = root.table("storage")? {
+            storage.allow(&["path"])?;
+            config.storage = storage.string("path")?.map(PathBuf::from);
+        }
+
+        if let Some(limits) = root.table("limits")? {
+            limits.allow(&[
+                "max_stanza_size",
+                "max_archive_page",
+                "max_archived_messages",
+                "max_depth",
+                "max_attributes",
+            ])?;
+            if let Some(size) = limits.positive("max_stanza_size")? {
+                config.limits.max_stanza_size = size;
+            }
+            if let Some(page) = limits.positive("max_archive_page")? {
+                config.limits.max_archive_page = page;
+            }
+            if let Some(messages) = limits.positive("max_archived_messages")? {
+                config.limits.max_archived_messages = messages;
+            }
+            if let Some(depth) = limits.positive("max_depth")? {
+                config.limits.max_depth = depth;
+            }
+            if let Some(attributes) = limits.positive("max_attributes")? {
+                config.limits.max_attributes = attributes;
+            }
+        }
+
+        if let Some(rates) = root.table("rate_limits")? {
+            rates.allow(&[
+                "bytes_per_second",
+                "burst_bytes",
+                "stanzas_per_second",
+                "burst_stanzas",
+                "exceeded",
+                "max_connections_per_ip",
+                "max_failed_auth",
+                "lockout_seconds",
+            ])?;
+            let limits = &mut config.rate_limits;
+            for (key, value) in [
+                ("bytes_per_second", &mut limits.bytes_per_second),
+                ("burst_bytes", &mut limits.burst_bytes),
+                ("stanzas_per_second", &mut limits.stanzas_per_second),
+                ("burst_stanzas", &mut limits.burst_stanzas),
+                ("max_connections_per_ip", &mut limits.max_connections_per_ip),
+                ("max_failed_auth", &mut limits.max_failed_auth),
+            ] {
+                if let Some(positive) = rates.positive(key)? {
+                    *value = positive;
+                }
+            }
+            if let Some(exceeded) = rates.parse("exceeded")? {
+                limits.exceeded = exceeded;
+            }
+            if let Some(seconds) = rates.positive("lockout_seconds")? {
+                limits.lockout = Duration::from_secs(seconds as u64);
+            }
+        }
+
+        if let Some(logging) = root.table("logging")? {
+            logging.allow(&["level", "format"])?;
+            if let Some(level) = logging.parse("level")? {
+                config.log_level = level;
+            }
+            if let Some(format) = logging.parse("format")? {
+                config.log_format = format;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn host(&self, domain: &str) -> Option<&HostConfig> {
+        self.hosts.iter().find(|host| host.domain == domain)
+    }
+
+    /// Modules of `host`, with its overrides of the global ones.
+    pub fn host_modules(&self, host: &HostConfig) -> Modules {
+        match host.modules {
+            Some(modules) => Modules {
+                federation: self.modules.federation,
+                components: self.modules.components,
+                ..modules
+            },
+            None => self.modules,
+        }
+    }
+
+    pub fn host_registration(&self, host: &HostConfig) -> RegistrationPolicy {
+        host.registration.unwrap_or(self.auth.registration)
+    }
+
+    /// Checks the settings that depend on each other, also after command line overrides.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.hosts.is_empty() {
+            return Err(Error::config("hosts: at least one host is required"));
+        }
+        for (i, host) in self.hosts.iter().enumerate() {
+            if host.certificate.is_some() && self.tls.is_none() {
+                return Err(Error::config(format!(
+                    "hosts[{}].cert: host certificates require the [tls] section",
+                    i
+                )));
+            }
+        }
+
+        for component in self.components.keys() {
+            if !self.modules.components {
+                return Err(Error::config(format!(
+                    "{}: the components module is disabled",
+                    key_path("components", component)
+                )));
+            }
+            if !self
+                .hosts
+                .iter()
+                .any(|host| component.ends_with(&format!(".{}", host.domain)))
+            {
+                return Err(Error::config(format!(
+                    "{}: not a subdomain of a served host",
+                    key_path("components", component)
+                )));
+            }
+        }
+
+        if self.limits.max_depth > MAX_ELEMENT_DEPTH {
+            return Err(Error::config(format!(
+                "limits.max_depth: at most {}",
+                MAX_ELEMENT_DEPTH
+            )));
+        }
+        if self.rate_limits.burst_bytes < self.limits.max_stanza_size {
+            return Err(Error::config(format!(
+                "rate_limits.burst_bytes: must be at least limits.max_stanza_size ({})",
+                self.limits.max_stanza_size
+            )));
+        }
+
+        if self.auth.backend == AuthBackend::File && self.storage.is_none() {
+            return Err(Error::config(
+                "auth.backend: the file backend requires storage.path",
+            ));
+        }
+        let invite_only = self
+            .hosts
+            .iter()
+            .any(|host| self.host_registration(host) == RegistrationPolicy::InviteOnly);
+        if !self.auth.invites.is_empty() && !invite_only {
+            return Err(Error::config(
+                "auth.invites: registration is not invite-only on any host",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads an entry of `hosts`, which must not repeat a domain of `previous`.
+fn host_config(host: &Section, previous: &[HostConfig]) -> Result<HostConfig, Error> {
+    host.allow(&["domain", "cert", "key", "modules", "registration"])?;
+    let domain = host.required_string("domain")?;
+    if !is_valid_domain(domain) {
+        return Err(host.error("domain", format!("invalid domain {:?}", domain)));
+    }
+    if previous.iter().any(|previous| previous.domain == domain) {
+        return Err(host.error("domain", format!("duplicate host {:?}", domain)));
+    }
+
+    let certificate = match (host.string("cert")?, host.string("key")?) {
+        (Some(cert), Some(key)) => Some(HostCertificate {
+            cert: cert.to_string(),
+            key: key.to_string(),
+        }),
+        (None, None) => None,
+        (Some(_), None) => return Err(host.error("key", "missing required key")),
+        (None, Some(_)) => return Err(host.error("cert", "missing required key")),
+    };
+
+    let modules = match host.strings("modules")? {
+        Some(names) => {
+            let mut modules = Modules::none();
+            for (i, name) in names.iter().enumerate() {
+                if !Modules::HOST_NAMES.contains(&name.as_str()) {
+                    return Err(host.error_at(
+                        "modules",
+                        i,
+                        format!(
+                            "unknown module {:?}, hosts can enable {}",
+                            name,
+                            Modules::HOST_NAMES.join(", ")
+                        ),
+                    ));
+                }
+                modules.enable(name);
+            }
+            Some(modules)
+        }
+        None => None,
+    };
+
+    Ok(HostConfig {
+        domain: domain.to_string(),
+        certificate,
+        modules,
+        registration: host.parse("registration")?,
+    })
+}
+
+/// Domains are dot separated labels of letters, digits and hyphens.
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= 1023
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Path of `key` inside the table at `path`, quoting keys that contain dots.
+fn key_path(path: &str, key: &str) -> String {
+    let key = match key.contains(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')) {
+        true => format!("{:?}", key),
+        false => key.to_string(),
+    };
+    match path.is_empty() {
+        true => key,
+        false => format!("{}.{}", path, key),
+    }
+}
+
+/// A table of the configuration with its path, for error messages.
+struct Section<'a> {
+    path: String,
+    table: &'a Table,
+}
+
+impl<'a> Section<'a> {
+    fn root(table: &'a Table) -> Self {
+        Self {
+            path: String::new(),
+            table,
+        }
+    }
+
+    fn error(&self, key: &str, message: impl fmt::Display) -> Error {
+        Error::config(format!("{}: {}", key_path(&self.path, key), message))
+    }
+
+    fn error_at(&self, key: &str, index: usize, message: impl fmt::Display) -> Error {
+        Error::config(format!(
+            "{}[{}]: {}",
+            key_path(&self.path, key),
+            index,
+            message
+        ))
+    }
+
+    /// Rejects the keys that are not in `keys`, mostly typos.
+    fn allow(&self, keys: &[&str]) -> Result<(), Error> {
+        match self.table.keys().find(|key| !keys.contains(&key.as_str())) {
+            Some(key) => Err(self.error(key, "unknown key")),
+            None => Ok(()),
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &'a String> {
+        self.table.keys()
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Section<'a>>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Section {
+                path: key_path(&self.path, key),
+                table,
+            })),
+            Some(_) => Err(self.error(key, "expected a table")),
+        }
+    }
+
+    fn array(&self, key: &str) -> Result<Option<&'a Vec<Value>>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Array(array)) => Ok(Some(array)),
+            Some(_) => Err(self.error(key, "expected an array")),
+        }
+    }
+
+    /// The `index`th table of the array `key`.
+    fn item(&self, key: &str, index: usize, value: &'a Value) -> Result<Section<'a>, Error> {
+        let path = format!("{}[{}]", key_path(&self.path, key), index);
+        match value {
+            Value::Table(table) => Ok(Section { path, table }),
+            _ => Err(Error::config(format!("{}: expected a table", path))),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(self.error(key, "expected a string")),
+        }
+    }
+
+    fn required_string(&self, key: &str) -> Result<&'a str, Error> {
+        self.string(key)?
+            .ok_or_else(|| self.error(key, "missing required key"))
+    }
+
+    fn strings(&self, key: &str) -> Result<Option<Vec<String>>, Error> {
+        let Some(array) = self.array(key)? else {
+            return Ok(None);
+        };
+        array
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match value {
+                Value::String(value) => Ok(value.clone()),
+                _ => Err(self.error_at(key, i, "expected a string")),
+            })
+            .collect::<Result<_, Error>>()
+            .map(Some)
+    }
+
+    fn positive(&self, key: &str) -> Result<Option<usize>, Error> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) if *value > 0 => Ok(Some(*value as usize)),
+            Some(_) => Err(self.error(key, "expected a positive integer")),
+        }
+    }
+
+    /// A string parsed with `FromStr`.
+    fn parse<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let Some(value) = self.string(key)? else {
+            return Ok(None);
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|e| self.error(key, format!("invalid value {:?}: {}", value, e)))
+    }
+}
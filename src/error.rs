@@ -0,0 +1,180 @@
+use std::fmt;
+
+use quick_xml::{escape::EscapeError, events::attributes::AttrError};
+use tokio_tungstenite::tungstenite;
+
+use crate::{StanzaError, StreamError};
+
+/// Failures of the library, for callers that need to tell them apart.
+#[derive(Debug)]
+pub enum Error {
+    /// Malformed XML
+    Xml(quick_xml::Error),
+    MissingAttribute {
+        name: String,
+    },
+    /// A required child element is absent
+    MissingElement {
+        name: String,
+    },
+    /// Another element than the one expected
+    UnexpectedElement {
+        name: String,
+    },
+    /// An attribute or text outside of its allowed values
+    InvalidValue {
+        name: String,
+        value: String,
+    },
+    /// The peer closed the stream with an error, or we would close it with this one
+    Stream(StreamError),
+    /// A request was answered with an error
+    Stanza(StanzaError),
+    /// Authentication or registration was refused, with the reason
+    Auth(String),
+    /// TLS could not be negotiated
+    Tls(String),
+    /// The connection failed or was closed
+    Io(std::io::Error),
+    /// No response came in time
+    Timeout,
+    /// A configuration or account file is invalid, the message says where and why
+    Config(String),
+    /// Link-local service discovery (mDNS) failed
+    Discovery(String),
+}
+
+impl Error {
+    pub fn missing_attribute(name: &str) -> Self {
+        Error::MissingAttribute {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn missing_element(name: &str) -> Self {
+        Error::MissingElement {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn unexpected_element(name: impl AsRef<[u8]>) -> Self {
+        Error::UnexpectedElement {
+            name: String::from_utf8_lossy(name.as_ref()).into_owned(),
+        }
+    }
+
+    pub fn invalid_value(name: &str, value: &str) -> Self {
+        Error::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// The end of the input before the element `name` is closed.
+    pub fn unclosed(name: &str) -> Self {
+        Error::Xml(quick_xml::Error::UnexpectedEof(name.to_string()))
+    }
+
+    pub fn connection_closed() -> Self {
+        Error::Io(std::io::ErrorKind::ConnectionAborted.into())
+    }
+
+    pub fn config(message: impl fmt::Display) -> Self {
+        Error::Config(message.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Xml(e) => write!(f, "malformed XML: {}", e),
+            Error::MissingAttribute { name } => write!(f, "missing attribute {}", name),
+            Error::MissingElement { name } => write!(f, "missing element <{}>", name),
+            Error::UnexpectedElement { name } => write!(f, "unexpected element <{}>", name),
+            Error::InvalidValue { name, value } => write!(f, "invalid {} {:?}", name, value),
+            Error::Stream(e) => write!(f, "stream error: {}", e),
+            Error::Stanza(e) => match &e.text {
+                Some(text) => write!(f, "stanza error: {} ({})", e.condition, text),
+                None => write!(f, "stanza error: {}", e.condition),
+            },
+            Error::Auth(reason) => write!(f, "authentication failed: {}", reason),
+            Error::Tls(reason) => write!(f, "TLS failed: {}", reason),
+            Error::Io(e) => write!(f, "connection failed: {}", e),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Discovery(reason) => write!(f, "service discovery failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Xml(e) => Some(e),
+            Error::Stream(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Self {
+        Error::Xml(e)
+    }
+}
+
+impl From<AttrError> for Error {
+    fn from(e: AttrError) -> Self {
+        Error::Xml(e.into())
+    }
+}
+
+impl From<EscapeError> for Error {
+    fn from(e: EscapeError) -> Self {
+        Error::Xml(e.into())
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Error::Xml(e.into())
+    }
+}
+
+impl From<StreamError> for Error {
+    fn from(e: StreamError) -> Self {
+        Error::Stream(e)
+    }
+}
+
+impl From<StanzaError> for Error {
+    fn from(e: StanzaError) -> Self {
+        Error::Stanza(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<mdns_sd::Error> for Error {
+    fn from(e: mdns_sd::Error) -> Self {
+        Error::Discovery(e.to_string())
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        match e {
+            tungstenite::Error::Io(e) => Error::Io(e),
+            tungstenite::Error::Tls(e) => Error::Tls(e.to_string()),
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                Error::connection_closed()
+            }
+            e => Error::Io(std::io::Error::other(e)),
+        }
+    }
+}
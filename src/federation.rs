@@ -0,0 +1,309 @@
+use std::{collections::HashMap, fs::File, io::BufReader, sync::Arc};
+
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        server::{AllowAnyAnonymousOrAuthenticatedClient, ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        Certificate, PrivateKey, RootCertStore, ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+use crate::{dialback_key, Error, Session, XmlStream};
+
+pub const S2S_PORT: u16 = 5269;
+pub const SERVER_NS: &str = "jabber:server";
+
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// Server-to-server stream, plain XML over TCP and over TLS when configured.
+pub type S2sStream = XmlStream<Box<dyn AsyncStream>>;
+
+/// Static map of domains to `host:port` addresses, used instead of DNS SRV lookups.
+#[derive(Debug, Clone, Default)]
+pub struct HostMap(HashMap<String, String>);
+
+impl HostMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry written as `domain=host:port`.
+    pub fn add(&mut self, entry: &str) -> Result<(), Error> {
+        let (domain, address) = entry
+            .split_once('=')
+            .ok_or_else(|| Error::config(format!("expected domain=host:port, got {}", entry)))?;
+        self.insert(domain, address);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, domain: &str, address: &str) {
+        self.0.insert(domain.to_string(), address.to_string());
+    }
+
+    pub fn address(&self, domain: &str) -> String {
+        self.0
+            .get(domain)
+            .cloned()
+            .unwrap_or_else(|| format!("{}:{}", domain, S2S_PORT))
+    }
+}
+
+/// Our certificates and the authorities we trust to sign the certificates of other servers.
+pub struct TlsIdentity {
+    roots: RootCertStore,
+    default: (Vec<Certificate>, PrivateKey),
+    /// Certificates of the hosts that have their own
+    hosts: HashMap<String, (Vec<Certificate>, PrivateKey)>,
+    acceptor: TlsAcceptor,
+    /// Connectors presenting the certificate of each host, for SASL EXTERNAL
+    connectors: HashMap<String, TlsConnector>,
+    default_connector: TlsConnector,
+}
+
+impl TlsIdentity {
+    /// Loads PEM files. Peers may connect without a certificate, they then have to use
+    /// dialback.
+    pub fn load(cert_path: &str, key_path: &str, ca_path: &str) -> Result<Self, Error> {
+        let default = load_certificate(cert_path, key_path)?;
+        let mut roots = RootCertStore::empty();
+        for ca in rustls_pemfile::certs(&mut BufReader::new(File::open(ca_path)?))? {
+            roots.add(&Certificate(ca)).map_err(tls_error)?;
+        }
+
+        let default_connector = connector(&roots, &default)?;
+        let acceptor = acceptor(&roots, &default, &HashMap::new())?;
+        Ok(Self {
+            roots,
+            default,
+            hosts: HashMap::new(),
+            acceptor,
+            connectors: HashMap::new(),
+            default_connector,
+        })
+    }
+
+    /// Presents another certificate for `domain`, to peers asking for it with SNI and when
+    /// connecting on its behalf.
+    pub fn add_host(&mut self, domain: &str, cert_path: &str, key_path: &str) -> Result<(), Error> {
+        let certificate = load_certificate(cert_path, key_path)?;
+        self.connectors
+            .insert(domain.to_string(), connector(&self.roots, &certificate)?);
+        self.hosts.insert(domain.to_string(), certificate);
+        self.acceptor = acceptor(&self.roots, &self.default, &self.hosts)?;
+        Ok(())
+    }
+
+    fn connector(&self, domain: &str) -> &TlsConnector {
+        self.connectors
+            .get(domain)
+            .unwrap_or(&self.default_connector)
+    }
+}
+
+fn load_certificate(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    let certs: Vec<Certificate> =
+        rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| tls_error(format!("no PKCS#8 private key in {}", key_path)))?;
+    Ok((certs, key))
+}
+
+fn connector(
+    roots: &RootCertStore,
+    (certs, key): &(Vec<Certificate>, PrivateKey),
+) -> Result<TlsConnector, Error> {
+    let client = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots.clone())
+        .with_client_auth_cert(certs.clone(), key.clone())
+        .map_err(tls_error)?;
+    Ok(TlsConnector::from(Arc::new(client)))
+}
+
+fn acceptor(
+    roots: &RootCertStore,
+    default: &(Vec<Certificate>, PrivateKey),
+    hosts: &HashMap<String, (Vec<Certificate>, PrivateKey)>,
+) -> Result<TlsAcceptor, Error> {
+    let certified = |(certs, key): &(Vec<Certificate>, PrivateKey)| -> Result<_, Error> {
+        let key = rustls::sign::any_supported_type(key).map_err(tls_error)?;
+        Ok(Arc::new(CertifiedKey::new(certs.clone(), key)))
+    };
+    let resolver = SniResolver {
+        default: certified(default)?,
+        hosts: hosts
+            .iter()
+            .map(|(domain, certificate)| Ok((domain.clone(), certified(certificate)?)))
+            .collect::<Result<_, Error>>()?,
+    };
+
+    let server = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots.clone()).boxed(),
+        )
+        .with_cert_resolver(Arc::new(resolver));
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+fn tls_error(e: impl std::fmt::Display) -> Error {
+    Error::Tls(e.to_string())
+}
+
+/// Picks the certificate of the host a peer asks for, the default one without SNI.
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello
+            .server_name()
+            .and_then(|name| self.hosts.get(name));
+        Some(host.unwrap_or(&self.default).clone())
+    }
+}
+
+/// How this server reaches and authenticates other servers, for any of its domains.
+pub struct Federation {
+    pub hosts: HostMap,
+    /// Largest stanza accepted from other servers
+    pub max_stanza_size: Option<usize>,
+    tls: Option<TlsIdentity>,
+    /// Secret the dialback keys are derived from
+    secret: String,
+}
+
+impl Federation {
+    /// Uses a random dialback secret when none is given.
+    pub fn new(hosts: HostMap, tls: Option<TlsIdentity>, secret: Option<String>) -> Self {
+        let secret = secret.unwrap_or_else(|| {
+            let mut bytes = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+        });
+
+        Self {
+            hosts,
+            max_stanza_size: None,
+            tls,
+            secret,
+        }
+    }
+
+    /// Key proving that our domain `originating` opened the stream `stream_id` towards
+    /// `receiving`.
+    pub fn dialback_key(&self, originating: &str, receiving: &str, stream_id: &str) -> String {
+        dialback_key(&self.secret, receiving, originating, stream_id)
+    }
+
+    /// Checks a key another server received on the stream `stream_id` supposedly from our
+    /// domain `originating`.
+    pub fn verify_dialback_key(
+        &self,
+        originating: &str,
+        receiving: &str,
+        stream_id: &str,
+        key: &str,
+    ) -> bool {
+        let expected = self.dialback_key(originating, receiving, stream_id);
+        expected.as_bytes().ct_eq(key.as_bytes()).into()
+    }
+
+    /// Accepts an incoming stream, returning the peer certificate if it presented one. The
+    /// TLS features of `session` are filled in.
+    pub async fn accept(
+        &self,
+        stream: TcpStream,
+        session: &mut Session,
+    ) -> Result<(S2sStream, Option<Certificate>), Error> {
+        let Some(tls) = &self.tls else {
+            let stream: Box<dyn AsyncStream> = Box::new(stream);
+            return Ok((XmlStream::new(stream, self.max_stanza_size), None));
+        };
+
+        let stream = tls.acceptor.accept(stream).await?;
+        let connection = stream.get_ref().1;
+        let certificate = connection
+            .peer_certificates()
+            .and_then(|certificates| certificates.first().cloned());
+        session.features.tls = true;
+        session.features.channel_binding = connection
+            .export_keying_material(vec![0u8; 32], b"EXPORTER-Channel-Binding", None)
+            .ok();
+
+        let stream: Box<dyn AsyncStream> = Box::new(stream);
+        Ok((XmlStream::new(stream, self.max_stanza_size), certificate))
+    }
+
+    /// Accepts a TLS connection from a client, for WebSocket streams over `wss://`. The TLS
+    /// features of `session` are filled in.
+    pub async fn accept_tls(
+        &self,
+        stream: TcpStream,
+        session: &mut Session,
+    ) -> Result<Box<dyn AsyncStream>, Error> {
+        let tls = self
+            .tls
+            .as_ref()
+            .ok_or_else(|| tls_error("TLS is not set up"))?;
+        let stream = tls.acceptor.accept(stream).await?;
+        session.features.tls = true;
+        session.features.channel_binding = stream
+            .get_ref()
+            .1
+            .export_keying_material(vec![0u8; 32], b"EXPORTER-Channel-Binding", None)
+            .ok();
+        Ok(Box::new(stream))
+    }
+
+    /// Opens a stream from our domain `from` to the server of `domain`, checking its
+    /// certificate when we use TLS.
+    pub async fn connect(&self, from: &str, domain: &str) -> Result<S2sStream, Error> {
+        let stream = TcpStream::connect(self.hosts.address(domain)).await?;
+        let stream: Box<dyn AsyncStream> = match &self.tls {
+            Some(tls) => {
+                let name = ServerName::try_from(domain).map_err(tls_error)?;
+                Box::new(tls.connector(from).connect(name, stream).await?)
+            }
+            None => Box::new(stream),
+        };
+        Ok(XmlStream::new(stream, self.max_stanza_size))
+    }
+
+    /// Whether TLS is set up, which is what SASL EXTERNAL needs.
+    pub fn has_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+}
+
+/// Whether a certificate, already checked against our authorities, was issued for `domain`.
+pub fn certificate_matches(certificate: &Certificate, domain: &str) -> bool {
+    let Ok(certificate) = webpki::EndEntityCert::try_from(certificate.0.as_slice()) else {
+        return false;
+    };
+    let Ok(name) = webpki::SubjectNameRef::try_from_ascii_str(domain) else {
+        return false;
+    };
+    certificate.verify_is_valid_for_subject_name(name).is_ok()
+}
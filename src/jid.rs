@@ -0,0 +1,12 @@
+/// JID without its resource.
+pub fn jid_bare(jid: &str) -> &str {
+    jid.split_once('/').map(|(bare, _)| bare).unwrap_or(jid)
+}
+
+/// Domain part of a JID.
+pub fn jid_domain(jid: &str) -> &str {
+    let bare = jid_bare(jid);
+    bare.split_once('@')
+        .map(|(_, domain)| domain)
+        .unwrap_or(bare)
+}
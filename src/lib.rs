@@ -1,5 +1,34 @@
+// Lets `#[derive(XmlElement)]` name this crate from inside it
+extern crate self as mini_jabber;
+
+mod accounts;
+mod admin;
+mod archive;
+mod client;
+mod commands;
+mod component;
+mod config;
+mod error;
+mod federation;
+mod jid;
+mod link_local;
+mod rate_limit;
+mod session;
 mod xmpp;
 mod stream;
 
+pub use accounts::*;
+pub use admin::*;
+pub use archive::*;
+pub use client::*;
+pub use commands::*;
+pub use component::*;
+pub use config::*;
+pub use error::*;
+pub use federation::*;
+pub use jid::*;
+pub use link_local::*;
+pub use rate_limit::*;
+pub use session::*;
 pub use xmpp::*;
-pub use stream::*;
\ No newline at end of file
+pub use stream::*;
@@ -0,0 +1,291 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use futures_util::SinkExt;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use rand::RngCore;
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::{
+    Error, GetNextTrait, StreamError, StreamHeader, StreamHeaderResponse, XmlCustomDeserialize,
+    XmlCustomSerialize, XmlStream,
+};
+
+/// DNS-SD service type of link-local messaging (XEP-0174).
+pub const PRESENCE_SERVICE: &str = "_presence._tcp.local.";
+pub const LINK_LOCAL_PORT: u16 = 5298;
+/// Largest stanza accepted from a peer, the default limit of the server
+const MAX_STANZA_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PeerStatus {
+    #[default]
+    Avail,
+    Away,
+    Dnd,
+}
+
+impl PeerStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeerStatus::Avail => "avail",
+            PeerStatus::Away => "away",
+            PeerStatus::Dnd => "dnd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "avail" => Some(PeerStatus::Avail),
+            "away" => Some(PeerStatus::Away),
+            "dnd" => Some(PeerStatus::Dnd),
+            _ => None,
+        }
+    }
+}
+
+/// What a peer publishes in its TXT record, see XEP-0174 section 6.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerPresence {
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub nick: Option<String>,
+    /// Server JID of the user, if they have one
+    pub jid: Option<String>,
+    pub status: PeerStatus,
+    /// Status message
+    pub msg: Option<String>,
+}
+
+impl PeerPresence {
+    fn txt_properties(&self, port: u16) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        properties.insert("txtvers".to_string(), "1".to_string());
+        properties.insert("port.p2pj".to_string(), port.to_string());
+        properties.insert("status".to_string(), self.status.name().to_string());
+        for (key, value) in [
+            ("1st", &self.first),
+            ("last", &self.last),
+            ("nick", &self.nick),
+            ("jid", &self.jid),
+            ("msg", &self.msg),
+        ] {
+            if let Some(value) = value {
+                properties.insert(key.to_string(), value.clone());
+            }
+        }
+        properties
+    }
+
+    fn from_service(info: &ServiceInfo) -> Self {
+        let value = |key: &str| {
+            info.get_property_val_str(key)
+                .map(|value| value.to_string())
+        };
+        Self {
+            first: value("1st"),
+            last: value("last"),
+            nick: value("nick"),
+            jid: value("jid"),
+            status: info
+                .get_property_val_str("status")
+                .and_then(PeerStatus::from_name)
+                .unwrap_or_default(),
+            msg: value("msg"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// Instance name, `user@machine`
+    pub name: String,
+    pub address: SocketAddr,
+    pub presence: PeerPresence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// A peer showed up or changed its presence
+    Available(Peer),
+    Unavailable(String),
+}
+
+/// Our advertisement on the local network.
+pub struct LinkLocal {
+    daemon: ServiceDaemon,
+    name: String,
+    port: u16,
+}
+
+impl LinkLocal {
+    /// Advertises `name` (`user@machine`) accepting streams on `port`. With an `interface`,
+    /// such as `lo`, mDNS only runs on it.
+    pub fn advertise(
+        name: &str,
+        port: u16,
+        presence: &PeerPresence,
+        interface: Option<&str>,
+    ) -> Result<Self, Error> {
+        let daemon = ServiceDaemon::new()?;
+        if let Some(interface) = interface {
+            daemon.disable_interface(IfKind::All)?;
+            daemon.enable_interface(IfKind::Name(interface.to_string()))?;
+            if interface == "lo" {
+                daemon.enable_interface(IfKind::LoopbackV4)?;
+            }
+        }
+
+        let link_local = Self {
+            daemon,
+            name: name.to_string(),
+            port,
+        };
+        link_local.update_presence(presence)?;
+        Ok(link_local)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Publishes a new TXT record, peers see it as a new `Available` event.
+    pub fn update_presence(&self, presence: &PeerPresence) -> Result<(), Error> {
+        let machine = self
+            .name
+            .split_once('@')
+            .map(|(_, machine)| machine)
+            .unwrap_or(&self.name);
+        let info = ServiceInfo::new(
+            PRESENCE_SERVICE,
+            &self.name,
+            &format!("{}.local.", machine),
+            "",
+            self.port,
+            presence.txt_properties(self.port),
+        )?
+        .enable_addr_auto();
+        self.daemon.register(info)?;
+        Ok(())
+    }
+
+    /// Watches for other peers on the network.
+    pub fn browse(&self) -> Result<mpsc::UnboundedReceiver<PeerEvent>, Error> {
+        let events = self.daemon.browse(PRESENCE_SERVICE)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let own_name = self.name.clone();
+
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv_async().await {
+                let event = match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let name = instance_name(info.get_fullname());
+                        let Some(ip) = info.get_addresses_v4().into_iter().next().copied() else {
+                            continue;
+                        };
+                        let port = info
+                            .get_property_val_str("port.p2pj")
+                            .and_then(|port| port.parse().ok())
+                            .unwrap_or(info.get_port());
+                        PeerEvent::Available(Peer {
+                            name: name.to_string(),
+                            address: SocketAddr::from((ip, port)),
+                            presence: PeerPresence::from_service(&info),
+                        })
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        PeerEvent::Unavailable(instance_name(&fullname).to_string())
+                    }
+                    _ => continue,
+                };
+
+                let is_own = match &event {
+                    PeerEvent::Available(peer) => peer.name == own_name,
+                    PeerEvent::Unavailable(name) => *name == own_name,
+                };
+                if !is_own && sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Withdraws the advertisement so peers see us leave.
+    pub fn shutdown(self) {
+        let fullname = format!("{}.{}", self.name, PRESENCE_SERVICE);
+        if let Ok(status) = self.daemon.unregister(&fullname) {
+            status.recv_timeout(std::time::Duration::from_secs(1)).ok();
+        }
+        self.daemon.shutdown().ok();
+    }
+}
+
+fn instance_name(fullname: &str) -> &str {
+    fullname
+        .strip_suffix(PRESENCE_SERVICE)
+        .map(|name| name.trim_end_matches('.'))
+        .unwrap_or(fullname)
+}
+
+/// Direct XML stream between two peers, there is no authentication on link-local streams.
+pub type PeerStream = XmlStream<TcpStream>;
+
+/// Opens a stream to `peer`, introducing ourselves as `name`.
+pub async fn connect_peer(name: &str, peer: &Peer) -> Result<PeerStream, Error> {
+    let mut stream = XmlStream::new(
+        TcpStream::connect(peer.address).await?,
+        Some(MAX_STANZA_SIZE),
+    );
+
+    let header = StreamHeader {
+        from: Some(name.to_string()),
+        to: peer.name.clone(),
+        version: "1.0".to_string(),
+        xml_lang: "en".to_string(),
+        xmlns: "jabber:client".to_string(),
+        xmlns_stream: "http://etherx.jabber.org/streams".to_string(),
+    };
+    stream.send(WsMessage::Text(header.into_string())).await?;
+
+    let response = stream
+        .get_next_text()
+        .await
+        .ok_or_else(Error::connection_closed)?;
+    if let Ok(error) = StreamError::from_string(&response) {
+        return Err(error.into());
+    }
+    StreamHeaderResponse::from_string(&response)?;
+    Ok(stream)
+}
+
+/// Accepts a stream for `name` and returns it with the name of the peer that opened it.
+pub async fn accept_peer(name: &str, stream: TcpStream) -> Result<(String, PeerStream), Error> {
+    let mut stream = XmlStream::new(stream, Some(MAX_STANZA_SIZE));
+
+    let header = stream
+        .get_next_text()
+        .await
+        .ok_or_else(Error::connection_closed)?;
+    let header = StreamHeader::from_string(&header)?;
+    if header.to != name {
+        let error = StreamError::new("host-unknown");
+        stream.send(WsMessage::Text(error.into_string())).await?;
+        return Err(error.into());
+    }
+    let peer = header
+        .from
+        .clone()
+        .ok_or_else(|| Error::missing_attribute("from"))?;
+
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let response = StreamHeaderResponse {
+        from: name.to_string(),
+        to: peer.clone(),
+        ..header.into_response(id.iter().map(|byte| format!("{:02x}", byte)).collect())
+    };
+    stream.send(WsMessage::Text(response.into_string())).await?;
+    Ok((peer, stream))
+}
@@ -0,0 +1,114 @@
+use std::time::{Duration, Instant};
+
+use crate::RateLimits;
+
+/// Token bucket refilled at `rate` tokens per second, holding at most `burst`.
+///
+/// Taking more than what is left puts the bucket in debt, which is paid back by waiting.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(rate: usize, burst: usize) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes `amount` tokens, and returns how long to wait before going on when there were
+    /// not enough.
+    pub fn take(&mut self, amount: usize) -> Option<Duration> {
+        let now = Instant::now();
+        self.tokens = self.tokens_at(now);
+        self.updated = now;
+
+        self.tokens -= amount as f64;
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+
+    /// Whether the bucket refilled up to `burst`, when it is no different from a new one.
+    pub fn is_full(&self) -> bool {
+        self.tokens_at(Instant::now()) >= self.burst
+    }
+
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// Limits both the bytes and the stanzas a stream or a user sends.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes: TokenBucket,
+    stanzas: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &RateLimits) -> Self {
+        Self {
+            bytes: TokenBucket::new(limits.bytes_per_second, limits.burst_bytes),
+            stanzas: TokenBucket::new(limits.stanzas_per_second, limits.burst_stanzas),
+        }
+    }
+
+    /// Accounts for a stanza of `size` bytes, see `TokenBucket::take`.
+    pub fn take(&mut self, size: usize) -> Option<Duration> {
+        let bytes = self.bytes.take(size);
+        let stanzas = self.stanzas.take(1);
+        bytes.max(stanzas)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.bytes.is_full() && self.stanzas.is_full()
+    }
+}
+
+/// Failed authentications of a peer, which is locked out once it has too many.
+///
+/// Failures are forgotten after as long as a lockout lasts.
+#[derive(Debug, Clone, Default)]
+pub struct AuthFailures {
+    count: usize,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl AuthFailures {
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .is_some_and(|locked_until| Instant::now() < locked_until)
+    }
+
+    /// Counts a failure, and starts a lockout when it is the `max`th in a row.
+    pub fn fail(&mut self, max: usize, lockout: Duration) {
+        if self.is_expired(lockout) {
+            self.count = 0;
+        }
+        let now = Instant::now();
+        self.count += 1;
+        self.last_failure = Some(now);
+        if self.count >= max {
+            self.count = 0;
+            self.locked_until = Some(now + lockout);
+        }
+    }
+
+    /// Whether the lockout, if any, is over and the last failure older than `lockout`, when
+    /// there is nothing left to remember.
+    pub fn is_expired(&self, lockout: Duration) -> bool {
+        !self.is_locked()
+            && self
+                .last_failure
+                .is_none_or(|last_failure| last_failure.elapsed() >= lockout)
+    }
+}
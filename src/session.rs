@@ -0,0 +1,102 @@
+use std::{fmt, net::SocketAddr, time::SystemTime};
+
+use rand::{rngs::OsRng, RngCore};
+
+/// Unguessable id of a stream, 128 bits from the operating system's random source.
+///
+/// Dialback keys and component handshakes are derived from it, so it must not be predictable.
+pub fn new_stream_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Who is on the other end of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Client,
+    Server,
+    Component,
+}
+
+impl fmt::Display for SessionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SessionKind::Client => "c2s",
+            SessionKind::Server => "s2s",
+            SessionKind::Component => "component",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What was agreed on during stream negotiation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegotiatedFeatures {
+    /// The connection is encrypted
+    pub tls: bool,
+    /// `tls-exporter` data (RFC 9266) for SCRAM-*-PLUS, when the connection is encrypted
+    pub channel_binding: Option<Vec<u8>>,
+    /// SASL mechanism, `dialback` or `handshake`, once the peer is authenticated
+    pub mechanism: Option<String>,
+}
+
+/// An incoming connection, from accept until it closes.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub kind: SessionKind,
+    /// Id of the current stream, which changes at each stream restart
+    pub stream_id: String,
+    pub peer: SocketAddr,
+    pub features: NegotiatedFeatures,
+    /// Username, server domain or component domain the peer authenticated as
+    pub identity: Option<String>,
+    /// Address stanzas of this session are sent from
+    pub jid: Option<String>,
+    pub opened_at: SystemTime,
+    pub authenticated_at: Option<SystemTime>,
+}
+
+impl Session {
+    pub fn new(kind: SessionKind, peer: SocketAddr) -> Self {
+        Self {
+            kind,
+            stream_id: new_stream_id(),
+            peer,
+            features: NegotiatedFeatures::default(),
+            identity: None,
+            jid: None,
+            opened_at: SystemTime::now(),
+            authenticated_at: None,
+        }
+    }
+
+    /// Restarts the stream after TLS or SASL with a fresh id, which it returns.
+    pub fn restart(&mut self) -> String {
+        self.stream_id = new_stream_id();
+        self.stream_id.clone()
+    }
+
+    pub fn authenticate(&mut self, mechanism: &str, identity: &str) {
+        self.features.mechanism = Some(mechanism.to_string());
+        self.identity = Some(identity.to_string());
+        self.authenticated_at = Some(SystemTime::now());
+    }
+
+    pub fn bind(&mut self, jid: &str) {
+        self.jid = Some(jid.to_string());
+    }
+}
+
+/// Short form for logs: kind, peer address, start of the stream id and identity.
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.stream_id.get(..8).unwrap_or(&self.stream_id);
+        write!(f, "{} {} [{}]", self.kind, self.peer, id)?;
+        match (&self.jid, &self.identity) {
+            (Some(jid), _) => write!(f, " {}", jid),
+            (None, Some(identity)) => write!(f, " {}", identity),
+            (None, None) => Ok(()),
+        }
+    }
+}
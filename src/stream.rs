@@ -1,7 +1,15 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
 use async_trait::async_trait;
-use futures_util::{stream::SplitStream, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_tungstenite::WebSocketStream;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{self, error::CapacityError, Message};
+
+use crate::Redacted;
 
 #[async_trait]
 pub trait GetNextTrait {
@@ -9,14 +17,330 @@ pub trait GetNextTrait {
 }
 
 #[async_trait]
-impl<T> GetNextTrait for SplitStream<WebSocketStream<T>>
+impl<S> GetNextTrait for S
 where
-    T: AsyncRead + AsyncWrite + Unpin + Send,
+    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin + Send,
 {
     async fn get_next_text(&mut self) -> Option<String> {
         self.next()
             .await
             .and_then(|result| result.ok())
+            // A close frame ends the stream like the end of the connection does
+            .filter(|message| !message.is_close())
             .and_then(|message| message.into_text().ok())
     }
 }
+
+/// WebSocket or XML stream that logs the stanzas it reads and writes at trace level, with their
+/// secrets redacted.
+#[derive(Debug)]
+pub struct Traced<S>(pub S);
+
+impl<S> Stream for Traced<S>
+where
+    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let next = ready!(self.0.poll_next_unpin(cx));
+        if let Some(Ok(Message::Text(text))) = &next {
+            tracing::trace!(stanza = %Redacted(text), "received");
+        }
+        Poll::Ready(next)
+    }
+}
+
+impl<S> Sink<Message> for Traced<S>
+where
+    S: Sink<Message> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.0.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), S::Error> {
+        if let Message::Text(text) = &message {
+            tracing::trace!(stanza = %Redacted(text), "sent");
+        }
+        self.0.start_send_unpin(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.0.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.0.poll_close_unpin(cx)
+    }
+}
+
+/// XML stream over a byte stream such as TCP (RFC 6120 section 4), framed like the WebSocket
+/// streams: the stream header and each top-level element are read and written as one text
+/// message. Closing writes `</stream:stream>`, reading it ends the stream.
+#[derive(Debug)]
+pub struct XmlStream<S> {
+    inner: S,
+    read: Vec<u8>,
+    scanner: Scanner,
+    write: Vec<u8>,
+    /// Largest element accepted, like the WebSocket message size limit
+    max_stanza_size: Option<usize>,
+    /// Whether the other side closed the stream
+    ended: bool,
+    /// Whether we closed the stream
+    closed: bool,
+}
+
+impl<S> XmlStream<S> {
+    pub fn new(inner: S, max_stanza_size: Option<usize>) -> Self {
+        Self {
+            inner,
+            read: Vec::new(),
+            scanner: Scanner::default(),
+            write: Vec::new(),
+            max_stanza_size,
+            ended: false,
+            closed: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn too_large(&self, size: usize) -> Option<tungstenite::Error> {
+        let max_size = self.max_stanza_size?;
+        (size > max_size).then_some(tungstenite::Error::Capacity(
+            CapacityError::MessageTooLong { size, max_size },
+        ))
+    }
+}
+
+/// What starts a buffer of stream data.
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    /// Whitespace or an XML declaration between elements
+    Skip,
+    Header,
+    Element,
+    /// `</stream:stream>`
+    End,
+}
+
+fn not_well_formed(reason: &str) -> tungstenite::Error {
+    tungstenite::Error::Io(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Position of the `>` ending the tag `tag` starts with, skipping those in attribute values.
+fn tag_end(tag: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    for (i, &byte) in tag.iter().enumerate() {
+        match (quote, byte) {
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (Some(open), _) if open == byte => quote = None,
+            (None, b'>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Progress through the frame being read, kept between reads so that each byte is scanned
+/// once however the data is split.
+#[derive(Debug, Default)]
+struct Scanner {
+    /// Length of the data scanned so far, up to the first incomplete markup
+    position: usize,
+    /// Elements open at `position`
+    depth: usize,
+}
+
+impl Scanner {
+    /// Finds the first frame of `buffer` and its length, `None` until enough data arrived.
+    /// `buffer` must start with the data given to the previous calls, and the frame must be
+    /// removed from it once found. Errors tell why the data is not well-formed.
+    fn next_frame(&mut self, buffer: &[u8]) -> Result<Option<(Frame, usize)>, &'static str> {
+        let frame = self.scan(buffer)?;
+        if frame.is_some() {
+            *self = Self::default();
+        }
+        Ok(frame)
+    }
+
+    fn scan(&mut self, buffer: &[u8]) -> Result<Option<(Frame, usize)>, &'static str> {
+        if self.position == 0 {
+            let Some(start) = buffer.iter().position(|byte| !byte.is_ascii_whitespace()) else {
+                return Ok((!buffer.is_empty()).then_some((Frame::Skip, buffer.len())));
+            };
+            if start > 0 {
+                return Ok(Some((Frame::Skip, start)));
+            }
+            if buffer[0] != b'<' {
+                return Err("text outside of elements");
+            }
+        }
+
+        while self.position < buffer.len() {
+            let rest = &buffer[self.position..];
+            if rest[0] != b'<' {
+                // Text content
+                match rest.iter().position(|&byte| byte == b'<') {
+                    Some(text) => self.position += text,
+                    None => self.position = buffer.len(),
+                }
+                continue;
+            }
+
+            let (terminator, skipped): (&[u8], _) = if rest.starts_with(b"<![CDATA[") {
+                (b"]]>", true)
+            } else if rest.starts_with(b"<!--") {
+                (b"-->", true)
+            } else if rest.starts_with(b"<?") {
+                (b"?>", true)
+            } else if b"<![CDATA[".starts_with(rest) || b"<!--".starts_with(rest) {
+                return Ok(None);
+            } else {
+                (b">", false)
+            };
+            if skipped {
+                let Some(end) = find(rest, terminator) else {
+                    return Ok(None);
+                };
+                self.position += end + terminator.len();
+                if self.depth == 0 {
+                    return Ok(Some((Frame::Skip, self.position)));
+                }
+                continue;
+            }
+
+            let Some(end) = tag_end(rest) else {
+                return Ok(None);
+            };
+            let tag = &rest[..=end];
+            self.position += end + 1;
+            if tag.starts_with(b"</") {
+                if self.depth == 0 {
+                    return Ok(Some((Frame::End, self.position)));
+                }
+                self.depth -= 1;
+            } else if !tag.ends_with(b"/>") {
+                let name = tag[1..]
+                    .split(|&byte| byte.is_ascii_whitespace() || byte == b'>')
+                    .next()
+                    .unwrap_or_default();
+                if self.depth == 0 && name.rsplit(|&byte| byte == b':').next() == Some(b"stream") {
+                    return Ok(Some((Frame::Header, self.position)));
+                }
+                self.depth += 1;
+            }
+            if self.depth == 0 {
+                return Ok(Some((Frame::Element, self.position)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<S: AsyncRead + Unpin> Stream for XmlStream<S> {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.ended {
+                return Poll::Ready(None);
+            }
+
+            match this.scanner.next_frame(&this.read) {
+                Ok(Some((frame, length))) => {
+                    let data: Vec<u8> = this.read.drain(..length).collect();
+                    match frame {
+                        Frame::Skip => continue,
+                        Frame::End => {
+                            this.ended = true;
+                            return Poll::Ready(None);
+                        }
+                        Frame::Header | Frame::Element => {}
+                    }
+                    if let Some(error) = this.too_large(data.len()) {
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    let text = String::from_utf8(data).map_err(|_| not_well_formed("not UTF-8"));
+                    return Poll::Ready(Some(text.map(Message::Text)));
+                }
+                Ok(None) => {}
+                Err(reason) => return Poll::Ready(Some(Err(not_well_formed(reason)))),
+            }
+            if let Some(error) = this.too_large(this.read.len()) {
+                return Poll::Ready(Some(Err(error)));
+            }
+
+            let mut chunk = [0u8; 4096];
+            let mut buffer = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buffer))?;
+            if buffer.filled().is_empty() {
+                return Poll::Ready(None);
+            }
+            this.read.extend_from_slice(buffer.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> XmlStream<S> {
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), tungstenite::Error>> {
+        while !self.write.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+            self.write.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Sink<Message> for XmlStream<S> {
+    type Error = tungstenite::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_write_buffer(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        if self.closed {
+            return Err(tungstenite::Error::AlreadyClosed);
+        }
+        match message {
+            Message::Text(text) => self.write.extend_from_slice(text.as_bytes()),
+            Message::Close(_) => {
+                self.write.extend_from_slice(b"</stream:stream>");
+                self.closed = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_write_buffer(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut self.inner).poll_flush(cx))?))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.closed {
+            self.write.extend_from_slice(b"</stream:stream>");
+            self.closed = true;
+        }
+        ready!(self.poll_write_buffer(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut self.inner).poll_shutdown(cx))?))
+    }
+}
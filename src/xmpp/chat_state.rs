@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::stanza::Message;
+
+pub const CHAT_STATES_NS: &str = "http://jabber.org/protocol/chatstates";
+/// Time without keystrokes after which `<composing/>` turns into `<paused/>`
+pub const PAUSED_AFTER: Duration = Duration::from_secs(5);
+/// Time without keystrokes after which the conversation becomes `<inactive/>`
+pub const INACTIVE_AFTER: Duration = Duration::from_secs(120);
+
+/// Chat state notifications defined in XEP-0085.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChatState {
+    Active,
+    Composing,
+    Paused,
+    Inactive,
+    Gone,
+}
+
+impl ChatState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatState::Active => "active",
+            ChatState::Composing => "composing",
+            ChatState::Paused => "paused",
+            ChatState::Inactive => "inactive",
+            ChatState::Gone => "gone",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"active" => Some(ChatState::Active),
+            b"composing" => Some(ChatState::Composing),
+            b"paused" => Some(ChatState::Paused),
+            b"inactive" => Some(ChatState::Inactive),
+            b"gone" => Some(ChatState::Gone),
+            _ => None,
+        }
+    }
+
+    /// State of a conversation `idle` after the last keystroke in it.
+    pub fn after_idle(idle: Duration) -> Self {
+        if idle >= INACTIVE_AFTER {
+            ChatState::Inactive
+        } else if idle >= PAUSED_AFTER {
+            ChatState::Paused
+        } else {
+            ChatState::Composing
+        }
+    }
+}
+
+/// Whether a contact is known to understand chat state notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatStateSupport {
+    Unknown,
+    Supported,
+    Unsupported,
+}
+
+struct ContactChatState {
+    support: ChatStateSupport,
+    last_sent: Option<ChatState>,
+    last_sent_at: Option<Instant>,
+}
+
+impl Default for ContactChatState {
+    fn default() -> Self {
+        Self {
+            support: ChatStateSupport::Unknown,
+            last_sent: None,
+            last_sent_at: None,
+        }
+    }
+}
+
+/// Decides which chat states should go out to which contact.
+///
+/// Following XEP-0085 section 5.1, `<active/>` is attached to the first message sent to a
+/// contact whose support is unknown. Standalone notifications are only sent once the contact
+/// has shown support by including a chat state in one of its messages, repeated states are
+/// suppressed and state changes are throttled to at most one per `min_interval`.
+pub struct ChatStateNotifier {
+    contacts: HashMap<String, ContactChatState>,
+    min_interval: Duration,
+}
+
+impl ChatStateNotifier {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            contacts: HashMap::new(),
+            min_interval,
+        }
+    }
+
+    pub fn support(&self, contact: &str) -> ChatStateSupport {
+        self.contacts
+            .get(contact)
+            .map(|state| state.support)
+            .unwrap_or(ChatStateSupport::Unknown)
+    }
+
+    /// Returns the chat state to attach to a message with a body, if any.
+    pub fn outgoing_message(&mut self, contact: &str, now: Instant) -> Option<ChatState> {
+        let state = self.contacts.entry(contact.to_string()).or_default();
+        if state.support == ChatStateSupport::Unsupported {
+            return None;
+        }
+
+        state.last_sent = Some(ChatState::Active);
+        state.last_sent_at = Some(now);
+        Some(ChatState::Active)
+    }
+
+    /// Returns `chat_state` if a standalone notification should be sent, `None` if it is
+    /// suppressed.
+    pub fn notify(
+        &mut self,
+        contact: &str,
+        chat_state: ChatState,
+        now: Instant,
+    ) -> Option<ChatState> {
+        let state = self.contacts.get_mut(contact)?;
+        if state.support != ChatStateSupport::Supported || state.last_sent == Some(chat_state) {
+            return None;
+        }
+
+        // Leaving the conversation is never throttled
+        let throttled = state
+            .last_sent_at
+            .is_some_and(|sent_at| now.duration_since(sent_at) < self.min_interval);
+        if throttled && chat_state != ChatState::Gone {
+            return None;
+        }
+
+        state.last_sent = Some(chat_state);
+        state.last_sent_at = Some(now);
+        Some(chat_state)
+    }
+
+    /// Updates what we know about the sender of an incoming message.
+    pub fn incoming_message(&mut self, contact: &str, message: &Message) {
+        let state = self.contacts.entry(contact.to_string()).or_default();
+        if message.chat_state.is_some() {
+            state.support = ChatStateSupport::Supported;
+        } else if message.body.is_some() && state.support == ChatStateSupport::Unknown {
+            state.support = ChatStateSupport::Unsupported;
+        }
+    }
+
+    /// Contacts that should receive `<gone/>` when the conversation ends.
+    pub fn active_contacts(&self) -> Vec<String> {
+        self.contacts
+            .iter()
+            .filter(|(_, state)| {
+                state.support == ChatStateSupport::Supported
+                    && state.last_sent != Some(ChatState::Gone)
+            })
+            .map(|(contact, _)| contact.clone())
+            .collect()
+    }
+}
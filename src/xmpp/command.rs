@@ -0,0 +1,325 @@
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    data_form::{DataForm, DATA_FORMS_NS},
+    element::Element,
+    serialize::{XmlCustomDeserialize, XmlCustomSerialize},
+};
+use crate::Error;
+
+pub const COMMANDS_NS: &str = "http://jabber.org/protocol/commands";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandAction {
+    Execute,
+    Next,
+    Prev,
+    Complete,
+    Cancel,
+}
+
+impl CommandAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandAction::Execute => "execute",
+            CommandAction::Next => "next",
+            CommandAction::Prev => "prev",
+            CommandAction::Complete => "complete",
+            CommandAction::Cancel => "cancel",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"execute" => Some(CommandAction::Execute),
+            b"next" => Some(CommandAction::Next),
+            b"prev" => Some(CommandAction::Prev),
+            b"complete" => Some(CommandAction::Complete),
+            b"cancel" => Some(CommandAction::Cancel),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandStatus {
+    Executing,
+    Completed,
+    Canceled,
+}
+
+impl CommandStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandStatus::Executing => "executing",
+            CommandStatus::Completed => "completed",
+            CommandStatus::Canceled => "canceled",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"executing" => Some(CommandStatus::Executing),
+            b"completed" => Some(CommandStatus::Completed),
+            b"canceled" => Some(CommandStatus::Canceled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandNote {
+    /// One of `info`, `warn` or `error`
+    pub note_type: String,
+    pub text: String,
+}
+
+impl CommandNote {
+    pub fn info(text: &str) -> Self {
+        Self {
+            note_type: "info".to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// `<command xmlns="http://jabber.org/protocol/commands"/>` from XEP-0050.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub node: String,
+    pub session_id: Option<String>,
+    /// Requested action, sent by the requester
+    pub action: Option<CommandAction>,
+    /// Sent by the responder
+    pub status: Option<CommandStatus>,
+    /// Actions the requester may take next, the first one is the default
+    pub actions: Vec<CommandAction>,
+    pub notes: Vec<CommandNote>,
+    pub form: Option<DataForm>,
+}
+
+impl Command {
+    /// A request to start executing `node`.
+    pub fn execute(node: &str) -> Self {
+        Self {
+            node: node.to_string(),
+            session_id: None,
+            action: Some(CommandAction::Execute),
+            status: None,
+            actions: Vec::new(),
+            notes: Vec::new(),
+            form: None,
+        }
+    }
+}
+
+impl XmlCustomSerialize for Command {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut command_start = BytesStart::new("command");
+        command_start.push_attribute(("xmlns", COMMANDS_NS));
+        command_start.push_attribute(("node", self.node.as_str()));
+        if let Some(session_id) = &self.session_id {
+            command_start.push_attribute(("sessionid", session_id.as_str()));
+        }
+        if let Some(action) = &self.action {
+            command_start.push_attribute(("action", action.name()));
+        }
+        if let Some(status) = &self.status {
+            command_start.push_attribute(("status", status.name()));
+        }
+
+        // <command>
+        writer.write_event(Event::Start(command_start)).unwrap();
+
+        if let Some((default, _)) = self.actions.split_first() {
+            let mut actions_start = BytesStart::new("actions");
+            actions_start.push_attribute(("execute", default.name()));
+            // <actions execute="...">
+            writer.write_event(Event::Start(actions_start)).unwrap();
+            for action in &self.actions {
+                // <next/>
+                writer
+                    .write_event(Event::Empty(BytesStart::new(action.name())))
+                    .unwrap();
+            }
+            // </actions>
+            writer
+                .write_event(Event::End(BytesEnd::new("actions")))
+                .unwrap();
+        }
+
+        for note in &self.notes {
+            let mut note_start = BytesStart::new("note");
+            note_start.push_attribute(("type", note.note_type.as_str()));
+            writer.write_event(Event::Start(note_start)).unwrap();
+            writer
+                .write_event(Event::Text(BytesText::new(note.text.as_str())))
+                .unwrap();
+            writer
+                .write_event(Event::End(BytesEnd::new("note")))
+                .unwrap();
+        }
+
+        if let Some(form) = &self.form {
+            writer
+                .get_mut()
+                .write_all(form.into_string().as_bytes())
+                .unwrap();
+        }
+
+        // </command>
+        writer
+            .write_event(Event::End(BytesEnd::new("command")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<Command> for Element {
+    fn from(command: Command) -> Self {
+        let mut element = Element::new("command", COMMANDS_NS).with_attr("node", &command.node);
+        if let Some(session_id) = &command.session_id {
+            element.set_attr("sessionid", session_id);
+        }
+        if let Some(action) = &command.action {
+            element.set_attr("action", action.name());
+        }
+        if let Some(status) = &command.status {
+            element.set_attr("status", status.name());
+        }
+
+        if let Some((default, _)) = command.actions.split_first() {
+            let mut actions =
+                Element::new("actions", COMMANDS_NS).with_attr("execute", default.name());
+            for action in &command.actions {
+                actions = actions.with_child(Element::new(action.name(), COMMANDS_NS));
+            }
+            element = element.with_child(actions);
+        }
+        for note in &command.notes {
+            element = element.with_child(
+                Element::new("note", COMMANDS_NS)
+                    .with_attr("type", &note.note_type)
+                    .with_text(&note.text),
+            );
+        }
+        if let Some(form) = command.form {
+            element = element.with_child(form.into());
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for Command {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut command: Option<Command> = None;
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            let Some(command) = command.as_mut() else {
+                if e.name().as_ref() != b"command" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+
+                let mut node: Option<String> = None;
+                let mut parsed = Command::execute("");
+                parsed.action = None;
+                for attr in e.attributes().flatten() {
+                    let value = attr.unescape_value()?.to_string();
+                    match attr.key.0 {
+                        b"node" => node = Some(value),
+                        b"sessionid" => parsed.session_id = Some(value),
+                        b"action" => parsed.action = CommandAction::from_name(value.as_bytes()),
+                        b"status" => parsed.status = CommandStatus::from_name(value.as_bytes()),
+                        _ => {}
+                    }
+                }
+                parsed.node = node.ok_or(Error::missing_attribute("node"))?;
+                command = Some(parsed);
+
+                if is_empty {
+                    break;
+                }
+                continue;
+            };
+
+            match e.name().as_ref() {
+                b"actions" if !is_empty => {
+                    let default = match e.try_get_attribute("execute")? {
+                        Some(attr) => CommandAction::from_name(&attr.value),
+                        None => None,
+                    };
+                    loop {
+                        match reader.read_event()? {
+                            Event::Empty(e) | Event::Start(e) => {
+                                if let Some(action) = CommandAction::from_name(e.name().as_ref()) {
+                                    command.actions.push(action);
+                                }
+                            }
+                            Event::End(e) if e.name().as_ref() == b"actions" => break,
+                            Event::Eof => return Err(Error::unclosed("actions")),
+                            _ => {}
+                        }
+                    }
+                    // Keep the default action first
+                    if let Some(default) = default {
+                        command.actions.retain(|action| *action != default);
+                        command.actions.insert(0, default);
+                    }
+                }
+                b"note" => {
+                    let note_type = match e.try_get_attribute("type")? {
+                        Some(attr) => attr.unescape_value()?.to_string(),
+                        None => "info".to_string(),
+                    };
+                    let text = if is_empty {
+                        String::new()
+                    } else {
+                        let text = reader.read_text(e.name())?;
+                        quick_xml::escape::unescape(&text)?.to_string()
+                    };
+                    command.notes.push(CommandNote { note_type, text });
+                }
+                b"x" => {
+                    if !is_empty {
+                        reader.read_to_end(e.name())?;
+                    }
+                    let is_form = e
+                        .try_get_attribute("xmlns")?
+                        .is_some_and(|xmlns| xmlns.value.as_ref() == DATA_FORMS_NS.as_bytes());
+                    if is_form {
+                        command.form = Some(DataForm::from_string(
+                            &value[child_start..reader.buffer_position()],
+                        )?);
+                    }
+                }
+                _ => {
+                    if !is_empty {
+                        reader.read_to_end(e.name())?;
+                    }
+                }
+            }
+        }
+
+        command.ok_or(Error::missing_element("command"))
+    }
+}
@@ -0,0 +1,29 @@
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+use super::serialize::XmlElement;
+
+pub const COMPONENT_NS: &str = "jabber:component:accept";
+
+/// `<handshake/>` of XEP-0114. The component sends the digest, the server answers with an
+/// empty element once it accepts it.
+#[derive(Debug, Clone, PartialEq, Eq, XmlElement)]
+#[xml(name = "handshake")]
+pub struct ComponentHandshake {
+    #[xml(text)]
+    pub digest: Option<String>,
+}
+
+/// Hex encoded SHA-1 of the stream id followed by the shared secret.
+pub fn component_digest(stream_id: &str, secret: &str) -> String {
+    Sha1::digest(format!("{}{}", stream_id, secret).as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether `digest` is the one of `stream_id` and `secret`, compared in constant time.
+pub fn verify_component_digest(stream_id: &str, secret: &str, digest: &str) -> bool {
+    let expected = component_digest(stream_id, secret);
+    expected.as_bytes().ct_eq(digest.as_bytes()).into()
+}
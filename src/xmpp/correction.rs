@@ -0,0 +1,14 @@
+pub const MESSAGE_CORRECT_NS: &str = "urn:xmpp:message-correct:0";
+pub const MESSAGE_RETRACT_NS: &str = "urn:xmpp:message-retract:1";
+
+/// `<replace id=.../>` from XEP-0308, the message body replaces the body of message `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replace {
+    pub id: String,
+}
+
+/// `<retract id=.../>` from XEP-0424, asks recipients to remove message `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retract {
+    pub id: String,
+}
@@ -0,0 +1,700 @@
+use std::{collections::HashSet, io::Cursor};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    element::Element,
+    serialize::{XmlCustomDeserialize, XmlCustomSerialize},
+};
+use crate::Error;
+
+pub const DATA_FORMS_NS: &str = "jabber:x:data";
+
+/// Var of the hidden field that names the namespace of a form, see XEP-0068.
+pub const FORM_TYPE: &str = "FORM_TYPE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormType {
+    Form,
+    Submit,
+    Cancel,
+    Result,
+}
+
+impl FormType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FormType::Form => "form",
+            FormType::Submit => "submit",
+            FormType::Cancel => "cancel",
+            FormType::Result => "result",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"form" => Some(FormType::Form),
+            b"submit" => Some(FormType::Submit),
+            b"cancel" => Some(FormType::Cancel),
+            b"result" => Some(FormType::Result),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Boolean,
+    Fixed,
+    Hidden,
+    JidMulti,
+    JidSingle,
+    ListMulti,
+    ListSingle,
+    TextMulti,
+    TextPrivate,
+    TextSingle,
+}
+
+impl FieldType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Boolean => "boolean",
+            FieldType::Fixed => "fixed",
+            FieldType::Hidden => "hidden",
+            FieldType::JidMulti => "jid-multi",
+            FieldType::JidSingle => "jid-single",
+            FieldType::ListMulti => "list-multi",
+            FieldType::ListSingle => "list-single",
+            FieldType::TextMulti => "text-multi",
+            FieldType::TextPrivate => "text-private",
+            FieldType::TextSingle => "text-single",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"boolean" => Some(FieldType::Boolean),
+            b"fixed" => Some(FieldType::Fixed),
+            b"hidden" => Some(FieldType::Hidden),
+            b"jid-multi" => Some(FieldType::JidMulti),
+            b"jid-single" => Some(FieldType::JidSingle),
+            b"list-multi" => Some(FieldType::ListMulti),
+            b"list-single" => Some(FieldType::ListSingle),
+            b"text-multi" => Some(FieldType::TextMulti),
+            b"text-private" => Some(FieldType::TextPrivate),
+            b"text-single" => Some(FieldType::TextSingle),
+            _ => None,
+        }
+    }
+
+    /// Whether a field of this type may carry more than one value.
+    pub fn is_multi(&self) -> bool {
+        matches!(
+            self,
+            FieldType::JidMulti | FieldType::ListMulti | FieldType::TextMulti | FieldType::Fixed
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldOption {
+    pub label: Option<String>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Field {
+    pub var: Option<String>,
+    /// Fields without a type are `text-single`
+    pub field_type: Option<FieldType>,
+    pub label: Option<String>,
+    pub desc: Option<String>,
+    pub required: bool,
+    pub values: Vec<String>,
+    pub options: Vec<FieldOption>,
+}
+
+impl Field {
+    pub fn new(var: &str, field_type: FieldType) -> Self {
+        Self {
+            var: Some(var.to_string()),
+            field_type: Some(field_type),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn with_value(mut self, value: &str) -> Self {
+        self.values.push(value.to_string());
+        self
+    }
+
+    pub fn with_option(mut self, label: Option<&str>, value: &str) -> Self {
+        self.options.push(FieldOption {
+            label: label.map(|label| label.to_string()),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn kind(&self) -> FieldType {
+        self.field_type.unwrap_or(FieldType::TextSingle)
+    }
+
+    /// Value of a `boolean` field, `None` if it has no valid value.
+    pub fn bool_value(&self) -> Option<bool> {
+        match self.values.first().map(|value| value.as_str()) {
+            Some("1") | Some("true") => Some(true),
+            Some("0") | Some("false") => Some(false),
+            _ => None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let name = self.var.as_deref().unwrap_or("<fixed>");
+        let kind = self.kind();
+
+        if self.var.is_none() && kind != FieldType::Fixed {
+            return Err(Error::missing_attribute("var"));
+        }
+        if !kind.is_multi() && self.values.len() > 1 {
+            return Err(Error::invalid_value("number of values of", name));
+        }
+
+        match kind {
+            FieldType::Boolean if !self.values.is_empty() && self.bool_value().is_none() => {
+                return Err(Error::invalid_value(
+                    &format!("boolean {}", name),
+                    &self.values[0],
+                ));
+            }
+            FieldType::JidSingle | FieldType::JidMulti => {
+                if let Some(jid) = self.values.iter().find(|jid| !is_valid_jid(jid)) {
+                    return Err(Error::invalid_value(&format!("jid in {}", name), jid));
+                }
+            }
+            FieldType::ListSingle | FieldType::ListMulti if !self.options.is_empty() => {
+                let outside = self.values.iter().find(|value| {
+                    !self.options.iter().any(|option| &option.value == *value)
+                });
+                if let Some(value) = outside {
+                    return Err(Error::invalid_value(&format!("option of {}", name), value));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Form defined in XEP-0004, `<x xmlns="jabber:x:data"/>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataForm {
+    pub form_type: FormType,
+    pub title: Option<String>,
+    pub instructions: Vec<String>,
+    pub fields: Vec<Field>,
+    /// Columns of a multi-item result
+    pub reported: Option<Vec<Field>>,
+    /// Rows of a multi-item result
+    pub items: Vec<Vec<Field>>,
+}
+
+impl DataForm {
+    pub fn new(form_type: FormType) -> Self {
+        Self {
+            form_type,
+            title: None,
+            instructions: Vec::new(),
+            fields: Vec::new(),
+            reported: None,
+            items: Vec::new(),
+        }
+    }
+
+    /// Creates a form whose hidden `FORM_TYPE` field is `namespace`.
+    pub fn with_form_type(form_type: FormType, namespace: &str) -> Self {
+        let mut form = Self::new(form_type);
+        form.fields
+            .push(Field::new(FORM_TYPE, FieldType::Hidden).with_value(namespace));
+        form
+    }
+
+    /// Value of the hidden `FORM_TYPE` field.
+    pub fn form_type_value(&self) -> Option<&str> {
+        self.value(FORM_TYPE)
+    }
+
+    pub fn field(&self, var: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|field| field.var.as_deref() == Some(var))
+    }
+
+    pub fn value(&self, var: &str) -> Option<&str> {
+        self.field(var)
+            .and_then(|field| field.values.first())
+            .map(|value| value.as_str())
+    }
+
+    pub fn values(&self, var: &str) -> &[String] {
+        self.field(var)
+            .map(|field| field.values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the values of field `var`, adding a field if there is none.
+    pub fn set_values(&mut self, var: &str, values: Vec<String>) {
+        match self
+            .fields
+            .iter_mut()
+            .find(|field| field.var.as_deref() == Some(var))
+        {
+            Some(field) => field.values = values,
+            None => self.fields.push(Field {
+                var: Some(var.to_string()),
+                values,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Builds an empty submission of this form, keeping `FORM_TYPE`.
+    pub fn submission(&self) -> DataForm {
+        let mut submission = match self.form_type_value() {
+            Some(namespace) => DataForm::with_form_type(FormType::Submit, namespace),
+            None => DataForm::new(FormType::Submit),
+        };
+        for field in &self.fields {
+            match &field.var {
+                Some(var) if var != FORM_TYPE && !field.values.is_empty() => {
+                    submission.set_values(var, field.values.clone())
+                }
+                _ => {}
+            }
+        }
+        submission
+    }
+
+    /// Checks the structural rules of XEP-0004.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut vars = HashSet::new();
+        for field in &self.fields {
+            field.validate()?;
+            if let Some(var) = &field.var {
+                if !vars.insert(var.as_str()) {
+                    return Err(Error::invalid_value("duplicate field", var));
+                }
+            }
+        }
+
+        if let Some(form_type) = self.field(FORM_TYPE) {
+            if form_type.kind() != FieldType::Hidden && self.form_type != FormType::Submit {
+                return Err(Error::invalid_value(
+                    "FORM_TYPE type",
+                    form_type.kind().name(),
+                ));
+            }
+        }
+
+        if !self.items.is_empty() {
+            if self.form_type != FormType::Result {
+                return Err(Error::invalid_value(
+                    "form type with items",
+                    self.form_type.name(),
+                ));
+            }
+            let Some(reported) = &self.reported else {
+                return Err(Error::missing_element("reported"));
+            };
+
+            let columns: HashSet<_> = reported.iter().filter_map(|f| f.var.as_deref()).collect();
+            for field in self.items.iter().flatten() {
+                let var = field.var.as_deref().unwrap_or_default();
+                if !columns.contains(var) {
+                    return Err(Error::invalid_value("unreported item field", var));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks `submission` against this form: required fields are filled, values fit the
+    /// declared types and options, and `FORM_TYPE` matches.
+    pub fn validate_submission(&self, submission: &DataForm) -> Result<(), Error> {
+        if submission.form_type != FormType::Submit {
+            return Err(Error::invalid_value(
+                "submission type",
+                submission.form_type.name(),
+            ));
+        }
+        if self.form_type_value() != submission.form_type_value() {
+            return Err(Error::invalid_value(
+                FORM_TYPE,
+                submission.form_type_value().unwrap_or_default(),
+            ));
+        }
+
+        for field in &self.fields {
+            let Some(var) = &field.var else { continue };
+            if var == FORM_TYPE {
+                continue;
+            }
+
+            let values = submission.values(var);
+            if field.required && values.iter().all(|value| value.is_empty()) {
+                return Err(Error::invalid_value("empty required field", var));
+            }
+
+            // Submitted fields may omit their type, use the one of the form
+            let typed = Field {
+                field_type: field.field_type,
+                values: values.to_vec(),
+                options: field.options.clone(),
+                ..field.clone()
+            };
+            typed.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loose JID check, enough to catch obvious mistakes in forms.
+fn is_valid_jid(jid: &str) -> bool {
+    let bare = jid.split('/').next().unwrap_or_default();
+    let domain = bare.rsplit('@').next().unwrap_or_default();
+    !domain.is_empty() && !jid.chars().any(|c| c.is_whitespace())
+}
+
+fn write_text_element(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, text: &str) {
+    writer
+        .write_event(Event::Start(BytesStart::new(name)))
+        .unwrap();
+    writer
+        .write_event(Event::Text(BytesText::new(text)))
+        .unwrap();
+    writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+}
+
+fn write_field(writer: &mut Writer<Cursor<Vec<u8>>>, field: &Field) {
+    let mut field_start = BytesStart::new("field");
+    if let Some(var) = &field.var {
+        field_start.push_attribute(("var", var.as_str()));
+    }
+    if let Some(field_type) = &field.field_type {
+        field_start.push_attribute(("type", field_type.name()));
+    }
+    if let Some(label) = &field.label {
+        field_start.push_attribute(("label", label.as_str()));
+    }
+
+    if field.desc.is_none() && !field.required && field.values.is_empty() && field.options.is_empty()
+    {
+        writer.write_event(Event::Empty(field_start)).unwrap();
+        return;
+    }
+
+    // <field>
+    writer.write_event(Event::Start(field_start)).unwrap();
+    if let Some(desc) = &field.desc {
+        write_text_element(writer, "desc", desc);
+    }
+    if field.required {
+        writer
+            .write_event(Event::Empty(BytesStart::new("required")))
+            .unwrap();
+    }
+    for value in &field.values {
+        write_text_element(writer, "value", value);
+    }
+    for option in &field.options {
+        let mut option_start = BytesStart::new("option");
+        if let Some(label) = &option.label {
+            option_start.push_attribute(("label", label.as_str()));
+        }
+        writer.write_event(Event::Start(option_start)).unwrap();
+        write_text_element(writer, "value", &option.value);
+        writer
+            .write_event(Event::End(BytesEnd::new("option")))
+            .unwrap();
+    }
+    // </field>
+    writer
+        .write_event(Event::End(BytesEnd::new("field")))
+        .unwrap();
+}
+
+fn write_fields(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, fields: &[Field]) {
+    writer
+        .write_event(Event::Start(BytesStart::new(name)))
+        .unwrap();
+    for field in fields {
+        write_field(writer, field);
+    }
+    writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+}
+
+impl XmlCustomSerialize for DataForm {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut x_start = BytesStart::new("x");
+        x_start.push_attribute(("xmlns", DATA_FORMS_NS));
+        x_start.push_attribute(("type", self.form_type.name()));
+        // <x xmlns="jabber:x:data" type="...">
+        writer.write_event(Event::Start(x_start)).unwrap();
+
+        if let Some(title) = &self.title {
+            write_text_element(&mut writer, "title", title);
+        }
+        for instructions in &self.instructions {
+            write_text_element(&mut writer, "instructions", instructions);
+        }
+        if let Some(reported) = &self.reported {
+            write_fields(&mut writer, "reported", reported);
+        }
+        for item in &self.items {
+            write_fields(&mut writer, "item", item);
+        }
+        for field in &self.fields {
+            write_field(&mut writer, field);
+        }
+
+        // </x>
+        writer.write_event(Event::End(BytesEnd::new("x"))).unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl From<Field> for Element {
+    fn from(field: Field) -> Self {
+        let mut element = Element::new("field", DATA_FORMS_NS);
+        if let Some(var) = &field.var {
+            element.set_attr("var", var);
+        }
+        if let Some(field_type) = &field.field_type {
+            element.set_attr("type", field_type.name());
+        }
+        if let Some(label) = &field.label {
+            element.set_attr("label", label);
+        }
+
+        if let Some(desc) = &field.desc {
+            element = element.with_child(Element::new("desc", DATA_FORMS_NS).with_text(desc));
+        }
+        if field.required {
+            element = element.with_child(Element::new("required", DATA_FORMS_NS));
+        }
+        for value in &field.values {
+            element = element.with_child(Element::new("value", DATA_FORMS_NS).with_text(value));
+        }
+        for option in &field.options {
+            let mut option_element = Element::new("option", DATA_FORMS_NS);
+            if let Some(label) = &option.label {
+                option_element.set_attr("label", label);
+            }
+            element = element.with_child(
+                option_element
+                    .with_child(Element::new("value", DATA_FORMS_NS).with_text(&option.value)),
+            );
+        }
+        element
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<DataForm> for Element {
+    fn from(form: DataForm) -> Self {
+        let mut element = Element::new("x", DATA_FORMS_NS).with_attr("type", form.form_type.name());
+        if let Some(title) = &form.title {
+            element = element.with_child(Element::new("title", DATA_FORMS_NS).with_text(title));
+        }
+        for instructions in &form.instructions {
+            element = element
+                .with_child(Element::new("instructions", DATA_FORMS_NS).with_text(instructions));
+        }
+        let rows = form
+            .reported
+            .into_iter()
+            .map(|fields| ("reported", fields))
+            .chain(form.items.into_iter().map(|fields| ("item", fields)));
+        for (name, fields) in rows {
+            let mut row = Element::new(name, DATA_FORMS_NS);
+            for field in fields {
+                row = row.with_child(field.into());
+            }
+            element = element.with_child(row);
+        }
+        for field in form.fields {
+            element = element.with_child(field.into());
+        }
+        element
+    }
+}
+
+fn read_text(reader: &mut Reader<&[u8]>, start: &BytesStart) -> Result<String, Error> {
+    let text = reader.read_text(start.name())?;
+    Ok(quick_xml::escape::unescape(&text)?.to_string())
+}
+
+fn attribute(start: &BytesStart, name: &str) -> Result<Option<String>, Error> {
+    Ok(match start.try_get_attribute(name)? {
+        Some(attr) => Some(attr.unescape_value()?.to_string()),
+        None => None,
+    })
+}
+
+fn read_field(
+    reader: &mut Reader<&[u8]>,
+    start: &BytesStart,
+    is_empty: bool,
+) -> Result<Field, Error> {
+    let mut field = Field {
+        var: attribute(start, "var")?,
+        label: attribute(start, "label")?,
+        ..Default::default()
+    };
+    if let Some(field_type) = attribute(start, "type")? {
+        field.field_type = Some(
+            FieldType::from_name(field_type.as_bytes())
+                .ok_or_else(|| Error::invalid_value("field type", &field_type))?,
+        );
+    }
+    if is_empty {
+        return Ok(field);
+    }
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"desc" => field.desc = Some(read_text(reader, &e)?),
+                b"value" => field.values.push(read_text(reader, &e)?),
+                b"required" => {
+                    field.required = true;
+                    reader.read_to_end(e.name())?;
+                }
+                b"option" => {
+                    let label = attribute(&e, "label")?;
+                    let mut value = None;
+                    loop {
+                        match reader.read_event()? {
+                            Event::Start(e) if e.name().as_ref() == b"value" => {
+                                value = Some(read_text(reader, &e)?);
+                            }
+                            Event::End(e) if e.name().as_ref() == b"option" => break,
+                            Event::Eof => return Err(Error::unclosed("option")),
+                            _ => {}
+                        }
+                    }
+                    field.options.push(FieldOption {
+                        label,
+                        value: value.ok_or(Error::missing_element("value"))?,
+                    });
+                }
+                _ => {
+                    reader.read_to_end(e.name())?;
+                }
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"required" => field.required = true,
+                b"value" => field.values.push(String::new()),
+                _ => {}
+            },
+            Event::End(e) if e.name().as_ref() == b"field" => break,
+            Event::Eof => return Err(Error::unclosed("field")),
+            _ => {}
+        }
+    }
+
+    Ok(field)
+}
+
+fn read_fields(reader: &mut Reader<&[u8]>, end: &[u8]) -> Result<Vec<Field>, Error> {
+    let mut fields = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"field" => {
+                fields.push(read_field(reader, &e, false)?)
+            }
+            Event::Empty(e) if e.name().as_ref() == b"field" => {
+                fields.push(read_field(reader, &e, true)?)
+            }
+            Event::End(e) if e.name().as_ref() == end => break,
+            Event::Eof => return Err(Error::unclosed(std::str::from_utf8(end)?)),
+            _ => {}
+        }
+    }
+    Ok(fields)
+}
+
+impl XmlCustomDeserialize for DataForm {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut form: Option<DataForm> = None;
+
+        loop {
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::End(e) if e.name().as_ref() == b"x" => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            let Some(form) = form.as_mut() else {
+                if e.name().as_ref() != b"x" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                let xmlns = attribute(&e, "xmlns")?.unwrap_or_default();
+                if xmlns != DATA_FORMS_NS {
+                    return Err(Error::invalid_value("xmlns", &xmlns));
+                }
+                let form_type = attribute(&e, "type")?.ok_or(Error::missing_attribute("type"))?;
+                let form_type = FormType::from_name(form_type.as_bytes())
+                    .ok_or_else(|| Error::invalid_value("form type", &form_type))?;
+                form = Some(DataForm::new(form_type));
+                if is_empty {
+                    break;
+                }
+                continue;
+            };
+
+            match (e.name().as_ref(), is_empty) {
+                (b"field", _) => form.fields.push(read_field(&mut reader, &e, is_empty)?),
+                (b"title", false) => form.title = Some(read_text(&mut reader, &e)?),
+                (b"instructions", false) => form.instructions.push(read_text(&mut reader, &e)?),
+                (b"reported", false) => {
+                    form.reported = Some(read_fields(&mut reader, b"reported")?)
+                }
+                (b"reported", true) => form.reported = Some(Vec::new()),
+                (b"item", false) => form.items.push(read_fields(&mut reader, b"item")?),
+                (b"item", true) => form.items.push(Vec::new()),
+                (_, false) => {
+                    reader.read_to_end(e.name())?;
+                }
+                _ => {}
+            }
+        }
+
+        form.ok_or(Error::missing_element("x"))
+    }
+}
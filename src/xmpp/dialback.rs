@@ -0,0 +1,210 @@
+use std::io::Cursor;
+
+use hmac::{Hmac, Mac};
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    NsReader, Writer,
+};
+use sha2::{Digest, Sha256};
+
+use super::serialize::{
+    element_namespace, invalid_namespace, XmlCustomDeserialize, XmlCustomSerialize,
+};
+use crate::Error;
+
+pub const DIALBACK_NS: &str = "jabber:server:dialback";
+/// Stream feature announcing dialback support, see XEP-0220 section 2.1
+pub const DIALBACK_FEATURE_NS: &str = "urn:xmpp:features:dialback";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialbackType {
+    Valid,
+    Invalid,
+}
+
+impl DialbackType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DialbackType::Valid => "valid",
+            DialbackType::Invalid => "invalid",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"valid" => Some(DialbackType::Valid),
+            b"invalid" => Some(DialbackType::Invalid),
+            _ => None,
+        }
+    }
+}
+
+/// `<db:result/>` sent by the originating server to ask for, and by the receiving server to
+/// report, the authorization of `from` on this stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialbackResult {
+    pub from: String,
+    pub to: String,
+    /// Dialback key, only set on the request
+    pub key: Option<String>,
+    /// Outcome, only set on the response
+    pub result_type: Option<DialbackType>,
+}
+
+/// `<db:verify/>` sent by the receiving server to ask the authoritative server whether it
+/// generated a key for the stream `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialbackVerify {
+    pub from: String,
+    pub to: String,
+    pub id: String,
+    /// Dialback key, only set on the request
+    pub key: Option<String>,
+    /// Outcome, only set on the response
+    pub verify_type: Option<DialbackType>,
+}
+
+/// Computes the dialback key recommended by XEP-0185.
+pub fn dialback_key(secret: &str, receiving: &str, originating: &str, stream_id: &str) -> String {
+    let hashed_secret = hex(&Sha256::digest(secret.as_bytes()));
+    let mut mac = Hmac::<Sha256>::new_from_slice(hashed_secret.as_bytes())
+        .expect("hmac accepts keys of any size");
+    mac.update(format!("{} {} {}", receiving, originating, stream_id).as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn write_dialback(
+    name: &str,
+    attributes: &[(&str, &str)],
+    key: Option<&str>,
+    dialback_type: Option<DialbackType>,
+) -> String {
+    let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+    let mut start = BytesStart::new(name);
+    start.push_attribute(("xmlns:db", DIALBACK_NS));
+    for attribute in attributes {
+        start.push_attribute(*attribute);
+    }
+    if let Some(dialback_type) = dialback_type {
+        start.push_attribute(("type", dialback_type.name()));
+    }
+
+    match key {
+        Some(key) => {
+            // <db:result ...>key</db:result>
+            writer.write_event(Event::Start(start)).unwrap();
+            writer
+                .write_event(Event::Text(BytesText::new(key)))
+                .unwrap();
+            writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+        }
+        None => writer.write_event(Event::Empty(start)).unwrap(),
+    }
+
+    std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+        .unwrap()
+        .to_string()
+}
+
+/// Attributes, key and type of a dialback element called `name`.
+type ParsedDialback = (Vec<(Vec<u8>, String)>, Option<String>, Option<DialbackType>);
+
+/// Reads the dialback element `db:<name>`, whatever prefix the peer bound the namespace to.
+fn read_dialback(value: &str, name: &str) -> Result<ParsedDialback, Error> {
+    let mut reader = NsReader::from_str(value);
+
+    loop {
+        let (e, is_empty) = match reader.read_event()? {
+            Event::Eof => return Err(Error::missing_element(&format!("db:{}", name))),
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            _ => continue,
+        };
+        if e.local_name().as_ref() != name.as_bytes() {
+            return Err(Error::unexpected_element(e.name()));
+        }
+        let ns = element_namespace(&reader, &e)?;
+        if ns != DIALBACK_NS {
+            return Err(invalid_namespace(&format!("db:{}", name), &ns, DIALBACK_NS).into());
+        }
+
+        let mut attributes = Vec::new();
+        let mut dialback_type = None;
+        for attr in e.attributes().flatten() {
+            let value = attr.unescape_value()?.to_string();
+            match attr.key.0 {
+                b"type" => dialback_type = DialbackType::from_name(value.as_bytes()),
+                key => attributes.push((key.to_vec(), value)),
+            }
+        }
+
+        let key = if is_empty {
+            None
+        } else {
+            let text = reader.read_text(e.name())?;
+            Some(quick_xml::escape::unescape(text.trim())?.to_string()).filter(|k| !k.is_empty())
+        };
+
+        return Ok((attributes, key, dialback_type));
+    }
+}
+
+fn attribute(attributes: &[(Vec<u8>, String)], name: &str) -> Result<String, Error> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name.as_bytes())
+        .map(|(_, value)| value.clone())
+        .ok_or(Error::missing_attribute(name))
+}
+
+impl XmlCustomSerialize for DialbackResult {
+    fn into_string(&self) -> String {
+        write_dialback(
+            "db:result",
+            &[("from", &self.from), ("to", &self.to)],
+            self.key.as_deref(),
+            self.result_type,
+        )
+    }
+}
+
+impl XmlCustomDeserialize for DialbackResult {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let (attributes, key, result_type) = read_dialback(value, "result")?;
+        Ok(DialbackResult {
+            from: attribute(&attributes, "from")?,
+            to: attribute(&attributes, "to")?,
+            key,
+            result_type,
+        })
+    }
+}
+
+impl XmlCustomSerialize for DialbackVerify {
+    fn into_string(&self) -> String {
+        write_dialback(
+            "db:verify",
+            &[("from", &self.from), ("to", &self.to), ("id", &self.id)],
+            self.key.as_deref(),
+            self.verify_type,
+        )
+    }
+}
+
+impl XmlCustomDeserialize for DialbackVerify {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let (attributes, key, verify_type) = read_dialback(value, "verify")?;
+        Ok(DialbackVerify {
+            from: attribute(&attributes, "from")?,
+            to: attribute(&attributes, "to")?,
+            id: attribute(&attributes, "id")?,
+            key,
+            verify_type,
+        })
+    }
+}
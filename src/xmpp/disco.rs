@@ -0,0 +1,45 @@
+use super::{element::Element, serialize::XmlElement};
+
+pub const DISCO_ITEMS_NS: &str = "http://jabber.org/protocol/disco#items";
+
+/// Items of an entity or of one of its nodes (XEP-0030),
+/// `<query xmlns="http://jabber.org/protocol/disco#items"/>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, XmlElement)]
+#[xml(name = "query", xmlns = DISCO_ITEMS_NS)]
+pub struct DiscoItems {
+    #[xml(attribute)]
+    pub node: Option<String>,
+    #[xml(children)]
+    pub items: Vec<DiscoItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, XmlElement)]
+#[xml(name = "item")]
+pub struct DiscoItem {
+    #[xml(attribute)]
+    pub jid: String,
+    #[xml(attribute)]
+    pub node: Option<String>,
+    #[xml(attribute)]
+    pub name: Option<String>,
+}
+
+impl From<DiscoItems> for Element {
+    fn from(query: DiscoItems) -> Self {
+        let mut element = Element::new("query", DISCO_ITEMS_NS);
+        if let Some(node) = &query.node {
+            element.set_attr("node", node);
+        }
+        for item in query.items {
+            let mut child = Element::new("item", DISCO_ITEMS_NS).with_attr("jid", &item.jid);
+            if let Some(node) = &item.node {
+                child.set_attr("node", node);
+            }
+            if let Some(name) = &item.name {
+                child.set_attr("name", name);
+            }
+            element = element.with_child(child);
+        }
+        element
+    }
+}
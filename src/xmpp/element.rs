@@ -0,0 +1,342 @@
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    name::ResolveResult,
+    NsReader, Writer,
+};
+
+use super::{
+    iq::Iq,
+    serialize::{resolve_namespace, XmlCustomDeserialize, XmlCustomSerialize},
+    stanza::{Message, Presence, Stanza},
+    stream_error::StreamError,
+};
+use crate::Error;
+
+/// Elements nested deeper are refused, writing and dropping them recurse once per level.
+pub const MAX_ELEMENT_DEPTH: usize = 64;
+
+/// Any XML element, with its namespace resolved. Extensions we don't model are kept as
+/// elements so that they can be forwarded untouched.
+///
+/// Children of stanzas that don't declare a namespace inherit the one of the stream, their
+/// `ns` is empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    /// Local name, without prefix
+    pub name: String,
+    pub ns: String,
+    /// Attributes as written, prefixes included, without the default namespace declaration
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn new(name: &str, ns: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ns: ns.to_string(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_attr(mut self, name: &str, value: &str) -> Self {
+        self.set_attr(name, value);
+        self
+    }
+
+    pub fn with_child(mut self, child: Element) -> Self {
+        self.children.push(Node::Element(child));
+        self
+    }
+
+    /// Adds `text` as a child, unless it is empty since parsed elements never hold empty text.
+    pub fn with_text(mut self, text: &str) -> Self {
+        if !text.is_empty() {
+            self.push_text(text);
+        }
+        self
+    }
+
+    pub fn is(&self, name: &str, ns: &str) -> bool {
+        self.name == name && self.ns == ns
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_attr(&mut self, name: &str, value: &str) {
+        match self.attrs.iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.attrs.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Text directly inside this element, children excluded.
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|node| match node {
+                Node::Text(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    /// Child elements, text left out.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn get_child(&self, name: &str, ns: &str) -> Option<&Element> {
+        self.elements().find(|child| child.is(name, ns))
+    }
+
+    /// First element matching `path`, see [`Element::find_all`].
+    pub fn find(&self, path: &str) -> Option<&Element> {
+        self.find_all(path).into_iter().next()
+    }
+
+    /// Descendants matching `path`, steps separated by `/` from the children of this element.
+    ///
+    /// A step is a local name in any namespace, `{ns}name` or `*`, such as
+    /// `{http://jabber.org/protocol/disco#items}query/item`.
+    pub fn find_all(&self, path: &str) -> Vec<&Element> {
+        let mut current = vec![self];
+        for step in path_steps(path) {
+            let (ns, name) = match step.strip_prefix('{').and_then(|step| step.split_once('}')) {
+                Some((ns, name)) => (Some(ns), name),
+                None => (None, step),
+            };
+            current = current
+                .into_iter()
+                .flat_map(|element| element.elements())
+                .filter(|child| name == "*" || child.name == name)
+                .filter(|child| ns.is_none_or(|ns| child.ns == ns))
+                .collect();
+        }
+        current
+    }
+
+    /// Parses `value`, a child of an element that declares the prefixed namespaces
+    /// `declarations`. The declarations its attributes rely on are copied onto it, so that it
+    /// serializes on its own.
+    pub fn from_string_in_scope(
+        value: &str,
+        declarations: &[(String, String)],
+    ) -> Result<Self, Error> {
+        if declarations.is_empty() {
+            return Element::from_string(value);
+        }
+
+        let mut scope = String::from("<scope");
+        for (prefix, ns) in declarations {
+            scope.push_str(&format!(
+                " xmlns:{}=\"{}\"",
+                prefix,
+                quick_xml::escape::escape(ns)
+            ));
+        }
+        scope.push('>');
+        scope.push_str(value);
+        scope.push_str("</scope>");
+
+        let mut element = Element::from_string(&scope)?
+            .children
+            .into_iter()
+            .find_map(|node| match node {
+                Node::Element(element) => Some(element),
+                Node::Text(_) => None,
+            })
+            .ok_or(Error::missing_element("*"))?;
+
+        let mut prefixes = Vec::new();
+        element.attribute_prefixes(&mut prefixes);
+        let missing: Vec<_> = declarations
+            .iter()
+            .filter(|(prefix, _)| prefixes.contains(&prefix.as_str()))
+            .map(|(prefix, ns)| (format!("xmlns:{}", prefix), ns.clone()))
+            .filter(|(key, _)| element.attr(key).is_none())
+            .collect();
+        element.attrs.extend(missing);
+        Ok(element)
+    }
+
+    /// Prefixes of the attributes of this element and its descendants.
+    fn attribute_prefixes<'a>(&'a self, prefixes: &mut Vec<&'a str>) {
+        for (key, _) in &self.attrs {
+            match key.split_once(':') {
+                Some(("xml" | "xmlns", _)) | None => {}
+                Some((prefix, _)) => prefixes.push(prefix),
+            }
+        }
+        for child in self.elements() {
+            child.attribute_prefixes(prefixes);
+        }
+    }
+
+    fn write_to<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        parent_ns: Option<&str>,
+    ) -> quick_xml::Result<()> {
+        let mut start = BytesStart::new(self.name.as_str());
+        let declare_ns = match parent_ns {
+            Some(parent_ns) => parent_ns != self.ns,
+            None => !self.ns.is_empty(),
+        };
+        if declare_ns {
+            start.push_attribute(("xmlns", self.ns.as_str()));
+        }
+        for (key, value) in &self.attrs {
+            start.push_attribute((key.as_str(), value.as_str()));
+        }
+
+        if self.children.is_empty() {
+            return writer.write_event(Event::Empty(start));
+        }
+        writer.write_event(Event::Start(start))?;
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.write_to(writer, Some(&self.ns))?,
+                Node::Text(text) => writer.write_event(Event::Text(BytesText::new(text)))?,
+            }
+        }
+        writer.write_event(Event::End(BytesEnd::new(self.name.as_str())))
+    }
+
+    fn from_start(ns: ResolveResult, start: &BytesStart) -> Result<Self, Error> {
+        let ns = resolve_namespace(ns)?;
+        let mut element = Element::new(std::str::from_utf8(start.local_name().as_ref())?, &ns);
+
+        for attr in start.attributes() {
+            let attr = attr?;
+            if attr.key.as_ref() == b"xmlns" {
+                continue;
+            }
+            element.attrs.push((
+                std::str::from_utf8(attr.key.as_ref())?.to_string(),
+                attr.unescape_value()?.to_string(),
+            ));
+        }
+        Ok(element)
+    }
+
+    fn push_text(&mut self, text: &str) {
+        match self.children.last_mut() {
+            Some(Node::Text(last)) => last.push_str(text),
+            _ => self.children.push(Node::Text(text.to_string())),
+        }
+    }
+}
+
+/// Splits a path on `/`, except inside `{namespace}`.
+fn path_steps(path: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in path.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '/' if depth == 0 => {
+                steps.push(&path[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    steps.push(&path[start..]);
+    steps.into_iter().filter(|step| !step.is_empty()).collect()
+}
+
+impl XmlCustomSerialize for Element {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+        self.write_to(&mut writer, None).unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl XmlCustomDeserialize for Element {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = NsReader::from_str(value);
+        // Elements being read, the root first
+        let mut open: Vec<Element> = Vec::new();
+
+        loop {
+            let (ns, event) = reader.read_resolved_event()?;
+            let finished = match event {
+                Event::Start(e) => {
+                    if open.len() == MAX_ELEMENT_DEPTH {
+                        return Err(StreamError {
+                            condition: "policy-violation".to_string(),
+                            text: Some(format!(
+                                "elements nested deeper than {}",
+                                MAX_ELEMENT_DEPTH
+                            )),
+                        }
+                        .into());
+                    }
+                    open.push(Element::from_start(ns, &e)?);
+                    continue;
+                }
+                Event::Empty(e) => Element::from_start(ns, &e)?,
+                Event::End(_) => open.pop().ok_or(Error::unexpected_element("/"))?,
+                Event::Text(e) => {
+                    if let Some(parent) = open.last_mut() {
+                        parent.push_text(&e.unescape()?);
+                    }
+                    continue;
+                }
+                Event::CData(e) => {
+                    if let Some(parent) = open.last_mut() {
+                        parent.push_text(std::str::from_utf8(&e)?);
+                    }
+                    continue;
+                }
+                Event::Eof => return Err(Error::missing_element("*")),
+                _ => continue,
+            };
+
+            match open.last_mut() {
+                Some(parent) => parent.children.push(Node::Element(finished)),
+                None => return Ok(finished),
+            }
+        }
+    }
+}
+
+/// Conversions from elements to typed stanzas, which go through their serialization.
+macro_rules! element_conversions {
+    ($($stanza:ident),*) => {
+        $(
+            impl TryFrom<Element> for $stanza {
+                type Error = Error;
+
+                fn try_from(element: Element) -> Result<Self, Error> {
+                    $stanza::from_string(&element.into_string())
+                }
+            }
+        )*
+    };
+}
+
+element_conversions!(Message, Presence, Iq, Stanza);
@@ -0,0 +1,410 @@
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    command::{Command, COMMANDS_NS},
+    disco::{DiscoItems, DISCO_ITEMS_NS},
+    element::Element,
+    mam::{MamFin, MamQuery, MAM_NS},
+    register::{RegisterQuery, REGISTER_NS},
+    serialize::{namespace_declarations, XmlCustomDeserialize, XmlCustomSerialize},
+};
+use crate::Error;
+
+pub const STANZAS_NS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IqType {
+    Get,
+    Set,
+    Result,
+    Error,
+}
+
+impl IqType {
+    /// Whether the IQ is a request, which gets exactly one response.
+    pub fn is_request(&self) -> bool {
+        matches!(self, IqType::Get | IqType::Set)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IqType::Get => "get",
+            IqType::Set => "set",
+            IqType::Result => "result",
+            IqType::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"get" => Some(IqType::Get),
+            b"set" => Some(IqType::Set),
+            b"result" => Some(IqType::Result),
+            b"error" => Some(IqType::Error),
+            _ => None,
+        }
+    }
+}
+
+/// `<error type=...>` child of a stanza, see RFC 6120 section 8.3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StanzaError {
+    /// One of `auth`, `cancel`, `continue`, `modify` or `wait`
+    pub error_type: String,
+    /// Defined condition such as `conflict` or `not-allowed`
+    pub condition: String,
+    pub text: Option<String>,
+}
+
+impl StanzaError {
+    pub fn new(error_type: &str, condition: &str) -> Self {
+        Self {
+            error_type: error_type.to_string(),
+            condition: condition.to_string(),
+            text: None,
+        }
+    }
+}
+
+impl XmlCustomSerialize for StanzaError {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut error_start = BytesStart::new("error");
+        error_start.push_attribute(("type", self.error_type.as_str()));
+        // <error type="...">
+        writer.write_event(Event::Start(error_start)).unwrap();
+
+        // <condition xmlns="urn:ietf:params:xml:ns:xmpp-stanzas"/>
+        let mut condition_start = BytesStart::new(self.condition.as_str());
+        condition_start.push_attribute(("xmlns", STANZAS_NS));
+        writer.write_event(Event::Empty(condition_start)).unwrap();
+
+        if let Some(text) = &self.text {
+            let mut text_start = BytesStart::new("text");
+            text_start.push_attribute(("xmlns", STANZAS_NS));
+            writer.write_event(Event::Start(text_start)).unwrap();
+            writer
+                .write_event(Event::Text(BytesText::new(text.as_str())))
+                .unwrap();
+            writer
+                .write_event(Event::End(BytesEnd::new("text")))
+                .unwrap();
+        }
+
+        // </error>
+        writer
+            .write_event(Event::End(BytesEnd::new("error")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl From<StanzaError> for Element {
+    fn from(error: StanzaError) -> Self {
+        let mut element = Element::new("error", "")
+            .with_attr("type", &error.error_type)
+            .with_child(Element::new(&error.condition, STANZAS_NS));
+        if let Some(text) = &error.text {
+            element = element.with_child(Element::new("text", STANZAS_NS).with_text(text));
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for StanzaError {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut error_type: Option<String> = None;
+        let mut condition: Option<String> = None;
+        let mut text: Option<String> = None;
+
+        loop {
+            match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"error" => {
+                    error_type = e
+                        .try_get_attribute("type")?
+                        .map(|attr| attr.unescape_value().map(|v| v.to_string()))
+                        .transpose()?;
+                }
+                Event::Start(e) if e.name().as_ref() == b"text" => {
+                    let value = reader.read_text(e.name())?;
+                    text = Some(quick_xml::escape::unescape(&value)?.to_string());
+                }
+                Event::Start(e) | Event::Empty(e) => {
+                    condition = Some(std::str::from_utf8(e.name().as_ref())?.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(StanzaError {
+            error_type: error_type.ok_or(Error::missing_attribute("type"))?,
+            condition: condition.ok_or(Error::missing_element("condition"))?,
+            text,
+        })
+    }
+}
+
+/// Known `<iq/>` payloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IqPayload {
+    Register(RegisterQuery),
+    Command(Command),
+    MamQuery(MamQuery),
+    MamFin(MamFin),
+    DiscoItems(DiscoItems),
+    /// Payload we don't know, kept so that it can be routed
+    Other(Element),
+}
+
+impl IqPayload {
+    /// Parses a payload element, as `Other` if we don't know the element.
+    fn from_element(
+        name: &[u8],
+        xmlns: &str,
+        value: &str,
+        declarations: &[(String, String)],
+    ) -> Result<Option<Self>, Error> {
+        match (name, xmlns) {
+            (b"query", REGISTER_NS) => Ok(Some(IqPayload::Register(RegisterQuery::from_string(
+                value,
+            )?))),
+            (b"command", COMMANDS_NS) => Ok(Some(IqPayload::Command(Command::from_string(value)?))),
+            (b"query", MAM_NS) => Ok(Some(IqPayload::MamQuery(MamQuery::from_string(value)?))),
+            (b"fin", MAM_NS) => Ok(Some(IqPayload::MamFin(MamFin::from_string(value)?))),
+            (b"query", DISCO_ITEMS_NS) => {
+                Ok(Some(IqPayload::DiscoItems(DiscoItems::from_string(value)?)))
+            }
+            _ => Ok(Some(IqPayload::Other(Element::from_string_in_scope(
+                value,
+                declarations,
+            )?))),
+        }
+    }
+}
+
+impl XmlCustomSerialize for IqPayload {
+    fn into_string(&self) -> String {
+        match self {
+            IqPayload::Register(query) => query.into_string(),
+            IqPayload::Command(command) => command.into_string(),
+            IqPayload::MamQuery(query) => query.into_string(),
+            IqPayload::MamFin(fin) => fin.into_string(),
+            IqPayload::DiscoItems(query) => query.into_string(),
+            IqPayload::Other(element) => element.into_string(),
+        }
+    }
+}
+
+impl From<IqPayload> for Element {
+    fn from(payload: IqPayload) -> Self {
+        match payload {
+            IqPayload::Register(query) => query.into(),
+            IqPayload::Command(command) => command.into(),
+            IqPayload::MamQuery(query) => query.into(),
+            IqPayload::MamFin(fin) => fin.into(),
+            IqPayload::DiscoItems(query) => query.into(),
+            IqPayload::Other(element) => element,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iq {
+    pub id: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub iq_type: IqType,
+    pub payload: Option<IqPayload>,
+    pub error: Option<StanzaError>,
+}
+
+impl Iq {
+    pub fn new(iq_type: IqType, id: String, payload: Option<IqPayload>) -> Self {
+        Self {
+            id,
+            from: None,
+            to: None,
+            iq_type,
+            payload,
+            error: None,
+        }
+    }
+
+    /// Builds the `result` response to this request.
+    pub fn result(&self, payload: Option<IqPayload>) -> Iq {
+        Iq {
+            id: self.id.clone(),
+            from: self.to.clone(),
+            to: self.from.clone(),
+            iq_type: IqType::Result,
+            payload,
+            error: None,
+        }
+    }
+
+    /// Builds the `error` response to this request.
+    pub fn error(&self, error: StanzaError) -> Iq {
+        Iq {
+            id: self.id.clone(),
+            from: self.to.clone(),
+            to: self.from.clone(),
+            iq_type: IqType::Error,
+            payload: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl XmlCustomSerialize for Iq {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut iq_start = BytesStart::new("iq");
+        iq_start.push_attribute(("id", self.id.as_str()));
+        if let Some(from) = &self.from {
+            iq_start.push_attribute(("from", from.as_str()));
+        }
+        if let Some(to) = &self.to {
+            iq_start.push_attribute(("to", to.as_str()));
+        }
+        iq_start.push_attribute(("type", self.iq_type.name()));
+
+        if self.payload.is_none() && self.error.is_none() {
+            writer.write_event(Event::Empty(iq_start)).unwrap();
+            return std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+                .unwrap()
+                .to_string();
+        }
+
+        // <iq>
+        writer.write_event(Event::Start(iq_start)).unwrap();
+
+        // Children serialize themselves
+        if let Some(payload) = &self.payload {
+            writer
+                .get_mut()
+                .write_all(payload.into_string().as_bytes())
+                .unwrap();
+        }
+        if let Some(error) = &self.error {
+            writer
+                .get_mut()
+                .write_all(error.into_string().as_bytes())
+                .unwrap();
+        }
+
+        // </iq>
+        writer.write_event(Event::End(BytesEnd::new("iq"))).unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<Iq> for Element {
+    fn from(iq: Iq) -> Self {
+        let mut element = Element::new("iq", "").with_attr("id", &iq.id);
+        for (name, value) in [("from", iq.from), ("to", iq.to)] {
+            if let Some(value) = value {
+                element.attrs.push((name.to_string(), value));
+            }
+        }
+        element.set_attr("type", iq.iq_type.name());
+
+        if let Some(payload) = iq.payload {
+            element = element.with_child(payload.into());
+        }
+        if let Some(error) = iq.error {
+            element = element.with_child(error.into());
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for Iq {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut id: Option<String> = None;
+        let mut from: Option<String> = None;
+        let mut to: Option<String> = None;
+        let mut iq_type: Option<IqType> = None;
+        let mut payload: Option<IqPayload> = None;
+        let mut error: Option<StanzaError> = None;
+        // Prefixes declared on <iq/>, which its payload may use
+        let mut declarations = Vec::new();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"iq" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                declarations = namespace_declarations(&e)?;
+
+                for attr in e.attributes().flatten() {
+                    let value = attr.unescape_value()?.to_string();
+                    match attr.key.0 {
+                        b"id" => id = Some(value),
+                        b"from" => from = Some(value),
+                        b"to" => to = Some(value),
+                        b"type" => iq_type = IqType::from_name(value.as_bytes()),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            // Hand the whole child element over to its own parser
+            if !is_empty {
+                reader.read_to_end(e.name())?;
+            }
+            let child = &value[child_start..reader.buffer_position()];
+
+            if e.name().as_ref() == b"error" {
+                error = Some(StanzaError::from_string(child)?);
+                continue;
+            }
+
+            let xmlns = match e.try_get_attribute("xmlns")? {
+                Some(xmlns) => xmlns.unescape_value()?.to_string(),
+                None => continue,
+            };
+            payload = IqPayload::from_element(e.name().as_ref(), &xmlns, child, &declarations)?;
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("iq"));
+        }
+
+        Ok(Iq {
+            id: id.ok_or(Error::missing_attribute("id"))?,
+            from,
+            to,
+            iq_type: iq_type.ok_or(Error::missing_attribute("type"))?,
+            payload,
+            error,
+        })
+    }
+}
@@ -0,0 +1,568 @@
+use std::{
+    io::{Cursor, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    data_form::{DataForm, FormType, DATA_FORMS_NS},
+    element::{Element, Node},
+    serialize::{XmlCustomDeserialize, XmlCustomSerialize},
+    stanza::Message,
+};
+use crate::Error;
+
+pub const MAM_NS: &str = "urn:xmpp:mam:2";
+pub const RSM_NS: &str = "http://jabber.org/protocol/rsm";
+pub const FORWARD_NS: &str = "urn:xmpp:forward:0";
+pub const DELAY_NS: &str = "urn:xmpp:delay";
+pub const STANZA_ID_NS: &str = "urn:xmpp:sid:0";
+
+/// Page of a result set (XEP-0059), `<set xmlns="http://jabber.org/protocol/rsm"/>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResultSet {
+    pub max: Option<usize>,
+    /// Asks for the page right after this id
+    pub after: Option<String>,
+    /// Asks for the page right before this id, or the last page when empty
+    pub before: Option<String>,
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub count: Option<usize>,
+}
+
+impl XmlCustomSerialize for ResultSet {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut set_start = BytesStart::new("set");
+        set_start.push_attribute(("xmlns", RSM_NS));
+        // <set xmlns="http://jabber.org/protocol/rsm">
+        writer.write_event(Event::Start(set_start)).unwrap();
+
+        let max = self.max.map(|max| max.to_string());
+        let count = self.count.map(|count| count.to_string());
+        for (name, value) in [
+            ("max", &max),
+            ("after", &self.after),
+            ("before", &self.before),
+            ("first", &self.first),
+            ("last", &self.last),
+            ("count", &count),
+        ] {
+            match value.as_deref() {
+                None => {}
+                Some("") => writer
+                    .write_event(Event::Empty(BytesStart::new(name)))
+                    .unwrap(),
+                Some(value) => {
+                    writer
+                        .write_event(Event::Start(BytesStart::new(name)))
+                        .unwrap();
+                    writer
+                        .write_event(Event::Text(BytesText::new(value)))
+                        .unwrap();
+                    writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+                }
+            }
+        }
+
+        // </set>
+        writer
+            .write_event(Event::End(BytesEnd::new("set")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+fn parse_number(name: &str, text: &str) -> Result<usize, Error> {
+    text.parse().map_err(|_| Error::invalid_value(name, text))
+}
+
+impl From<ResultSet> for Element {
+    fn from(set: ResultSet) -> Self {
+        let mut element = Element::new("set", RSM_NS);
+        let max = set.max.map(|max| max.to_string());
+        let count = set.count.map(|count| count.to_string());
+        for (name, value) in [
+            ("max", max),
+            ("after", set.after),
+            ("before", set.before),
+            ("first", set.first),
+            ("last", set.last),
+            ("count", count),
+        ] {
+            if let Some(value) = value {
+                element = element.with_child(Element::new(name, RSM_NS).with_text(&value));
+            }
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for ResultSet {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut set = ResultSet::default();
+
+        loop {
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"set" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                continue;
+            }
+
+            let text = if is_empty {
+                String::new()
+            } else {
+                let text = reader.read_text(e.name())?;
+                quick_xml::escape::unescape(&text)?.to_string()
+            };
+
+            match e.name().as_ref() {
+                b"max" => set.max = Some(parse_number("max", &text)?),
+                b"after" => set.after = Some(text),
+                b"before" => set.before = Some(text),
+                b"first" => set.first = Some(text),
+                b"last" => set.last = Some(text),
+                b"count" => set.count = Some(parse_number("count", &text)?),
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("set"));
+        }
+
+        Ok(set)
+    }
+}
+
+/// Archive query (XEP-0313), filters are submitted with a data form.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MamQuery {
+    pub query_id: Option<String>,
+    pub form: Option<DataForm>,
+    pub set: Option<ResultSet>,
+}
+
+impl MamQuery {
+    /// Asks for the conversation with `with`, or every conversation when `None`.
+    pub fn new(query_id: &str, with: Option<&str>, set: ResultSet) -> Self {
+        let form = with.map(|with| {
+            let mut form = DataForm::with_form_type(FormType::Submit, MAM_NS);
+            form.set_values("with", vec![with.to_string()]);
+            form
+        });
+        Self {
+            query_id: Some(query_id.to_string()),
+            form,
+            set: Some(set),
+        }
+    }
+
+    pub fn with(&self) -> Option<&str> {
+        self.form.as_ref().and_then(|form| form.value("with"))
+    }
+}
+
+impl XmlCustomSerialize for MamQuery {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut query_start = BytesStart::new("query");
+        query_start.push_attribute(("xmlns", MAM_NS));
+        if let Some(query_id) = &self.query_id {
+            query_start.push_attribute(("queryid", query_id.as_str()));
+        }
+
+        if self.form.is_none() && self.set.is_none() {
+            writer.write_event(Event::Empty(query_start)).unwrap();
+        } else {
+            // <query xmlns="urn:xmpp:mam:2">
+            writer.write_event(Event::Start(query_start)).unwrap();
+            if let Some(form) = &self.form {
+                writer
+                    .get_mut()
+                    .write_all(form.into_string().as_bytes())
+                    .unwrap();
+            }
+            if let Some(set) = &self.set {
+                writer
+                    .get_mut()
+                    .write_all(set.into_string().as_bytes())
+                    .unwrap();
+            }
+            // </query>
+            writer
+                .write_event(Event::End(BytesEnd::new("query")))
+                .unwrap();
+        }
+
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<MamQuery> for Element {
+    fn from(query: MamQuery) -> Self {
+        let mut element = Element::new("query", MAM_NS);
+        if let Some(query_id) = &query.query_id {
+            element.set_attr("queryid", query_id);
+        }
+        if let Some(form) = query.form {
+            element = element.with_child(form.into());
+        }
+        if let Some(set) = query.set {
+            element = element.with_child(set.into());
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for MamQuery {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut query = MamQuery::default();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"query" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                if let Some(query_id) = e.try_get_attribute("queryid")? {
+                    query.query_id = Some(query_id.unescape_value()?.to_string());
+                }
+                continue;
+            }
+
+            let xmlns = match e.try_get_attribute("xmlns")? {
+                Some(xmlns) => xmlns.unescape_value()?.to_string(),
+                None => String::new(),
+            };
+            if !is_empty {
+                reader.read_to_end(e.name())?;
+            }
+            let child = &value[child_start..reader.buffer_position()];
+
+            match (e.name().as_ref(), xmlns.as_str()) {
+                (b"x", DATA_FORMS_NS) => query.form = Some(DataForm::from_string(child)?),
+                (b"set", RSM_NS) => query.set = Some(ResultSet::from_string(child)?),
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("query"));
+        }
+
+        Ok(query)
+    }
+}
+
+/// `<fin/>` closing the results of a query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MamFin {
+    /// Whether the page reaches the end of the archive
+    pub complete: bool,
+    pub set: ResultSet,
+}
+
+impl XmlCustomSerialize for MamFin {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut fin_start = BytesStart::new("fin");
+        fin_start.push_attribute(("xmlns", MAM_NS));
+        if self.complete {
+            fin_start.push_attribute(("complete", "true"));
+        }
+        // <fin xmlns="urn:xmpp:mam:2">
+        writer.write_event(Event::Start(fin_start)).unwrap();
+        writer
+            .get_mut()
+            .write_all(self.set.into_string().as_bytes())
+            .unwrap();
+        // </fin>
+        writer
+            .write_event(Event::End(BytesEnd::new("fin")))
+            .unwrap();
+
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<MamFin> for Element {
+    fn from(fin: MamFin) -> Self {
+        let mut element = Element::new("fin", MAM_NS);
+        if fin.complete {
+            element.set_attr("complete", "true");
+        }
+        element.with_child(fin.set.into())
+    }
+}
+
+impl XmlCustomDeserialize for MamFin {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut fin = MamFin::default();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"fin" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                fin.complete = e
+                    .try_get_attribute("complete")?
+                    .is_some_and(|complete| complete.value.as_ref() == b"true");
+                continue;
+            }
+
+            if !is_empty {
+                reader.read_to_end(e.name())?;
+            }
+            if e.name().as_ref() == b"set" {
+                fin.set = ResultSet::from_string(&value[child_start..reader.buffer_position()])?;
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("fin"));
+        }
+
+        Ok(fin)
+    }
+}
+
+/// Archived message sent back for a query, forwarded (XEP-0297) with the time the server
+/// received it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MamResult {
+    pub query_id: Option<String>,
+    /// Archive id of the message, used to ask for the pages around it
+    pub id: String,
+    pub stamp: Option<String>,
+    pub message: Box<Message>,
+}
+
+impl XmlCustomSerialize for MamResult {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut result_start = BytesStart::new("result");
+        result_start.push_attribute(("xmlns", MAM_NS));
+        if let Some(query_id) = &self.query_id {
+            result_start.push_attribute(("queryid", query_id.as_str()));
+        }
+        result_start.push_attribute(("id", self.id.as_str()));
+        // <result xmlns="urn:xmpp:mam:2" id="...">
+        writer.write_event(Event::Start(result_start)).unwrap();
+
+        let mut forwarded_start = BytesStart::new("forwarded");
+        forwarded_start.push_attribute(("xmlns", FORWARD_NS));
+        // <forwarded xmlns="urn:xmpp:forward:0">
+        writer.write_event(Event::Start(forwarded_start)).unwrap();
+        if let Some(stamp) = &self.stamp {
+            // <delay xmlns="urn:xmpp:delay" stamp="..."/>
+            let mut delay_start = BytesStart::new("delay");
+            delay_start.push_attribute(("xmlns", DELAY_NS));
+            delay_start.push_attribute(("stamp", stamp.as_str()));
+            writer.write_event(Event::Empty(delay_start)).unwrap();
+        }
+        writer
+            .get_mut()
+            .write_all(self.message.into_string().as_bytes())
+            .unwrap();
+        // </forwarded>
+        writer
+            .write_event(Event::End(BytesEnd::new("forwarded")))
+            .unwrap();
+
+        // </result>
+        writer
+            .write_event(Event::End(BytesEnd::new("result")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<MamResult> for Element {
+    fn from(result: MamResult) -> Self {
+        let mut element = Element::new("result", MAM_NS);
+        if let Some(query_id) = &result.query_id {
+            element.set_attr("queryid", query_id);
+        }
+        element.set_attr("id", &result.id);
+
+        let mut forwarded = Element::new("forwarded", FORWARD_NS);
+        if let Some(stamp) = &result.stamp {
+            forwarded =
+                forwarded.with_child(Element::new("delay", DELAY_NS).with_attr("stamp", stamp));
+        }
+        let mut message = (*result.message).into();
+        inherit_namespace(&mut message, FORWARD_NS);
+        element.with_child(forwarded.with_child(message))
+    }
+}
+
+/// Moves `element` and its descendants without a namespace into `ns`, as they are when written
+/// inside an element of `ns`.
+fn inherit_namespace(element: &mut Element, ns: &str) {
+    if !element.ns.is_empty() {
+        return;
+    }
+    element.ns = ns.to_string();
+    for child in &mut element.children {
+        if let Node::Element(child) = child {
+            inherit_namespace(child, ns);
+        }
+    }
+}
+
+impl XmlCustomDeserialize for MamResult {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut query_id = None;
+        let mut id = None;
+        let mut stamp = None;
+        let mut message = None;
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"result" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                for attr in e.attributes().flatten() {
+                    let value = attr.unescape_value()?.to_string();
+                    match attr.key.0 {
+                        b"queryid" => query_id = Some(value),
+                        b"id" => id = Some(value),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            match e.name().as_ref() {
+                b"delay" => {
+                    if let Some(value) = e.try_get_attribute("stamp")? {
+                        stamp = Some(value.unescape_value()?.to_string());
+                    }
+                }
+                b"message" => {
+                    // The forwarded message parses on its own
+                    if !is_empty {
+                        reader.read_to_end(e.name())?;
+                    }
+                    let child = &value[child_start..reader.buffer_position()];
+                    message = Some(Box::new(Message::from_string(child)?));
+                }
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("result"));
+        }
+
+        Ok(MamResult {
+            query_id,
+            id: id.ok_or(Error::missing_attribute("id"))?,
+            stamp,
+            message: message.ok_or(Error::missing_element("forwarded"))?,
+        })
+    }
+}
+
+/// Formats a time as an XEP-0082 timestamp in UTC, like `2024-01-31T18:30:00Z`.
+pub fn datetime(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Civil date from the days since the epoch, see Howard Hinnant's `civil_from_days`
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
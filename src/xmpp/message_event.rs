@@ -0,0 +1,70 @@
+use super::{chat_state::ChatState, stanza::Message};
+
+/// What an incoming message means to a client, so that clients and bots can match on it
+/// instead of inspecting every extension of [`Message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageEvent {
+    Chat {
+        from: String,
+        id: Option<String>,
+        body: String,
+    },
+    Correction {
+        from: String,
+        /// Id of the message being corrected
+        id: String,
+        body: String,
+    },
+    Retraction {
+        from: String,
+        id: String,
+    },
+    Reaction {
+        from: String,
+        /// Id of the message being reacted to
+        id: String,
+        reactions: Vec<String>,
+    },
+    ChatState {
+        from: String,
+        chat_state: ChatState,
+    },
+}
+
+impl MessageEvent {
+    /// Returns `None` for messages without anything to show.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let from = message.from.clone().unwrap_or_default();
+
+        if let Some(retract) = &message.retract {
+            return Some(MessageEvent::Retraction {
+                from,
+                id: retract.id.clone(),
+            });
+        }
+        if let Some(reactions) = &message.reactions {
+            return Some(MessageEvent::Reaction {
+                from,
+                id: reactions.id.clone(),
+                reactions: reactions.reactions.clone(),
+            });
+        }
+        if let Some(body) = &message.body {
+            return Some(match &message.replace {
+                Some(replace) => MessageEvent::Correction {
+                    from,
+                    id: replace.id.clone(),
+                    body: body.clone(),
+                },
+                None => MessageEvent::Chat {
+                    from,
+                    id: message.id.clone(),
+                    body: body.clone(),
+                },
+            });
+        }
+        message
+            .chat_state
+            .map(|chat_state| MessageEvent::ChatState { from, chat_state })
+    }
+}
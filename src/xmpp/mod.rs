@@ -1,5 +1,39 @@
+mod chat_state;
+mod command;
+mod component;
+mod correction;
+mod data_form;
+mod dialback;
+mod disco;
+mod element;
 mod handshake;
+mod iq;
+mod mam;
+mod message_event;
+mod reactions;
+mod redact;
+mod register;
+mod restricted;
 mod serialize;
+mod stanza;
+mod stream_error;
 
+pub use chat_state::*;
+pub use command::*;
+pub use component::*;
+pub use correction::*;
+pub use data_form::*;
+pub use dialback::*;
+pub use disco::*;
+pub use element::*;
 pub use handshake::*;
-pub use serialize::*;
\ No newline at end of file
+pub use iq::*;
+pub use mam::*;
+pub use message_event::*;
+pub use reactions::*;
+pub use redact::*;
+pub use register::*;
+pub use restricted::*;
+pub use serialize::*;
+pub use stanza::*;
+pub use stream_error::*;
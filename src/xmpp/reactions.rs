@@ -0,0 +1,38 @@
+use crate::Error;
+
+pub const REACTIONS_NS: &str = "urn:xmpp:reactions:0";
+
+/// `<reactions id=...>` from XEP-0444, the full set of reactions of the sender to message `id`.
+///
+/// An empty list removes all previous reactions of the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reactions {
+    pub id: String,
+    pub reactions: Vec<String>,
+}
+
+impl Reactions {
+    /// Creates a reaction set, failing if any reaction is not a single emoji.
+    pub fn new(id: String, reactions: Vec<String>) -> Result<Self, Error> {
+        if let Some(invalid) = reactions.iter().find(|reaction| !is_emoji(reaction)) {
+            return Err(Error::invalid_value("reaction", invalid));
+        }
+
+        let mut unique: Vec<String> = Vec::with_capacity(reactions.len());
+        for reaction in reactions {
+            if !unique.contains(&reaction) {
+                unique.push(reaction);
+            }
+        }
+
+        Ok(Reactions {
+            id,
+            reactions: unique,
+        })
+    }
+}
+
+/// Checks that `value` is exactly one emoji, including skin tone and ZWJ sequences.
+pub fn is_emoji(value: &str) -> bool {
+    emojis::get(value).is_some()
+}
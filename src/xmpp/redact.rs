@@ -0,0 +1,83 @@
+use std::fmt;
+
+use quick_xml::{
+    events::{BytesText, Event},
+    NsReader, Writer,
+};
+
+use super::{
+    dialback::DIALBACK_NS, handshake::SASL_NS, register::REGISTER_NS, serialize::resolve_namespace,
+};
+
+const REDACTED: &str = "[redacted]";
+
+/// Whether the text of an element holds a secret: SASL payloads, passwords, invite keys,
+/// dialback keys and component handshakes.
+fn is_secret(ns: &str, name: &[u8]) -> bool {
+    match ns {
+        SASL_NS => matches!(name, b"auth" | b"response" | b"challenge" | b"success"),
+        REGISTER_NS => matches!(name, b"password" | b"key"),
+        DIALBACK_NS => matches!(name, b"result" | b"verify"),
+        _ => name == b"handshake",
+    }
+}
+
+/// Writes `xml` with the text of secret elements and password fields of data forms replaced,
+/// fit for logs. Malformed XML is not written at all.
+pub struct Redacted<'a>(pub &'a str);
+
+impl Redacted<'_> {
+    fn redact(&self) -> Option<String> {
+        let mut reader = NsReader::from_str(self.0);
+        reader.check_end_names(false);
+        let mut writer = Writer::new(Vec::new());
+        // Depth inside the outermost secret element, 0 outside of any
+        let mut secret: usize = 0;
+
+        loop {
+            let (ns, event) = reader.read_resolved_event().ok()?;
+            let event = match event {
+                Event::Eof => break,
+                Event::Start(e) => {
+                    // An undeclared prefix could hide a secret element, treat it as one
+                    let ns = resolve_namespace(ns);
+                    let is_password = e.local_name().as_ref() == b"field"
+                        && e.try_get_attribute("var")
+                            .ok()
+                            .flatten()
+                            .is_some_and(|var| {
+                                String::from_utf8_lossy(&var.value).contains("password")
+                            });
+                    let is_secret = match ns {
+                        Ok(ns) => is_secret(&ns, e.local_name().as_ref()),
+                        Err(_) => true,
+                    };
+                    if secret > 0 || is_password || is_secret {
+                        secret += 1;
+                    }
+                    Event::Start(e)
+                }
+                Event::End(e) => {
+                    secret = secret.saturating_sub(1);
+                    Event::End(e)
+                }
+                Event::Text(_) | Event::CData(_) if secret > 0 => {
+                    Event::Text(BytesText::new(REDACTED))
+                }
+                event => event,
+            };
+            writer.write_event(event).ok()?;
+        }
+
+        String::from_utf8(writer.into_inner()).ok()
+    }
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.redact() {
+            Some(xml) => write!(f, "{}", xml),
+            None => write!(f, "[malformed XML, {} bytes]", self.0.len()),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    data_form::{DataForm, DATA_FORMS_NS},
+    element::Element,
+    serialize::{XmlCustomDeserialize, XmlCustomSerialize},
+};
+use crate::Error;
+
+pub const REGISTER_NS: &str = "jabber:iq:register";
+
+/// `<query xmlns="jabber:iq:register"/>` from XEP-0077.
+///
+/// Fields set to an empty string are serialized as empty elements, which is how the server
+/// tells the client which fields it needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterQuery {
+    pub instructions: Option<String>,
+    /// Set by the server when the requesting entity already has an account
+    pub registered: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub email: Option<String>,
+    /// Invitation token when registration is invite-only
+    pub key: Option<String>,
+    /// Asks the server to remove the account
+    pub remove: bool,
+    /// Extensible registration form, with `FORM_TYPE` `jabber:iq:register`
+    pub form: Option<DataForm>,
+}
+
+impl RegisterQuery {
+    /// Fills the fixed fields from the submitted form, if any.
+    pub fn with_form_values(mut self) -> Self {
+        if let Some(form) = &self.form {
+            for (name, value) in [
+                ("username", &mut self.username),
+                ("password", &mut self.password),
+                ("email", &mut self.email),
+                ("key", &mut self.key),
+            ] {
+                if let Some(submitted) = form.value(name) {
+                    *value = Some(submitted.to_string());
+                }
+            }
+        }
+        self
+    }
+
+    fn fields(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("instructions", &self.instructions),
+            ("username", &self.username),
+            ("password", &self.password),
+            ("email", &self.email),
+            ("key", &self.key),
+        ]
+    }
+}
+
+impl XmlCustomSerialize for RegisterQuery {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut query_start = BytesStart::new("query");
+        query_start.push_attribute(("xmlns", REGISTER_NS));
+        // <query xmlns="jabber:iq:register">
+        writer.write_event(Event::Start(query_start)).unwrap();
+
+        if self.registered {
+            writer
+                .write_event(Event::Empty(BytesStart::new("registered")))
+                .unwrap();
+        }
+
+        for (name, value) in self.fields() {
+            match value.as_deref() {
+                None => {}
+                Some("") => writer
+                    .write_event(Event::Empty(BytesStart::new(name)))
+                    .unwrap(),
+                Some(value) => {
+                    writer
+                        .write_event(Event::Start(BytesStart::new(name)))
+                        .unwrap();
+                    writer
+                        .write_event(Event::Text(BytesText::new(value)))
+                        .unwrap();
+                    writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+                }
+            }
+        }
+
+        if let Some(form) = &self.form {
+            writer
+                .get_mut()
+                .write_all(form.into_string().as_bytes())
+                .unwrap();
+        }
+
+        if self.remove {
+            writer
+                .write_event(Event::Empty(BytesStart::new("remove")))
+                .unwrap();
+        }
+
+        // </query>
+        writer
+            .write_event(Event::End(BytesEnd::new("query")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<RegisterQuery> for Element {
+    fn from(query: RegisterQuery) -> Self {
+        let mut element = Element::new("query", REGISTER_NS);
+        if query.registered {
+            element = element.with_child(Element::new("registered", REGISTER_NS));
+        }
+        for (name, value) in query.fields() {
+            if let Some(value) = value {
+                element = element.with_child(Element::new(name, REGISTER_NS).with_text(value));
+            }
+        }
+        if let Some(form) = query.form {
+            element = element.with_child(form.into());
+        }
+        if query.remove {
+            element = element.with_child(Element::new("remove", REGISTER_NS));
+        }
+        element
+    }
+}
+
+impl XmlCustomDeserialize for RegisterQuery {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut query = RegisterQuery::default();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let (e, is_empty) = match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+
+            if !header_found {
+                if e.name().as_ref() != b"query" {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                header_found = true;
+                continue;
+            }
+
+            let is_form = e
+                .try_get_attribute("xmlns")?
+                .is_some_and(|xmlns| xmlns.value.as_ref() == DATA_FORMS_NS.as_bytes());
+            if e.name().as_ref() == b"x" && is_form {
+                if !is_empty {
+                    reader.read_to_end(e.name())?;
+                }
+                query.form = Some(DataForm::from_string(
+                    &value[child_start..reader.buffer_position()],
+                )?);
+                continue;
+            }
+
+            let text = if is_empty {
+                String::new()
+            } else {
+                let text = reader.read_text(e.name())?;
+                quick_xml::escape::unescape(&text)?.to_string()
+            };
+
+            match e.name().as_ref() {
+                b"registered" => query.registered = true,
+                b"remove" => query.remove = true,
+                b"instructions" => query.instructions = Some(text),
+                b"username" => query.username = Some(text),
+                b"password" => query.password = Some(text),
+                b"email" => query.email = Some(text),
+                b"key" => query.key = Some(text),
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("query"));
+        }
+
+        Ok(query)
+    }
+}
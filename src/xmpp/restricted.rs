@@ -0,0 +1,101 @@
+use quick_xml::{events::Event, Reader};
+
+use super::stream_error::StreamError;
+
+fn policy_violation(text: String) -> StreamError {
+    StreamError {
+        condition: "policy-violation".to_string(),
+        text: Some(text),
+    }
+}
+
+fn not_well_formed(text: String) -> StreamError {
+    StreamError {
+        condition: "not-well-formed".to_string(),
+        text: Some(text),
+    }
+}
+
+fn restricted_xml(text: &str) -> StreamError {
+    StreamError {
+        condition: "restricted-xml".to_string(),
+        text: Some(text.to_string()),
+    }
+}
+
+/// Checks that `xml` only uses the XML allowed on streams (RFC 6120 section 11.1): no
+/// DOCTYPE, processing instructions, comments or entities other than the predefined ones.
+/// Elements may not nest deeper than `max_depth`, nor have more than `max_attributes`.
+///
+/// Elements may be left open, and closed in a later frame, as the stream header is.
+pub fn check_restricted_xml(
+    xml: &str,
+    max_depth: usize,
+    max_attributes: usize,
+) -> Result<(), StreamError> {
+    let mut reader = Reader::from_str(xml);
+    // End tags are matched here, a frame may close the element a previous one opened
+    reader.check_end_names(false);
+    // Names of the elements left open
+    let mut open: Vec<Vec<u8>> = Vec::new();
+
+    loop {
+        let (e, is_empty) = match reader.read_event() {
+            Ok(Event::Eof) => return Ok(()),
+            Err(e) => return Err(not_well_formed(e.to_string())),
+            Ok(Event::DocType(_)) => return Err(restricted_xml("DOCTYPE is not allowed")),
+            Ok(Event::PI(_)) => {
+                return Err(restricted_xml("processing instructions are not allowed"))
+            }
+            Ok(Event::Comment(_)) => return Err(restricted_xml("comments are not allowed")),
+            Ok(Event::Text(e)) => {
+                if e.unescape().is_err() {
+                    return Err(restricted_xml("entity references are not allowed"));
+                }
+                continue;
+            }
+            Ok(Event::End(e)) => {
+                match open.pop() {
+                    Some(name) if name != e.name().as_ref() => {
+                        return Err(not_well_formed(format!(
+                            "</{}> closes <{}>",
+                            String::from_utf8_lossy(e.name().as_ref()),
+                            String::from_utf8_lossy(&name)
+                        )));
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Ok(Event::Start(e)) => (e, false),
+            Ok(Event::Empty(e)) => (e, true),
+            Ok(_) => continue,
+        };
+
+        if open.len() >= max_depth {
+            return Err(policy_violation(format!(
+                "elements nested deeper than {}",
+                max_depth
+            )));
+        }
+        if !is_empty {
+            open.push(e.name().as_ref().to_vec());
+        }
+
+        let mut count = 0;
+        for attr in e.attributes() {
+            let attr = attr.map_err(|e| not_well_formed(e.to_string()))?;
+            if attr.unescape_value().is_err() {
+                return Err(restricted_xml("entity references are not allowed"));
+            }
+            count += 1;
+        }
+        if count > max_attributes {
+            return Err(policy_violation(format!(
+                "more than {} attributes on <{}>",
+                max_attributes,
+                String::from_utf8_lossy(e.name().as_ref())
+            )));
+        }
+    }
+}
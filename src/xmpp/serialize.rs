@@ -1,9 +1,151 @@
-use color_eyre::eyre;
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    name::ResolveResult,
+    NsReader, Writer,
+};
+
+use super::{dialback::DIALBACK_NS, handshake::STREAM_NS, stream_error::StreamError};
+use crate::Error;
+
+pub use mini_jabber_derive::XmlElement;
+
+/// Used by the code `#[derive(XmlElement)]` generates.
+#[doc(hidden)]
+pub mod __private {
+    pub use quick_xml;
+}
 
 pub trait XmlCustomSerialize {
+    #[allow(clippy::wrong_self_convention)]
     fn into_string(&self) -> String;
 }
 
 pub trait XmlCustomDeserialize where Self: Sized {
-    fn from_string(value: &str) -> eyre::Result<Self>;
-}
\ No newline at end of file
+    fn from_string(value: &str) -> Result<Self, Error>;
+}
+
+/// An element that can be nested in others, usually implemented with `#[derive(XmlElement)]`.
+pub trait XmlElement: Sized {
+    /// Whether `local_name` is the name of this element, without prefix
+    fn accepts(local_name: &[u8]) -> bool;
+
+    fn write_to<W: Write>(&self, writer: &mut Writer<W>) -> quick_xml::Result<()>;
+
+    /// Reads the element opened by `start`, up to and including its end tag unless `empty`.
+    fn read_from(reader: &mut NsReader<&[u8]>, start: &BytesStart, empty: bool)
+        -> Result<Self, Error>;
+}
+
+impl<T: XmlElement> XmlCustomSerialize for T {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+        self.write_to(&mut writer).unwrap();
+        String::from_utf8(writer.into_inner().into_inner()).unwrap()
+    }
+}
+
+impl<T: XmlElement> XmlCustomDeserialize for T {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = NsReader::from_str(value);
+
+        loop {
+            let (start, empty) = match reader.read_event()? {
+                Event::Eof => return Err(Error::missing_element("*")),
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                _ => continue,
+            };
+            if !T::accepts(start.local_name().as_ref()) {
+                return Err(Error::unexpected_element(start.name()));
+            }
+            return T::read_from(&mut reader, &start, empty);
+        }
+    }
+}
+
+/// Skips the element opened by `start` along with its children.
+pub fn skip_element(
+    reader: &mut NsReader<&[u8]>,
+    start: &BytesStart,
+    empty: bool,
+) -> Result<(), Error> {
+    if !empty {
+        reader.read_to_end(start.name())?;
+    }
+    Ok(())
+}
+
+/// Unescaped text of the element opened by `start`, which must not have children.
+pub fn element_text(
+    reader: &mut NsReader<&[u8]>,
+    start: &BytesStart,
+    empty: bool,
+) -> Result<String, Error> {
+    if empty {
+        return Ok(String::new());
+    }
+    let text = reader.read_text(start.name())?;
+    Ok(quick_xml::escape::unescape(&text)?.into_owned())
+}
+
+/// Prefixes that stream headers declare. Frames are parsed on their own, without the header, so
+/// these are known even when the frame doesn't declare them.
+const STREAM_PREFIXES: [(&str, &str); 2] = [("stream", STREAM_NS), ("db", DIALBACK_NS)];
+
+/// Namespace of a name read by `NsReader`, empty when it has none.
+pub fn resolve_namespace(ns: ResolveResult) -> Result<String, StreamError> {
+    match ns {
+        // Namespaces are resolved from the attribute as written, escapes included
+        ResolveResult::Bound(ns) => {
+            quick_xml::escape::unescape(&String::from_utf8_lossy(ns.as_ref()))
+                .map(|ns| ns.into_owned())
+                .map_err(|e| StreamError {
+                    condition: "not-well-formed".to_string(),
+                    text: Some(e.to_string()),
+                })
+        }
+        ResolveResult::Unbound => Ok(String::new()),
+        ResolveResult::Unknown(prefix) => STREAM_PREFIXES
+            .iter()
+            .find(|(known, _)| known.as_bytes() == prefix)
+            .map(|(_, ns)| ns.to_string())
+            .ok_or_else(|| StreamError {
+                condition: "bad-namespace-prefix".to_string(),
+                text: Some(format!("undeclared prefix {}", String::from_utf8_lossy(&prefix))),
+            }),
+    }
+}
+
+/// Prefixed namespaces declared on `start`, such as `("xhtml", "http://www.w3.org/1999/xhtml")`
+/// for `xmlns:xhtml`.
+pub fn namespace_declarations(start: &BytesStart) -> Result<Vec<(String, String)>, Error> {
+    let mut declarations = Vec::new();
+    for attr in start.attributes() {
+        let attr = attr?;
+        if let Some(prefix) = attr.key.as_ref().strip_prefix(b"xmlns:") {
+            declarations.push((
+                std::str::from_utf8(prefix)?.to_string(),
+                attr.unescape_value()?.into_owned(),
+            ));
+        }
+    }
+    Ok(declarations)
+}
+
+/// Namespace of the element opened by `start`.
+pub fn element_namespace(
+    reader: &NsReader<&[u8]>,
+    start: &BytesStart,
+) -> Result<String, StreamError> {
+    resolve_namespace(reader.resolve_element(start.name()).0)
+}
+
+/// The `invalid-namespace` stream error for element `name` found in `ns` instead of `expected`.
+pub fn invalid_namespace(name: &str, ns: &str, expected: &str) -> StreamError {
+    StreamError {
+        condition: "invalid-namespace".to_string(),
+        text: Some(format!("<{}> in {:?}, expected {}", name, ns, expected)),
+    }
+}
@@ -0,0 +1,596 @@
+use std::io::{Cursor, Write};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    chat_state::{ChatState, CHAT_STATES_NS},
+    correction::{Replace, Retract, MESSAGE_CORRECT_NS, MESSAGE_RETRACT_NS},
+    element::{Element, Node},
+    iq::Iq,
+    mam::{MamResult, MAM_NS},
+    reactions::{is_emoji, Reactions, REACTIONS_NS},
+    serialize::{namespace_declarations, XmlCustomDeserialize, XmlCustomSerialize},
+};
+use crate::Error;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub message_type: Option<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    pub chat_state: Option<ChatState>,
+    pub replace: Option<Replace>,
+    pub retract: Option<Retract>,
+    pub reactions: Option<Reactions>,
+    /// Set on messages the server sends back from its archive
+    pub archived: Option<MamResult>,
+    /// Children we don't model, such as `<thread/>`
+    pub payloads: Vec<Element>,
+}
+
+impl XmlCustomSerialize for Message {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut message_start = BytesStart::new("message");
+        if let Some(id) = &self.id {
+            message_start.push_attribute(("id", id.as_str()));
+        }
+        if let Some(from) = &self.from {
+            message_start.push_attribute(("from", from.as_str()));
+        }
+        if let Some(to) = &self.to {
+            message_start.push_attribute(("to", to.as_str()));
+        }
+        if let Some(message_type) = &self.message_type {
+            message_start.push_attribute(("type", message_type.as_str()));
+        }
+
+        // <message>
+        writer.write_event(Event::Start(message_start)).unwrap();
+
+        if let Some(subject) = &self.subject {
+            // <subject>text</subject>
+            writer
+                .write_event(Event::Start(BytesStart::new("subject")))
+                .unwrap();
+            writer
+                .write_event(Event::Text(BytesText::new(subject.as_str())))
+                .unwrap();
+            writer
+                .write_event(Event::End(BytesEnd::new("subject")))
+                .unwrap();
+        }
+
+        if let Some(body) = &self.body {
+            // <body>text</body>
+            writer
+                .write_event(Event::Start(BytesStart::new("body")))
+                .unwrap();
+            writer
+                .write_event(Event::Text(BytesText::new(body.as_str())))
+                .unwrap();
+            writer
+                .write_event(Event::End(BytesEnd::new("body")))
+                .unwrap();
+        }
+
+        if let Some(chat_state) = &self.chat_state {
+            // <composing xmlns="http://jabber.org/protocol/chatstates"/>
+            let mut chat_state_start = BytesStart::new(chat_state.name());
+            chat_state_start.push_attribute(("xmlns", CHAT_STATES_NS));
+            writer.write_event(Event::Empty(chat_state_start)).unwrap();
+        }
+
+        if let Some(replace) = &self.replace {
+            // <replace id="..." xmlns="urn:xmpp:message-correct:0"/>
+            let mut replace_start = BytesStart::new("replace");
+            replace_start.push_attribute(("id", replace.id.as_str()));
+            replace_start.push_attribute(("xmlns", MESSAGE_CORRECT_NS));
+            writer.write_event(Event::Empty(replace_start)).unwrap();
+        }
+
+        if let Some(retract) = &self.retract {
+            // <retract id="..." xmlns="urn:xmpp:message-retract:1"/>
+            let mut retract_start = BytesStart::new("retract");
+            retract_start.push_attribute(("id", retract.id.as_str()));
+            retract_start.push_attribute(("xmlns", MESSAGE_RETRACT_NS));
+            writer.write_event(Event::Empty(retract_start)).unwrap();
+        }
+
+        if let Some(reactions) = &self.reactions {
+            let mut reactions_start = BytesStart::new("reactions");
+            reactions_start.push_attribute(("id", reactions.id.as_str()));
+            reactions_start.push_attribute(("xmlns", REACTIONS_NS));
+
+            if reactions.reactions.is_empty() {
+                writer.write_event(Event::Empty(reactions_start)).unwrap();
+            } else {
+                // <reactions id="..." xmlns="urn:xmpp:reactions:0">
+                writer.write_event(Event::Start(reactions_start)).unwrap();
+                for reaction in &reactions.reactions {
+                    // <reaction>emoji</reaction>
+                    writer
+                        .write_event(Event::Start(BytesStart::new("reaction")))
+                        .unwrap();
+                    writer
+                        .write_event(Event::Text(BytesText::new(reaction.as_str())))
+                        .unwrap();
+                    writer
+                        .write_event(Event::End(BytesEnd::new("reaction")))
+                        .unwrap();
+                }
+                // </reactions>
+                writer
+                    .write_event(Event::End(BytesEnd::new("reactions")))
+                    .unwrap();
+            }
+        }
+
+        if let Some(archived) = &self.archived {
+            writer
+                .get_mut()
+                .write_all(archived.into_string().as_bytes())
+                .unwrap();
+        }
+
+        for payload in &self.payloads {
+            writer
+                .get_mut()
+                .write_all(payload.into_string().as_bytes())
+                .unwrap();
+        }
+
+        // </message>
+        writer
+            .write_event(Event::End(BytesEnd::new("message")))
+            .unwrap();
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl XmlCustomDeserialize for Message {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut message = Message::default();
+        // Prefixes declared on <message/>, which its payloads may use
+        let mut declarations = Vec::new();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let event = reader.read_event()?;
+            let is_empty = matches!(event, Event::Empty(_));
+            match event {
+                Event::Eof => break,
+                Event::Start(e) if header_found && e.name().as_ref() == b"result" => {
+                    // Results carry a whole forwarded message, which parses on its own
+                    let is_archived = e
+                        .try_get_attribute("xmlns")?
+                        .is_some_and(|xmlns| xmlns.value.as_ref() == MAM_NS.as_bytes());
+                    reader.read_to_end(e.name())?;
+                    let child = &value[child_start..reader.buffer_position()];
+                    match is_archived {
+                        true => message.archived = Some(MamResult::from_string(child)?),
+                        false => message
+                            .payloads
+                            .push(Element::from_string_in_scope(child, &declarations)?),
+                    }
+                }
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"message" => {
+                    header_found = true;
+                    declarations = namespace_declarations(&e)?;
+
+                    for attr in e.attributes().flatten() {
+                        let value = attr.unescape_value()?.to_string();
+                        match attr.key.0 {
+                            b"id" => message.id = Some(value),
+                            b"from" => message.from = Some(value),
+                            b"to" => message.to = Some(value),
+                            b"type" => message.message_type = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+                Event::Start(e) if e.name().as_ref() == b"subject" => {
+                    if !header_found {
+                        return Err(Error::missing_element("message"));
+                    }
+                    let subject = reader.read_text(e.name())?;
+                    message.subject = Some(quick_xml::escape::unescape(&subject)?.to_string());
+                }
+                Event::Start(e) if e.name().as_ref() == b"body" => {
+                    if !header_found {
+                        return Err(Error::missing_element("message"));
+                    }
+                    let body = reader.read_text(e.name())?;
+                    message.body = Some(quick_xml::escape::unescape(&body)?.to_string());
+                }
+                Event::Start(e) if e.name().as_ref() == b"reactions" => {
+                    if !header_found {
+                        return Err(Error::missing_element("message"));
+                    }
+                    let is_reactions = e
+                        .try_get_attribute("xmlns")?
+                        .is_some_and(|xmlns| xmlns.value.as_ref() == REACTIONS_NS.as_bytes());
+                    if !is_reactions {
+                        reader.read_to_end(e.name())?;
+                        let child = &value[child_start..reader.buffer_position()];
+                        message
+                            .payloads
+                            .push(Element::from_string_in_scope(child, &declarations)?);
+                        continue;
+                    }
+
+                    let id = e
+                        .try_get_attribute("id")?
+                        .ok_or(Error::missing_attribute("id"))?
+                        .unescape_value()?
+                        .to_string();
+                    let mut reactions = Vec::new();
+
+                    loop {
+                        match reader.read_event()? {
+                            Event::Start(e) if e.name().as_ref() == b"reaction" => {
+                                let reaction = reader.read_text(e.name())?;
+                                let reaction = quick_xml::escape::unescape(&reaction)?;
+                                // Receivers ignore reactions that are not a single emoji
+                                if is_emoji(&reaction) && !reactions.contains(&reaction.to_string())
+                                {
+                                    reactions.push(reaction.to_string());
+                                }
+                            }
+                            Event::End(e) if e.name().as_ref() == b"reactions" => break,
+                            Event::Eof => return Err(Error::unclosed("reactions")),
+                            _ => {}
+                        }
+                    }
+
+                    message.reactions = Some(Reactions { id, reactions });
+                }
+                Event::Start(e) | Event::Empty(e) => {
+                    if !header_found {
+                        return Err(Error::missing_element("message"));
+                    }
+
+                    let xmlns = match e.try_get_attribute("xmlns")? {
+                        Some(xmlns) => xmlns.unescape_value()?.to_string(),
+                        None => String::new(),
+                    };
+                    let id = match e.try_get_attribute("id")? {
+                        Some(id) => Some(id.unescape_value()?.to_string()),
+                        None => None,
+                    };
+                    if !is_empty {
+                        reader.read_to_end(e.name())?;
+                    }
+
+                    match (e.name().as_ref(), xmlns.as_str()) {
+                        (name, CHAT_STATES_NS) => message.chat_state = ChatState::from_name(name),
+                        (b"replace", MESSAGE_CORRECT_NS) => {
+                            let id = id.ok_or(Error::missing_attribute("id"))?;
+                            message.replace = Some(Replace { id });
+                        }
+                        (b"retract", MESSAGE_RETRACT_NS) => {
+                            let id = id.ok_or(Error::missing_attribute("id"))?;
+                            message.retract = Some(Retract { id });
+                        }
+                        (b"reactions", REACTIONS_NS) => {
+                            let id = id.ok_or(Error::missing_attribute("id"))?;
+                            message.reactions = Some(Reactions {
+                                id,
+                                reactions: Vec::new(),
+                            });
+                        }
+                        _ => {
+                            // Forwarded as is
+                            let child = &value[child_start..reader.buffer_position()];
+                            message
+                                .payloads
+                                .push(Element::from_string_in_scope(child, &declarations)?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("message"));
+        }
+
+        Ok(message)
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<Message> for Element {
+    fn from(message: Message) -> Self {
+        let mut element = Element::new("message", "");
+        for (name, value) in [
+            ("id", message.id),
+            ("from", message.from),
+            ("to", message.to),
+            ("type", message.message_type),
+        ] {
+            if let Some(value) = value {
+                element.attrs.push((name.to_string(), value));
+            }
+        }
+
+        for (name, text) in [("subject", message.subject), ("body", message.body)] {
+            if let Some(text) = text {
+                element = element.with_child(Element::new(name, "").with_text(&text));
+            }
+        }
+        if let Some(chat_state) = message.chat_state {
+            element = element.with_child(Element::new(chat_state.name(), CHAT_STATES_NS));
+        }
+        if let Some(replace) = message.replace {
+            element = element.with_child(
+                Element::new("replace", MESSAGE_CORRECT_NS).with_attr("id", &replace.id),
+            );
+        }
+        if let Some(retract) = message.retract {
+            element = element.with_child(
+                Element::new("retract", MESSAGE_RETRACT_NS).with_attr("id", &retract.id),
+            );
+        }
+        if let Some(reactions) = message.reactions {
+            let mut reactions_element =
+                Element::new("reactions", REACTIONS_NS).with_attr("id", &reactions.id);
+            for reaction in &reactions.reactions {
+                reactions_element = reactions_element
+                    .with_child(Element::new("reaction", REACTIONS_NS).with_text(reaction));
+            }
+            element = element.with_child(reactions_element);
+        }
+        if let Some(archived) = message.archived {
+            element = element.with_child(archived.into());
+        }
+        element
+            .children
+            .extend(message.payloads.into_iter().map(Node::Element));
+        element
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Presence {
+    pub id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// `None` when available, otherwise `unavailable`, `subscribe`...
+    pub presence_type: Option<String>,
+    pub show: Option<String>,
+    pub status: Option<String>,
+    /// Children we don't model, such as `<priority/>` or entity capabilities
+    pub payloads: Vec<Element>,
+}
+
+impl XmlCustomSerialize for Presence {
+    fn into_string(&self) -> String {
+        let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
+
+        let mut presence_start = BytesStart::new("presence");
+        if let Some(id) = &self.id {
+            presence_start.push_attribute(("id", id.as_str()));
+        }
+        if let Some(from) = &self.from {
+            presence_start.push_attribute(("from", from.as_str()));
+        }
+        if let Some(to) = &self.to {
+            presence_start.push_attribute(("to", to.as_str()));
+        }
+        if let Some(presence_type) = &self.presence_type {
+            presence_start.push_attribute(("type", presence_type.as_str()));
+        }
+
+        if self.show.is_none() && self.status.is_none() && self.payloads.is_empty() {
+            // <presence/>
+            writer.write_event(Event::Empty(presence_start)).unwrap();
+        } else {
+            // <presence>
+            writer.write_event(Event::Start(presence_start)).unwrap();
+            for (name, text) in [("show", &self.show), ("status", &self.status)] {
+                let Some(text) = text else { continue };
+                // <show>away</show>
+                writer
+                    .write_event(Event::Start(BytesStart::new(name)))
+                    .unwrap();
+                writer
+                    .write_event(Event::Text(BytesText::new(text.as_str())))
+                    .unwrap();
+                writer.write_event(Event::End(BytesEnd::new(name))).unwrap();
+            }
+            for payload in &self.payloads {
+                writer
+                    .get_mut()
+                    .write_all(payload.into_string().as_bytes())
+                    .unwrap();
+            }
+            // </presence>
+            writer
+                .write_event(Event::End(BytesEnd::new("presence")))
+                .unwrap();
+        }
+
+        std::str::from_utf8(writer.into_inner().into_inner().as_slice())
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl XmlCustomDeserialize for Presence {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        let mut reader = Reader::from_str(value);
+
+        let mut header_found = false;
+        let mut presence = Presence::default();
+        // Prefixes declared on <presence/>, which its payloads may use
+        let mut declarations = Vec::new();
+
+        loop {
+            let child_start = reader.buffer_position();
+            let event = reader.read_event()?;
+            let is_empty = matches!(event, Event::Empty(_));
+            match event {
+                Event::Eof => break,
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"presence" => {
+                    header_found = true;
+                    declarations = namespace_declarations(&e)?;
+
+                    for attr in e.attributes().flatten() {
+                        let value = attr.unescape_value()?.to_string();
+                        match attr.key.0 {
+                            b"id" => presence.id = Some(value),
+                            b"from" => presence.from = Some(value),
+                            b"to" => presence.to = Some(value),
+                            b"type" => presence.presence_type = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+                Event::Start(e) if !header_found => {
+                    return Err(Error::unexpected_element(e.name()));
+                }
+                Event::Start(e) if e.name().as_ref() == b"show" => {
+                    let show = reader.read_text(e.name())?;
+                    presence.show = Some(quick_xml::escape::unescape(&show)?.to_string());
+                }
+                Event::Start(e) if e.name().as_ref() == b"status" => {
+                    let status = reader.read_text(e.name())?;
+                    presence.status = Some(quick_xml::escape::unescape(&status)?.to_string());
+                }
+                Event::Start(e) | Event::Empty(e) if header_found => {
+                    if !is_empty {
+                        reader.read_to_end(e.name())?;
+                    }
+                    let child = &value[child_start..reader.buffer_position()];
+                    presence
+                        .payloads
+                        .push(Element::from_string_in_scope(child, &declarations)?);
+                }
+                _ => {}
+            }
+        }
+
+        if !header_found {
+            return Err(Error::missing_element("presence"));
+        }
+
+        Ok(presence)
+    }
+}
+
+/// Builds the element `into_string` writes, without parsing it back.
+impl From<Presence> for Element {
+    fn from(presence: Presence) -> Self {
+        let mut element = Element::new("presence", "");
+        for (name, value) in [
+            ("id", presence.id),
+            ("from", presence.from),
+            ("to", presence.to),
+            ("type", presence.presence_type),
+        ] {
+            if let Some(value) = value {
+                element.attrs.push((name.to_string(), value));
+            }
+        }
+
+        for (name, text) in [("show", presence.show), ("status", presence.status)] {
+            if let Some(text) = text {
+                element = element.with_child(Element::new(name, "").with_text(&text));
+            }
+        }
+        element
+            .children
+            .extend(presence.payloads.into_iter().map(Node::Element));
+        element
+    }
+}
+
+/// A stanza we know how to route, on streams that carry more than one kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stanza {
+    Message(Message),
+    Presence(Presence),
+    Iq(Iq),
+}
+
+impl From<Message> for Stanza {
+    fn from(message: Message) -> Self {
+        Stanza::Message(message)
+    }
+}
+
+impl From<Presence> for Stanza {
+    fn from(presence: Presence) -> Self {
+        Stanza::Presence(presence)
+    }
+}
+
+impl From<Iq> for Stanza {
+    fn from(iq: Iq) -> Self {
+        Stanza::Iq(iq)
+    }
+}
+
+impl Stanza {
+    pub fn from(&self) -> Option<&str> {
+        match self {
+            Stanza::Message(message) => message.from.as_deref(),
+            Stanza::Presence(presence) => presence.from.as_deref(),
+            Stanza::Iq(iq) => iq.from.as_deref(),
+        }
+    }
+
+    pub fn to(&self) -> Option<&str> {
+        match self {
+            Stanza::Message(message) => message.to.as_deref(),
+            Stanza::Presence(presence) => presence.to.as_deref(),
+            Stanza::Iq(iq) => iq.to.as_deref(),
+        }
+    }
+}
+
+impl From<Stanza> for Element {
+    fn from(stanza: Stanza) -> Self {
+        match stanza {
+            Stanza::Message(message) => message.into(),
+            Stanza::Presence(presence) => presence.into(),
+            Stanza::Iq(iq) => iq.into(),
+        }
+    }
+}
+
+impl XmlCustomSerialize for Stanza {
+    fn into_string(&self) -> String {
+        match self {
+            Stanza::Message(message) => message.into_string(),
+            Stanza::Presence(presence) => presence.into_string(),
+            Stanza::Iq(iq) => iq.into_string(),
+        }
+    }
+}
+
+impl XmlCustomDeserialize for Stanza {
+    fn from_string(value: &str) -> Result<Self, Error> {
+        if let Ok(iq) = Iq::from_string(value) {
+            return Ok(Stanza::Iq(iq));
+        }
+        if let Ok(presence) = Presence::from_string(value) {
+            return Ok(Stanza::Presence(presence));
+        }
+        Ok(Stanza::Message(Message::from_string(value)?))
+    }
+}
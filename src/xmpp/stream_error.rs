@@ -0,0 +1,36 @@
+use std::fmt;
+
+use super::{handshake::STREAM_NS, serialize::XmlElement};
+
+pub const STREAMS_NS: &str = "urn:ietf:params:xml:ns:xmpp-streams";
+
+/// `<stream:error/>`, sent right before closing the stream, see RFC 6120 section 4.9.
+#[derive(Debug, Clone, PartialEq, Eq, XmlElement)]
+#[xml(name = "stream:error", xmlns = STREAM_NS)]
+pub struct StreamError {
+    /// Defined condition such as `host-unknown` or `not-authorized`
+    #[xml(condition, xmlns = STREAMS_NS)]
+    pub condition: String,
+    #[xml(text = "text", xmlns = STREAMS_NS)]
+    pub text: Option<String>,
+}
+
+impl StreamError {
+    pub fn new(condition: &str) -> Self {
+        Self {
+            condition: condition.to_string(),
+            text: None,
+        }
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.text {
+            Some(text) => write!(f, "{} ({})", self.condition, text),
+            None => write!(f, "{}", self.condition),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use mini_jabber::*;
+
+/// A file path of its own for each test, removed when the test ends.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("mini-jabber-{}-{}", std::process::id(), name));
+        std::fs::remove_file(&path).ok();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+#[test]
+fn passwords_are_hashed_with_argon2() {
+    let file = TempFile::new("argon2");
+    let mut accounts = Accounts::new(RegistrationPolicy::Open);
+    accounts
+        .create("zet", HashedPassword::new("hunter2").unwrap())
+        .unwrap();
+    accounts.save(&file.0).unwrap();
+
+    let saved = std::fs::read_to_string(&file.0).unwrap();
+    assert!(saved.starts_with("zet $argon2id$"), "{}", saved);
+    assert!(!saved.contains("hunter2"));
+
+    let accounts = Accounts::load(RegistrationPolicy::Open, &file.0).unwrap();
+    assert!(accounts.authenticate("zet", "hunter2"));
+    assert!(!accounts.authenticate("zet", "hunter3"));
+    assert!(!accounts.authenticate("amy", "hunter2"));
+}
+
+#[test]
+fn consumed_invites_stay_consumed() {
+    let file = TempFile::new("invites");
+    let mut accounts = Accounts::new(RegistrationPolicy::InviteOnly);
+    accounts.add_invite("welcome".to_string());
+    let password = || HashedPassword::new("hunter2").unwrap();
+    accounts
+        .register("zet", password(), Some("welcome"))
+        .unwrap();
+    accounts.save(&file.0).unwrap();
+
+    // The server adds the invites of its configuration at each start
+    let mut accounts = Accounts::load(RegistrationPolicy::InviteOnly, &file.0).unwrap();
+    accounts.add_invite("welcome".to_string());
+    assert_eq!(
+        accounts.register("amy", password(), Some("welcome")),
+        Err(AccountError::Forbidden)
+    );
+    assert!(accounts.authenticate("zet", "hunter2"));
+}
+
+#[test]
+fn empty_passwords_are_refused() {
+    assert!(matches!(
+        HashedPassword::new(""),
+        Err(AccountError::NotAcceptable)
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_owner_reads_the_accounts_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file = TempFile::new("mode");
+    std::fs::write(&file.0, "").unwrap();
+    std::fs::set_permissions(&file.0, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut accounts = Accounts::new(RegistrationPolicy::Open);
+    accounts
+        .create("zet", HashedPassword::new("hunter2").unwrap())
+        .unwrap();
+    accounts.save(&file.0).unwrap();
+    let mode = std::fs::metadata(&file.0).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
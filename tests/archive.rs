@@ -0,0 +1,196 @@
+use mini_jabber::*;
+
+fn message(id: &str, body: &str) -> Message {
+    Message {
+        id: Some(id.to_string()),
+        to: Some("amy@localhost".to_string()),
+        body: Some(body.to_string()),
+        ..Default::default()
+    }
+}
+
+fn correction(id: &str, body: &str) -> Message {
+    Message {
+        replace: Some(Replace { id: id.to_string() }),
+        ..message("correction", body)
+    }
+}
+
+fn retraction(id: &str) -> Message {
+    Message {
+        id: Some("retraction".to_string()),
+        retract: Some(Retract { id: id.to_string() }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn corrections_keep_the_previous_bodies() {
+    let mut archive = Archive::new(100);
+    let id = archive
+        .apply("zet@localhost", &message("1", "helo"))
+        .unwrap()
+        .unwrap();
+    archive
+        .apply("zet@localhost", &correction("1", "hello"))
+        .unwrap();
+    archive
+        .apply("zet@localhost/laptop", &correction("1", "hello!"))
+        .unwrap();
+
+    for jid in ["zet@localhost", "amy@localhost"] {
+        let original = archive.get(jid, &id).unwrap();
+        assert_eq!(original.body.as_deref(), Some("hello!"));
+        assert_eq!(original.edits, ["helo", "hello"]);
+        assert!(original.edited_at.is_some());
+    }
+}
+
+#[test]
+fn only_the_sender_corrects_or_retracts() {
+    let mut archive = Archive::new(100);
+    let id = archive
+        .apply("zet@localhost", &message("1", "hello"))
+        .unwrap()
+        .unwrap();
+
+    assert!(archive
+        .apply("amy@localhost", &correction("1", "bye"))
+        .is_err());
+    assert!(archive.apply("amy@localhost", &retraction("1")).is_err());
+    let original = archive.get("amy@localhost", &id).unwrap();
+    assert_eq!(original.body.as_deref(), Some("hello"));
+    assert!(!original.retracted);
+}
+
+#[test]
+fn retractions_leave_tombstones() {
+    let mut archive = Archive::new(100);
+    let id = archive
+        .apply("zet@localhost", &message("1", "helo"))
+        .unwrap()
+        .unwrap();
+    archive
+        .apply("zet@localhost", &correction("1", "hello"))
+        .unwrap();
+    archive.apply("zet@localhost", &retraction("1")).unwrap();
+
+    let original = archive.get("zet@localhost", &id).unwrap();
+    assert!(original.retracted);
+    assert_eq!(original.body, None);
+    assert_eq!(original.edits, ["helo", "hello"]);
+
+    let (page, complete) = archive.page("amy@localhost", None, None, None, 10).unwrap();
+    assert_eq!(page.len(), 1);
+    assert!(page[0].retracted);
+    assert_eq!(page[0].body, None);
+    assert!(complete);
+    assert!(matches!(
+        archive.apply("zet@localhost", &correction("1", "hi")),
+        Err(Error::Stanza(_))
+    ));
+}
+
+#[test]
+fn messages_to_a_full_jid_reach_the_recipient() {
+    let mut archive = Archive::new(100);
+    let to_resource = Message {
+        to: Some("amy@localhost/phone".to_string()),
+        ..message("1", "hello")
+    };
+    let id = archive
+        .apply("zet@localhost/laptop", &to_resource)
+        .unwrap()
+        .unwrap();
+
+    assert!(archive.get("amy@localhost", &id).is_some());
+    assert!(archive.get("zet@localhost", &id).is_some());
+    let (page, _) = archive
+        .page("amy@localhost", Some("zet@localhost"), None, None, 10)
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert!(archive.messages_for("bob@localhost").next().is_none());
+}
+
+#[test]
+fn stanza_ids_do_not_depend_on_client_ids() {
+    let mut archive = Archive::new(100);
+    let first = archive
+        .apply("zet@localhost", &message("1", "hello"))
+        .unwrap();
+    let second = archive
+        .apply("bob@localhost", &message("1", "hello"))
+        .unwrap();
+    assert!(first.is_some());
+    assert_ne!(first, second);
+}
+
+#[test]
+fn each_user_keeps_the_latest_messages() {
+    let mut archive = Archive::new(2);
+    for id in ["1", "2", "3"] {
+        archive
+            .apply("zet@localhost", &message(id, "hello"))
+            .unwrap();
+    }
+    let bob = Message {
+        to: Some("bob@localhost".to_string()),
+        ..message("4", "hello")
+    };
+    archive.apply("zet@localhost", &bob).unwrap();
+
+    let origin_ids = |jid: &str| {
+        let ids: Vec<String> = archive
+            .messages_for(jid)
+            .filter_map(|message| message.origin_id.clone())
+            .collect();
+        ids.join(",")
+    };
+    assert_eq!(origin_ids("zet@localhost"), "3,4");
+    assert_eq!(origin_ids("amy@localhost"), "2,3");
+    assert_eq!(origin_ids("bob@localhost"), "4");
+}
+
+#[test]
+fn pages_around_a_known_message() {
+    let mut archive = Archive::new(100);
+    let ids: Vec<String> = ["1", "2", "3"]
+        .into_iter()
+        .map(|id| {
+            archive
+                .apply("zet@localhost", &message(id, "hello"))
+                .unwrap()
+                .unwrap()
+        })
+        .collect();
+
+    let origin_ids = |(page, complete): (Vec<&ArchivedMessage>, bool)| {
+        let ids: Vec<&str> = page
+            .iter()
+            .filter_map(|message| message.origin_id.as_deref())
+            .collect();
+        (ids.join(","), complete)
+    };
+    let page = |after: Option<&str>, before: Option<&str>| {
+        archive
+            .page("zet@localhost", None, after, before, 2)
+            .map(origin_ids)
+    };
+    assert_eq!(page(None, Some("")).unwrap(), ("2,3".to_string(), false));
+    assert_eq!(page(None, Some(&ids[1])).unwrap(), ("1".to_string(), true));
+    assert_eq!(page(None, None).unwrap(), ("1,2".to_string(), false));
+    assert_eq!(
+        page(Some(&ids[0]), None).unwrap(),
+        ("2,3".to_string(), true)
+    );
+    assert_eq!(
+        page(Some(&ids[0]), Some(&ids[2])).unwrap(),
+        ("2".to_string(), false)
+    );
+    for unknown in [(None, Some("1")), (Some("unknown"), None)] {
+        match page(unknown.0, unknown.1) {
+            Err(Error::Stanza(error)) => assert_eq!(error.condition, "item-not-found"),
+            other => panic!("unexpected page {:?}", other),
+        }
+    }
+}
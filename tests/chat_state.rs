@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use mini_jabber::*;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// A notifier talking to `amy@localhost`, who showed support for chat states.
+fn notifier() -> ChatStateNotifier {
+    let mut notifier = ChatStateNotifier::new(INTERVAL);
+    let message = Message {
+        chat_state: Some(ChatState::Active),
+        body: Some("hi".to_string()),
+        ..Default::default()
+    };
+    notifier.incoming_message("amy@localhost", &message);
+    notifier
+}
+
+#[test]
+fn composing_turns_into_paused_then_inactive() {
+    assert_eq!(ChatState::after_idle(Duration::ZERO), ChatState::Composing);
+    assert_eq!(
+        ChatState::after_idle(PAUSED_AFTER - Duration::from_millis(1)),
+        ChatState::Composing
+    );
+    assert_eq!(ChatState::after_idle(PAUSED_AFTER), ChatState::Paused);
+    assert_eq!(ChatState::after_idle(INACTIVE_AFTER), ChatState::Inactive);
+}
+
+#[test]
+fn paused_waits_for_the_interval() {
+    let mut notifier = notifier();
+    let start = Instant::now();
+    assert_eq!(
+        notifier.notify("amy@localhost", ChatState::Composing, start),
+        Some(ChatState::Composing)
+    );
+    assert_eq!(
+        notifier.notify("amy@localhost", ChatState::Paused, start + INTERVAL / 2),
+        None
+    );
+    assert_eq!(
+        notifier.notify("amy@localhost", ChatState::Paused, start + INTERVAL),
+        Some(ChatState::Paused)
+    );
+}
+
+#[test]
+fn repeated_states_are_suppressed() {
+    let mut notifier = notifier();
+    let start = Instant::now();
+    assert!(notifier
+        .notify("amy@localhost", ChatState::Composing, start)
+        .is_some());
+    for seconds in 1..5 {
+        let now = start + Duration::from_secs(seconds);
+        assert_eq!(
+            notifier.notify("amy@localhost", ChatState::Composing, now),
+            None
+        );
+    }
+}
+
+#[test]
+fn gone_is_never_throttled() {
+    let mut notifier = notifier();
+    let start = Instant::now();
+    notifier.notify("amy@localhost", ChatState::Composing, start);
+    assert_eq!(
+        notifier.notify("amy@localhost", ChatState::Gone, start),
+        Some(ChatState::Gone)
+    );
+    assert!(notifier.active_contacts().is_empty());
+}
+
+#[test]
+fn only_contacts_with_support_get_notifications() {
+    let mut notifier = ChatStateNotifier::new(INTERVAL);
+    let now = Instant::now();
+    assert_eq!(
+        notifier.outgoing_message("bob@localhost", now),
+        Some(ChatState::Active)
+    );
+    assert_eq!(
+        notifier.notify("bob@localhost", ChatState::Composing, now),
+        None
+    );
+
+    let reply = Message {
+        body: Some("hello".to_string()),
+        ..Default::default()
+    };
+    notifier.incoming_message("bob@localhost", &reply);
+    assert_eq!(
+        notifier.support("bob@localhost"),
+        ChatStateSupport::Unsupported
+    );
+    assert_eq!(notifier.outgoing_message("bob@localhost", now), None);
+}
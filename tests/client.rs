@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use futures_util::SinkExt;
+use mini_jabber::*;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// Plays a server that offers STARTTLS over a plain WebSocket, then returns the first stanza
+/// the client sends once the stream restarted, if any.
+async fn serve_once(listener: TcpListener) -> Option<String> {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+    for start_tls in [true, false] {
+        let header = StreamHeader::from_string(&stream.get_next_text().await?).unwrap();
+        let response = StreamHeaderResponse {
+            from: "localhost".to_string(),
+            ..header.into_response("1".to_string())
+        };
+        stream
+            .send(WsMessage::Text(response.into_string()))
+            .await
+            .unwrap();
+        let features = StreamFeatures {
+            start_tls: start_tls.then(|| StartTls {
+                xmlns: TLS_NS.to_string(),
+                required: true,
+            }),
+            mechanisms: Some(Mechanisms {
+                xmlns: SASL_NS.to_string(),
+                mechanisms: vec![Mechanism("PLAIN".to_string())],
+            }),
+            dialback: false,
+        };
+        stream
+            .send(WsMessage::Text(features.into_string()))
+            .await
+            .unwrap();
+
+        if start_tls {
+            StartTls::from_string(&stream.get_next_text().await?).unwrap();
+            stream
+                .send(WsMessage::Text(StartTlsProceed().into_string()))
+                .await
+                .unwrap();
+        }
+    }
+    stream.get_next_text().await
+}
+
+/// Plays a server that has nothing to negotiate and never answers.
+async fn serve_silently(listener: TcpListener) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+    let header = StreamHeader::from_string(&stream.get_next_text().await.unwrap()).unwrap();
+    let response = header.into_response("1".to_string());
+    let features = StreamFeatures {
+        start_tls: None,
+        mechanisms: None,
+        dialback: false,
+    };
+    for stanza in [response.into_string(), features.into_string()] {
+        stream.send(WsMessage::Text(stanza)).await.unwrap();
+    }
+    while stream.get_next_text().await.is_some() {}
+}
+
+/// Connects with `tls`, the default policy when `None`.
+async fn connect(tls: Option<TlsPolicy>) -> (Result<Client, Error>, Option<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(serve_once(listener));
+
+    let mut builder = ClientBuilder::new("zet@localhost", "hunter2")
+        .server(&url)
+        .reconnect(ReconnectPolicy::never());
+    if let Some(tls) = tls {
+        builder = builder.tls(tls);
+    }
+    let client = builder.connect().await;
+    (client, server.await.unwrap())
+}
+
+/// Going through STARTTLS over `ws://` does not encrypt anything.
+#[tokio::test]
+async fn required_tls_refuses_plain_connections() {
+    let (client, sent) = connect(Some(TlsPolicy::Required)).await;
+    assert!(matches!(client, Err(Error::Tls(_))));
+    assert_eq!(sent, None);
+}
+
+/// Passwords never go over `ws://` unless asked to.
+#[tokio::test]
+async fn plain_is_refused_over_ws_by_default() {
+    let (client, sent) = connect(None).await;
+    assert!(matches!(client, Err(Error::Tls(_))));
+    assert_eq!(sent, None);
+}
+
+#[tokio::test]
+async fn unanswered_requests_time_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve_silently(listener));
+
+    let client = ClientBuilder::new("zet@localhost", "hunter2")
+        .server(&url)
+        .reconnect(ReconnectPolicy::never())
+        .request_timeout(Duration::from_millis(100))
+        .connect()
+        .await
+        .unwrap();
+    let ping = Iq::new(IqType::Get, next_stanza_id(), None);
+    assert!(matches!(client.request(ping).await, Err(Error::Timeout)));
+}
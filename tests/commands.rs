@@ -0,0 +1,374 @@
+use std::{sync::Mutex, time::Duration};
+
+use mini_jabber::*;
+
+const PIZZA: &str = "urn:example:pizza";
+
+/// Asks for a size, then for toppings that depend on the size.
+struct OrderPizza;
+
+impl AdHocCommand<()> for OrderPizza {
+    fn node(&self) -> &str {
+        PIZZA
+    }
+
+    fn name(&self) -> &str {
+        "Order Pizza"
+    }
+
+    fn stage_count(&self) -> usize {
+        2
+    }
+
+    fn form(&self, stage: usize, submitted: &[DataForm], _context: &()) -> DataForm {
+        let mut form = DataForm::with_form_type(FormType::Form, PIZZA);
+        match stage {
+            0 => form.fields.push(
+                Field::new("size", FieldType::ListSingle)
+                    .with_option(None, "small")
+                    .with_option(None, "large")
+                    .required(),
+            ),
+            _ => {
+                let size = submitted[0].value("size").unwrap_or_default();
+                form.title = Some(format!("Toppings for a {} pizza", size));
+                form.fields
+                    .push(Field::new("toppings", FieldType::TextSingle).required());
+            }
+        }
+        form
+    }
+
+    fn execute(
+        &self,
+        _requester: &str,
+        submitted: &[DataForm],
+        _context: &(),
+    ) -> Result<CommandOutcome, StanzaError> {
+        let size = submitted[0].value("size").unwrap_or_default();
+        let toppings = submitted[1].value("toppings").unwrap_or_default();
+        Ok(CommandOutcome {
+            note: Some(CommandNote::info(&format!(
+                "Ordered a {} pizza with {}",
+                size, toppings
+            ))),
+            form: None,
+        })
+    }
+}
+
+fn request(session_id: &str, action: CommandAction, values: &[(&str, &str)]) -> Command {
+    let mut form = DataForm::with_form_type(FormType::Submit, PIZZA);
+    for (var, value) in values {
+        form.set_values(var, vec![value.to_string()]);
+    }
+    Command {
+        session_id: Some(session_id.to_string()),
+        action: Some(action),
+        form: (!values.is_empty()).then_some(form),
+        ..Command::execute(PIZZA)
+    }
+}
+
+#[test]
+fn two_stage_commands_go_back_and_forth() {
+    let mut registry = CommandRegistry::new();
+    registry.register(Box::new(OrderPizza));
+    let requester = "zet@localhost/laptop";
+
+    let response = registry
+        .handle(requester, &Command::execute(PIZZA), &())
+        .unwrap();
+    assert_eq!(response.status, Some(CommandStatus::Executing));
+    assert_eq!(response.actions, [CommandAction::Next]);
+    assert!(response.form.unwrap().field("size").is_some());
+    let session_id = response.session_id.unwrap();
+
+    let error = registry
+        .handle(
+            requester,
+            &request(&session_id, CommandAction::Complete, &[("size", "large")]),
+            &(),
+        )
+        .unwrap_err();
+    assert_eq!(error.condition, "bad-request");
+
+    let response = registry
+        .handle(
+            requester,
+            &request(&session_id, CommandAction::Next, &[("size", "large")]),
+            &(),
+        )
+        .unwrap();
+    assert_eq!(
+        response.actions,
+        [CommandAction::Complete, CommandAction::Prev]
+    );
+    let form = response.form.unwrap();
+    assert_eq!(form.title.as_deref(), Some("Toppings for a large pizza"));
+
+    let response = registry
+        .handle(
+            requester,
+            &request(&session_id, CommandAction::Prev, &[]),
+            &(),
+        )
+        .unwrap();
+    assert_eq!(response.actions, [CommandAction::Next]);
+    assert!(response.form.unwrap().field("size").is_some());
+
+    let response = registry
+        .handle(
+            requester,
+            &request(&session_id, CommandAction::Next, &[("size", "small")]),
+            &(),
+        )
+        .unwrap();
+    let form = response.form.unwrap();
+    assert_eq!(form.title.as_deref(), Some("Toppings for a small pizza"));
+
+    // Only the requester who started the session may continue it
+    assert!(registry
+        .handle(
+            "amy@localhost/phone",
+            &request(&session_id, CommandAction::Complete, &[("toppings", "ham")]),
+            &(),
+        )
+        .is_err());
+
+    let response = registry
+        .handle(
+            requester,
+            &request(
+                &session_id,
+                CommandAction::Complete,
+                &[("toppings", "cheese")],
+            ),
+            &(),
+        )
+        .unwrap();
+    assert_eq!(response.status, Some(CommandStatus::Completed));
+    assert_eq!(
+        response.notes,
+        [CommandNote::info("Ordered a small pizza with cheese")]
+    );
+
+    let error = registry
+        .handle(
+            requester,
+            &request(&session_id, CommandAction::Prev, &[]),
+            &(),
+        )
+        .unwrap_err();
+    assert_eq!(error.text.as_deref(), Some("bad-sessionid"));
+}
+
+struct Server {
+    accounts: Mutex<Accounts>,
+    online: Vec<String>,
+    disconnected: Mutex<Vec<String>>,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self {
+            accounts: Mutex::new(Accounts::new(RegistrationPolicy::Closed)),
+            online: vec![
+                "zet@localhost/laptop".to_string(),
+                "amy@example.org/phone".to_string(),
+            ],
+            disconnected: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ServerAdmin for Server {
+    fn is_admin(&self, jid: &str) -> bool {
+        jid_bare(jid) == "admin@localhost" || self.is_server_admin(jid)
+    }
+
+    fn is_server_admin(&self, jid: &str) -> bool {
+        jid_bare(jid) == "root@localhost"
+    }
+
+    fn accounts(&self, domain: &str) -> Option<&Mutex<Accounts>> {
+        (domain == "localhost").then_some(&self.accounts)
+    }
+
+    fn online_users(&self, domain: &str) -> Vec<String> {
+        self.online
+            .iter()
+            .filter(|jid| jid_domain(jid) == domain)
+            .cloned()
+            .collect()
+    }
+
+    fn announce(&self, _domain: &str, _message: Message) {}
+
+    fn disconnect(&self, jid: &str) {
+        self.disconnected.lock().unwrap().push(jid.to_string());
+    }
+
+    fn shutdown(&self, _delay: Duration, _announcement: Option<String>) {}
+}
+
+/// Runs the single-stage admin command `node` with the `values` of its form.
+fn run_admin_command(
+    registry: &mut CommandRegistry<Server>,
+    server: &Server,
+    requester: &str,
+    node: &str,
+    values: &[(&str, &str)],
+) -> Result<Command, StanzaError> {
+    let session_id = registry
+        .handle(requester, &Command::execute(node), server)?
+        .session_id;
+    let mut form = DataForm::with_form_type(FormType::Submit, ADMIN_NS);
+    for (var, value) in values {
+        form.set_values(var, vec![value.to_string()]);
+    }
+    let request = Command {
+        session_id,
+        action: Some(CommandAction::Complete),
+        form: Some(form),
+        ..Command::execute(node)
+    };
+    registry.handle(requester, &request, server)
+}
+
+#[test]
+fn administrators_only_manage_their_domain() {
+    let server = Server::new();
+    let mut registry = CommandRegistry::new();
+    register_admin_commands(&mut registry);
+    let admin = "admin@localhost/console";
+    let node = "http://jabber.org/protocol/admin#add-user";
+
+    let mut add_user = |jid: &str| {
+        run_admin_command(
+            &mut registry,
+            &server,
+            admin,
+            node,
+            &[
+                ("accountjid", jid),
+                ("password", "hunter2"),
+                ("password-verify", "hunter2"),
+            ],
+        )
+    };
+
+    assert_eq!(
+        add_user("zet@example.org").unwrap_err().condition,
+        "forbidden"
+    );
+    assert_eq!(add_user("zet").unwrap_err().condition, "jid-malformed");
+    assert!(!server.accounts.lock().unwrap().exists("zet"));
+
+    let response = add_user("zet@localhost").unwrap();
+    assert_eq!(response.status, Some(CommandStatus::Completed));
+    assert!(server.accounts.lock().unwrap().exists("zet"));
+}
+
+#[test]
+fn administrators_only_see_the_users_of_their_domain() {
+    let server = Server::new();
+    let mut registry = CommandRegistry::new();
+    register_admin_commands(&mut registry);
+    let node = "http://jabber.org/protocol/admin#get-online-users-list";
+
+    let response = run_admin_command(
+        &mut registry,
+        &server,
+        "admin@localhost",
+        node,
+        &[("max_items", "none")],
+    )
+    .unwrap();
+    let form = response.form.unwrap();
+    assert_eq!(form.values("onlineuserjids"), ["zet@localhost/laptop"]);
+}
+
+#[test]
+fn only_server_administrators_shut_down() {
+    let server = Server::new();
+    let mut registry = CommandRegistry::new();
+    register_admin_commands(&mut registry);
+    let node = "http://jabber.org/protocol/admin#shutdown";
+
+    let error = run_admin_command(&mut registry, &server, "admin@localhost", node, &[]);
+    assert_eq!(error.unwrap_err().condition, "forbidden");
+    let response = run_admin_command(&mut registry, &server, "root@localhost", node, &[]);
+    assert_eq!(response.unwrap().status, Some(CommandStatus::Completed));
+}
+
+#[test]
+fn deleted_users_are_disconnected() {
+    let server = Server::new();
+    server
+        .accounts
+        .lock()
+        .unwrap()
+        .create("zet", HashedPassword::new("hunter2").unwrap())
+        .unwrap();
+    let mut registry = CommandRegistry::new();
+    register_admin_commands(&mut registry);
+
+    run_admin_command(
+        &mut registry,
+        &server,
+        "admin@localhost",
+        "http://jabber.org/protocol/admin#delete-user",
+        &[("accountjids", "zet@localhost/laptop")],
+    )
+    .unwrap();
+    assert!(!server.accounts.lock().unwrap().exists("zet"));
+    assert_eq!(*server.disconnected.lock().unwrap(), ["zet@localhost"]);
+}
+
+#[test]
+fn commands_are_listed_for_those_allowed_to_run_them() {
+    let server = Server::new();
+    let mut registry = CommandRegistry::new();
+    register_admin_commands(&mut registry);
+
+    let request = Iq::from_string(
+        "<iq type='get' id='1'><query xmlns='http://jabber.org/protocol/disco#items' \
+         node='http://jabber.org/protocol/commands'/></iq>",
+    )
+    .unwrap();
+    let Some(IqPayload::DiscoItems(query)) = request.payload else {
+        panic!("expected a disco#items query");
+    };
+    assert_eq!(query.node.as_deref(), Some(COMMANDS_NS));
+
+    let nodes = |requester: &str| {
+        let items = registry.disco_items("localhost", requester, &server);
+        assert_eq!(items.node.as_deref(), Some(COMMANDS_NS));
+        assert!(items.items.iter().all(|item| item.jid == "localhost"));
+        items
+            .items
+            .into_iter()
+            .filter_map(|item| item.node)
+            .collect::<Vec<_>>()
+    };
+    assert!(nodes("zet@localhost").is_empty());
+    let admin = nodes("admin@localhost");
+    assert!(admin.contains(&"http://jabber.org/protocol/admin#add-user".to_string()));
+    assert!(!admin.contains(&"http://jabber.org/protocol/admin#shutdown".to_string()));
+    assert!(
+        nodes("root@localhost").contains(&"http://jabber.org/protocol/admin#shutdown".to_string())
+    );
+
+    let response = Iq::new(
+        IqType::Result,
+        "1".to_string(),
+        Some(IqPayload::DiscoItems(registry.disco_items(
+            "localhost",
+            "admin@localhost",
+            &server,
+        ))),
+    );
+    let parsed = Iq::from_string(&response.into_string()).unwrap();
+    assert_eq!(parsed, response);
+}
@@ -0,0 +1,16 @@
+use mini_jabber::*;
+
+#[test]
+fn component_digests_are_checked() {
+    let digest = component_digest("3BF96D32", "s3cret");
+    assert_eq!(digest.len(), 40);
+    assert!(verify_component_digest("3BF96D32", "s3cret", &digest));
+    assert!(!verify_component_digest("3BF96D32", "guessed", &digest));
+    assert!(!verify_component_digest("3BF96D33", "s3cret", &digest));
+    assert!(!verify_component_digest(
+        "3BF96D32",
+        "s3cret",
+        &digest[..39]
+    ));
+    assert!(!verify_component_digest("3BF96D32", "s3cret", ""));
+}
@@ -0,0 +1,71 @@
+use mini_jabber::*;
+
+/// Error of an invalid configuration, which has to start with the key path `key`.
+fn error(text: &str, key: &str) -> String {
+    let error = ServerConfig::from_toml(text).unwrap_err().to_string();
+    assert!(error.starts_with(&format!("{}: ", key)), "{}", error);
+    error
+}
+
+#[test]
+fn defaults_need_no_file() {
+    let config = ServerConfig::from_toml("").unwrap();
+    assert_eq!(config.limits.max_stanza_size, 64 * 1024);
+
+    let config = ServerConfig::from_toml("[limits]\nmax_depth = 8").unwrap();
+    assert_eq!(config.limits.max_depth, 8);
+}
+
+#[test]
+fn unknown_keys_are_refused() {
+    error("[loging]\nlevel = 'info'", "loging");
+    error("[limits]\nmax_stanza = 10", "limits.max_stanza");
+    error(
+        "[[hosts]]\ndomain = 'a.test'\ncolour = 3",
+        "hosts[0].colour",
+    );
+}
+
+#[test]
+fn values_of_the_wrong_type_are_refused() {
+    let message = error(
+        "[limits]\nmax_stanza_size = 'big'",
+        "limits.max_stanza_size",
+    );
+    assert!(message.contains("positive integer"), "{}", message);
+    error(
+        "[[hosts]]\ndomain = 'a.test'\nregistration = 3",
+        "hosts[0].registration",
+    );
+    error(
+        "[rate_limits]\nlockout_seconds = 1.5",
+        "rate_limits.lockout_seconds",
+    );
+    error(
+        "[modules]\nenabled = ['archive', 'nope']",
+        "modules.enabled[1]",
+    );
+}
+
+#[test]
+fn limits_out_of_range_are_refused() {
+    error("[limits]\nmax_archive_page = 0", "limits.max_archive_page");
+    error("[limits]\nmax_archive_page = -3", "limits.max_archive_page");
+    error(
+        "[limits]\nmax_archived_messages = 0",
+        "limits.max_archived_messages",
+    );
+    let message = error(
+        &format!("[limits]\nmax_depth = {}", MAX_ELEMENT_DEPTH + 1),
+        "limits.max_depth",
+    );
+    assert!(
+        message.contains(&MAX_ELEMENT_DEPTH.to_string()),
+        "{}",
+        message
+    );
+    error(
+        "[limits]\nmax_stanza_size = 4096\n[rate_limits]\nburst_bytes = 1024",
+        "rate_limits.burst_bytes",
+    );
+}
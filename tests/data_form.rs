@@ -0,0 +1,142 @@
+use mini_jabber::*;
+
+const NAMESPACE: &str = "urn:example:signup";
+
+/// A signup form with a required username, a color to pick and a newsletter checkbox.
+fn form() -> DataForm {
+    let mut form = DataForm::with_form_type(FormType::Form, NAMESPACE);
+    form.fields.extend([
+        Field::new("username", FieldType::TextSingle).required(),
+        Field::new("color", FieldType::ListSingle)
+            .with_option(Some("Red"), "red")
+            .with_option(Some("Blue"), "blue"),
+        Field::new("newsletter", FieldType::Boolean),
+    ]);
+    form
+}
+
+fn submission(values: &[(&str, &str)]) -> DataForm {
+    let mut submission = form().submission();
+    for (var, value) in values {
+        submission.set_values(var, vec![value.to_string()]);
+    }
+    submission
+}
+
+#[test]
+fn required_fields_must_be_filled() {
+    let form = form();
+    assert!(form.validate().is_ok());
+    assert!(form
+        .validate_submission(&submission(&[("username", "zet")]))
+        .is_ok());
+
+    let error = form.validate_submission(&submission(&[])).unwrap_err();
+    assert!(
+        matches!(&error, Error::InvalidValue { value, .. } if value == "username"),
+        "{}",
+        error
+    );
+    assert!(form
+        .validate_submission(&submission(&[("username", "")]))
+        .is_err());
+}
+
+#[test]
+fn list_single_values_come_from_the_options() {
+    let form = form();
+    assert!(form
+        .validate_submission(&submission(&[("username", "zet"), ("color", "blue")]))
+        .is_ok());
+
+    let error = form
+        .validate_submission(&submission(&[("username", "zet"), ("color", "green")]))
+        .unwrap_err();
+    assert!(
+        matches!(&error, Error::InvalidValue { value, .. } if value == "green"),
+        "{}",
+        error
+    );
+
+    let mut submission = submission(&[("username", "zet")]);
+    submission.set_values("color", vec!["red".to_string(), "blue".to_string()]);
+    assert!(form.validate_submission(&submission).is_err());
+}
+
+#[test]
+fn booleans_are_0_1_true_or_false() {
+    for (value, expected) in [("1", true), ("true", true), ("0", false), ("false", false)] {
+        let field = Field::new("newsletter", FieldType::Boolean).with_value(value);
+        assert_eq!(field.bool_value(), Some(expected));
+        assert!(form()
+            .validate_submission(&submission(&[("username", "zet"), ("newsletter", value)]))
+            .is_ok());
+    }
+
+    let field = Field::new("newsletter", FieldType::Boolean).with_value("yes");
+    assert_eq!(field.bool_value(), None);
+    assert!(form()
+        .validate_submission(&submission(&[("username", "zet"), ("newsletter", "yes")]))
+        .is_err());
+}
+
+#[test]
+fn form_type_is_hidden_and_must_match() {
+    let form = DataForm::from_string(
+        "<x xmlns='jabber:x:data' type='form'>\
+         <field var='FORM_TYPE' type='hidden'><value>urn:example:signup</value></field>\
+         <field var='username' type='text-single'><required/></field>\
+         </x>",
+    )
+    .unwrap();
+    assert_eq!(form.form_type_value(), Some(NAMESPACE));
+    assert!(form.validate().is_ok());
+
+    // Submissions may leave the type of FORM_TYPE out
+    let submitted = DataForm::from_string(
+        "<x xmlns='jabber:x:data' type='submit'>\
+         <field var='FORM_TYPE'><value>urn:example:signup</value></field>\
+         <field var='username'><value>zet</value></field>\
+         </x>",
+    )
+    .unwrap();
+    assert!(submitted.validate().is_ok());
+    assert!(form.validate_submission(&submitted).is_ok());
+
+    let mut visible = form.clone();
+    visible.fields[0].field_type = Some(FieldType::TextSingle);
+    assert!(visible.validate().is_err());
+
+    let mut other = submitted.clone();
+    other.set_values(FORM_TYPE, vec!["urn:example:other".to_string()]);
+    assert!(form.validate_submission(&other).is_err());
+}
+
+#[test]
+fn items_match_the_reported_fields() {
+    let result = DataForm::from_string(
+        "<x xmlns='jabber:x:data' type='result'>\
+         <reported><field var='jid' type='jid-single'/><field var='name'/></reported>\
+         <item><field var='jid'><value>zet@localhost</value></field>\
+         <field var='name'><value>Zet</value></field></item>\
+         <item><field var='jid'><value>amy@localhost</value></field></item>\
+         </x>",
+    )
+    .unwrap();
+    assert_eq!(result.reported.as_ref().unwrap().len(), 2);
+    assert_eq!(result.items.len(), 2);
+    assert!(result.validate().is_ok());
+
+    let mut unreported = result.clone();
+    unreported.items[1].push(Field::new("email", FieldType::TextSingle));
+    let error = unreported.validate().unwrap_err();
+    assert!(error.to_string().contains("email"), "{}", error);
+
+    let mut without_reported = result.clone();
+    without_reported.reported = None;
+    assert!(without_reported.validate().is_err());
+
+    let mut not_a_result = result;
+    not_a_result.form_type = FormType::Form;
+    assert!(not_a_result.validate().is_err());
+}
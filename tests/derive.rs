@@ -0,0 +1,6 @@
+/// Misuses of `#[derive(XmlElement)]` fail with an error pointing at the culprit.
+#[test]
+fn bad_xml_attributes_do_not_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,152 @@
+use std::{
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use mini_jabber::*;
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const SECRET: &str = "s3cret";
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// A server process serving `domain`, stopped when dropped.
+struct Server {
+    process: Child,
+    s2s_port: u16,
+}
+
+impl Server {
+    fn start(domain: &str, extra_args: &[String]) -> Self {
+        let s2s_port = free_port();
+        let process = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--domain", domain, "--dialback-secret", SECRET])
+            .args(["--port", &free_port().to_string()])
+            .args(["--s2s-port", &s2s_port.to_string()])
+            .args(["--component-port", &free_port().to_string()])
+            .args(extra_args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let started = Instant::now();
+        while std::net::TcpStream::connect(("127.0.0.1", s2s_port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "server did not start"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Self { process, s2s_port }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+/// Opens a stream from `a.test` to the server of `b.test` and asks it to accept `key`, the
+/// result of a key computed from the stream id.
+async fn dialback(port: u16, key: impl FnOnce(&str) -> String) -> Option<DialbackType> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let (mut writer, mut reader) = XmlStream::new(stream, None).split();
+
+    let header = StreamHeader {
+        from: Some("a.test".to_string()),
+        to: "b.test".to_string(),
+        version: "1.0".to_string(),
+        xml_lang: "en".to_string(),
+        xmlns: SERVER_NS.to_string(),
+        xmlns_stream: "http://etherx.jabber.org/streams".to_string(),
+    };
+    writer
+        .send(WsMessage::Text(header.into_string()))
+        .await
+        .unwrap();
+    let response = reader.get_next_text().await.unwrap();
+    let id = StreamHeaderResponse::from_string(&response).unwrap().id;
+    let features = reader.get_next_text().await.unwrap();
+    assert!(StreamFeatures::from_string(&features).unwrap().dialback);
+
+    let request = DialbackResult {
+        from: "a.test".to_string(),
+        to: "b.test".to_string(),
+        key: Some(key(&id)),
+        result_type: None,
+    };
+    writer
+        .send(WsMessage::Text(request.into_string()))
+        .await
+        .unwrap();
+    let response = reader.get_next_text().await.unwrap();
+    writer.close().await.ok();
+    DialbackResult::from_string(&response).unwrap().result_type
+}
+
+/// `b.test` checks the keys it is given with the authoritative server of `a.test`.
+#[tokio::test]
+async fn dialback_between_two_servers() {
+    let a = Server::start("a.test", &[]);
+    let route = format!("a.test=127.0.0.1:{}", a.s2s_port);
+    let b = Server::start("b.test", &["--host".to_string(), route]);
+
+    let valid = dialback(b.s2s_port, |id| {
+        dialback_key(SECRET, "b.test", "a.test", id)
+    });
+    assert_eq!(
+        timeout(Duration::from_secs(10), valid).await.unwrap(),
+        Some(DialbackType::Valid)
+    );
+
+    let invalid = dialback(b.s2s_port, |id| {
+        dialback_key("guessed", "b.test", "a.test", id)
+    });
+    assert_eq!(
+        timeout(Duration::from_secs(10), invalid).await.unwrap(),
+        Some(DialbackType::Invalid)
+    );
+
+    drop((a, b));
+}
+
+/// Streams from other servers are held to the same limits as those of clients.
+#[tokio::test]
+async fn restricted_xml_closes_server_streams() {
+    let b = Server::start("b.test", &[]);
+    let stream = TcpStream::connect(("127.0.0.1", b.s2s_port)).await.unwrap();
+    let (mut writer, mut reader) = XmlStream::new(stream, None).split();
+
+    let header = StreamHeader {
+        from: Some("a.test".to_string()),
+        to: "b.test".to_string(),
+        version: "1.0".to_string(),
+        xml_lang: "en".to_string(),
+        xmlns: SERVER_NS.to_string(),
+        xmlns_stream: "http://etherx.jabber.org/streams".to_string(),
+    };
+    for stanza in [
+        header.into_string(),
+        "<presence><!-- hi --></presence>".to_string(),
+    ] {
+        writer.send(WsMessage::Text(stanza)).await.unwrap();
+    }
+    let response = async {
+        reader.get_next_text().await.unwrap();
+        reader.get_next_text().await.unwrap();
+        reader.get_next_text().await
+    };
+    let error = timeout(Duration::from_secs(10), response).await.unwrap();
+    let error = StreamError::from_string(&error.unwrap()).unwrap();
+    assert_eq!(error.condition, "restricted-xml");
+    assert_eq!(reader.get_next_text().await, None);
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use futures_util::SinkExt;
+use mini_jabber::*;
+use tokio::{net::TcpListener, time::timeout};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// Two peers exchange a message on a stream, without discovering each other.
+#[tokio::test]
+async fn peers_exchange_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let bob = Peer {
+        name: "bob@loopback".to_string(),
+        address: listener.local_addr().unwrap(),
+        presence: PeerPresence::default(),
+    };
+
+    let accepting = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (from, mut stream) = accept_peer("bob@loopback", stream).await.unwrap();
+        let message = stream.get_next_text().await.unwrap();
+        let closed = stream.get_next_text().await.is_none();
+        (from, Message::from_string(&message).unwrap(), closed)
+    });
+
+    let mut stream = connect_peer("alice@loopback", &bob).await.unwrap();
+    let message = Message {
+        from: Some("alice@loopback".to_string()),
+        to: Some("bob@loopback".to_string()),
+        body: Some("hi".to_string()),
+        ..Default::default()
+    };
+    stream
+        .send(WsMessage::Text(message.into_string()))
+        .await
+        .unwrap();
+    stream.close().await.unwrap();
+
+    let (from, received, closed) = timeout(Duration::from_secs(5), accepting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(from, "alice@loopback");
+    assert_eq!(received, message);
+    assert!(closed);
+}
+
+#[tokio::test]
+async fn streams_for_someone_else_are_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let carol = Peer {
+        name: "carol@loopback".to_string(),
+        address: listener.local_addr().unwrap(),
+        presence: PeerPresence::default(),
+    };
+
+    let accepting = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        accept_peer("bob@loopback", stream).await.is_err()
+    });
+    assert!(connect_peer("alice@loopback", &carol).await.is_err());
+    assert!(accepting.await.unwrap());
+}
+
+/// Two peers on the loopback interface find each other and exchange a message. Needs
+/// multicast on the loopback interface, which CI machines and containers often lack.
+#[tokio::test]
+#[ignore = "needs multicast on the loopback interface, run with --ignored"]
+async fn peers_discover_each_other_on_loopback() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let bob_port = listener.local_addr().unwrap().port();
+
+    let bob_presence = PeerPresence {
+        nick: Some("Bob".to_string()),
+        status: PeerStatus::Away,
+        msg: Some("lunch".to_string()),
+        ..Default::default()
+    };
+    let bob = LinkLocal::advertise("bob@loopback", bob_port, &bob_presence, Some("lo")).unwrap();
+    let alice =
+        LinkLocal::advertise("alice@loopback", 1, &PeerPresence::default(), Some("lo")).unwrap();
+
+    let mut events = alice.browse().unwrap();
+    let peer = timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(PeerEvent::Available(peer)) = events.recv().await {
+                if peer.name == "bob@loopback" {
+                    return peer;
+                }
+            }
+        }
+    })
+    .await
+    .expect("bob was not discovered");
+    assert_eq!(peer.address.port(), bob_port);
+    assert_eq!(peer.presence, bob_presence);
+
+    let accepting = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (from, mut stream) = accept_peer("bob@loopback", stream).await.unwrap();
+        let message = stream.get_next_text().await.unwrap();
+        (from, Message::from_string(&message).unwrap())
+    });
+
+    let mut stream = connect_peer("alice@loopback", &peer).await.unwrap();
+    let message = Message {
+        from: Some("alice@loopback".to_string()),
+        to: Some("bob@loopback".to_string()),
+        body: Some("hi".to_string()),
+        ..Default::default()
+    };
+    stream
+        .send(WsMessage::Text(message.into_string()))
+        .await
+        .unwrap();
+
+    let (from, received) = timeout(Duration::from_secs(5), accepting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(from, "alice@loopback");
+    assert_eq!(received, message);
+
+    alice.shutdown();
+    bob.shutdown();
+}
@@ -0,0 +1,166 @@
+use mini_jabber::*;
+
+fn stream_error<T>(result: Result<T, Error>) -> StreamError {
+    match result {
+        Err(Error::Stream(error)) => error,
+        Err(error) => panic!("expected a stream error, got {}", error),
+        Ok(_) => panic!("expected a stream error"),
+    }
+}
+
+#[test]
+fn stream_header_with_another_prefix() {
+    let header = StreamHeader::from_string(
+        "<s:stream xmlns:s='http://etherx.jabber.org/streams' xmlns='jabber:client' \
+         version='1.0' xml:lang='en' to='localhost'>",
+    )
+    .unwrap();
+    assert_eq!(header.xmlns, CLIENT_NS);
+    assert_eq!(header.xmlns_stream, STREAM_NS);
+    assert_eq!(header.to, "localhost");
+}
+
+#[test]
+fn features_with_another_prefix() {
+    let features = StreamFeatures::from_string(
+        "<s:features xmlns:s='http://etherx.jabber.org/streams'>\
+         <tls:starttls xmlns:tls='urn:ietf:params:xml:ns:xmpp-tls'><tls:required/></tls:starttls>\
+         <mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'><mechanism>PLAIN</mechanism></mechanisms>\
+         </s:features>",
+    )
+    .unwrap();
+    assert!(features.start_tls.unwrap().required);
+    let mechanisms = features.mechanisms.unwrap().mechanisms;
+    assert_eq!(mechanisms.len(), 1);
+    assert_eq!(mechanisms[0].0, "PLAIN");
+}
+
+#[test]
+fn features_in_the_default_namespace() {
+    let features = StreamFeatures::from_string(
+        "<features xmlns='http://etherx.jabber.org/streams'>\
+         <dialback xmlns='urn:xmpp:features:dialback'/></features>",
+    )
+    .unwrap();
+    assert!(features.dialback);
+    assert!(features.start_tls.is_none());
+}
+
+#[test]
+fn starttls_with_a_prefix() {
+    let start_tls =
+        StartTls::from_string("<t:starttls xmlns:t='urn:ietf:params:xml:ns:xmpp-tls'/>").unwrap();
+    assert_eq!(start_tls.xmlns, TLS_NS);
+    assert!(!start_tls.required);
+}
+
+#[test]
+fn stream_error_with_another_prefix() {
+    let error = StreamError::from_string(
+        "<e:error xmlns:e='http://etherx.jabber.org/streams'>\
+         <host-unknown xmlns='urn:ietf:params:xml:ns:xmpp-streams'/></e:error>",
+    )
+    .unwrap();
+    assert_eq!(error.condition, "host-unknown");
+}
+
+#[test]
+fn dialback_with_another_prefix() {
+    let result = DialbackResult::from_string(
+        "<dialback:result xmlns:dialback='jabber:server:dialback' from='a' to='b'>key</dialback:result>",
+    )
+    .unwrap();
+    assert_eq!(result.from, "a");
+    assert_eq!(result.key.as_deref(), Some("key"));
+}
+
+#[test]
+fn starttls_in_the_wrong_namespace() {
+    let error = stream_error(StartTls::from_string(
+        "<starttls xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>",
+    ));
+    assert_eq!(error.condition, "invalid-namespace");
+}
+
+#[test]
+fn features_in_the_wrong_namespace() {
+    let error = stream_error(StreamFeatures::from_string(
+        "<stream:features xmlns:stream='jabber:client'/>",
+    ));
+    assert_eq!(error.condition, "invalid-namespace");
+}
+
+#[test]
+fn dialback_in_the_wrong_namespace() {
+    let error = stream_error(DialbackResult::from_string(
+        "<db:result xmlns:db='jabber:server' from='a' to='b'>key</db:result>",
+    ));
+    assert_eq!(error.condition, "invalid-namespace");
+}
+
+#[test]
+fn undeclared_prefix() {
+    let error = stream_error(StartTls::from_string("<tls:starttls/>"));
+    assert_eq!(error.condition, "bad-namespace-prefix");
+}
+
+#[test]
+fn payloads_use_prefixes_declared_on_the_stanza() {
+    let message = Message::from_string(
+        "<message xmlns:e='urn:example:e' to='amy@localhost'>\
+         <thread e:parent='1'>t</thread><e:flag/></message>",
+    )
+    .unwrap();
+    assert_eq!(message.payloads.len(), 2);
+    let thread = &message.payloads[0];
+    assert_eq!(thread.attr("e:parent"), Some("1"));
+    assert_eq!(thread.attr("xmlns:e"), Some("urn:example:e"));
+    assert!(message.payloads[1].is("flag", "urn:example:e"));
+
+    // Payloads carry the declarations they use and read the same on their own
+    let reparsed = Message::from_string(&message.into_string()).unwrap();
+    assert_eq!(reparsed.payloads, message.payloads);
+
+    let presence =
+        Presence::from_string("<presence xmlns:e='urn:example:e'><e:status-icon/></presence>")
+            .unwrap();
+    assert!(presence.payloads[0].is("status-icon", "urn:example:e"));
+
+    let iq = Iq::from_string(
+        "<iq xmlns:e='urn:example:e' id='1' type='get'><e:query xmlns='urn:example:e'/></iq>",
+    )
+    .unwrap();
+    assert!(
+        matches!(iq.payload, Some(IqPayload::Other(query)) if query.is("query", "urn:example:e"))
+    );
+}
+
+#[test]
+fn payloads_that_do_not_parse_fail_the_stanza() {
+    let error = stream_error(Message::from_string(
+        "<message to='amy@localhost'><body>hi</body><e:flag/></message>",
+    ));
+    assert_eq!(error.condition, "bad-namespace-prefix");
+    assert!(Presence::from_string("<presence><e:status-icon/></presence>").is_err());
+    assert!(
+        Iq::from_string("<iq id='1' type='get'><e:query xmlns='urn:example:e'/></iq>").is_err()
+    );
+}
+
+#[test]
+fn deep_payloads_convert_to_elements() {
+    let mut payload = Element::new("x", "urn:example:e");
+    for _ in 0..MAX_ELEMENT_DEPTH {
+        payload = Element::new("x", "urn:example:e").with_child(payload);
+    }
+    let message = Message {
+        body: Some("hi".to_string()),
+        payloads: vec![payload.clone()],
+        ..Default::default()
+    };
+
+    let element = Element::from(Stanza::Message(message));
+    assert!(element.is("message", ""));
+    assert_eq!(element.find("body").unwrap().text(), "hi");
+    assert_eq!(element.find("{urn:example:e}x"), Some(&payload));
+}
@@ -0,0 +1,66 @@
+use std::{thread::sleep, time::Duration};
+
+use mini_jabber::*;
+
+#[test]
+fn buckets_refill_up_to_the_burst() {
+    let mut bucket = TokenBucket::new(100, 10);
+    assert!(bucket.is_full());
+    assert_eq!(bucket.take(10), None);
+    assert!(!bucket.is_full());
+
+    // 100 tokens per second refill the 10 taken in 100ms
+    sleep(Duration::from_millis(150));
+    assert!(bucket.is_full());
+    assert_eq!(bucket.take(10), None);
+}
+
+#[test]
+fn debt_is_paid_back_by_waiting() {
+    let mut bucket = TokenBucket::new(10, 10);
+    let wait = bucket.take(15).unwrap();
+    // 5 tokens in debt at 10 per second
+    assert!(
+        wait > Duration::from_millis(450) && wait <= Duration::from_millis(500),
+        "{:?}",
+        wait
+    );
+
+    let wait = bucket.take(1).unwrap();
+    assert!(wait > Duration::from_millis(500), "{:?}", wait);
+    assert!(!bucket.is_full());
+}
+
+#[test]
+fn lockouts_start_after_max_failures_and_expire() {
+    let lockout = Duration::from_millis(100);
+    let mut failures = AuthFailures::default();
+    assert!(failures.is_expired(lockout));
+
+    failures.fail(3, lockout);
+    failures.fail(3, lockout);
+    assert!(!failures.is_locked());
+    assert!(!failures.is_expired(lockout));
+    failures.fail(3, lockout);
+    assert!(failures.is_locked());
+    assert!(!failures.is_expired(lockout));
+
+    sleep(lockout + Duration::from_millis(50));
+    assert!(!failures.is_locked());
+    assert!(failures.is_expired(lockout));
+}
+
+#[test]
+fn old_failures_are_forgotten() {
+    let lockout = Duration::from_millis(100);
+    let mut failures = AuthFailures::default();
+    failures.fail(2, lockout);
+    sleep(lockout + Duration::from_millis(50));
+    assert!(failures.is_expired(lockout));
+
+    // Not the second failure in a row anymore
+    failures.fail(2, lockout);
+    assert!(!failures.is_locked());
+    failures.fail(2, lockout);
+    assert!(failures.is_locked());
+}
@@ -0,0 +1,117 @@
+use mini_jabber::*;
+
+const SECRET: &str = "aHVudGVyMg==";
+
+fn redacted(xml: &str) -> String {
+    let written = Redacted(xml).to_string();
+    assert!(!written.contains(SECRET), "{}", written);
+    written
+}
+
+#[test]
+fn sasl_payloads_are_redacted() {
+    let auth = SaslAuth {
+        xmlns: SASL_NS.to_string(),
+        mechanism: "PLAIN".to_string(),
+        data: SECRET.to_string(),
+    };
+    let written = redacted(&auth.into_string());
+    assert!(written.contains("[redacted]"), "{}", written);
+    assert!(written.contains("mechanism=\"PLAIN\""), "{}", written);
+
+    redacted(&format!(
+        "<sasl:auth xmlns:sasl='{}' mechanism='PLAIN'>{}</sasl:auth>",
+        SASL_NS, SECRET
+    ));
+    redacted(&format!(
+        "<response xmlns='{}'>{}</response>",
+        SASL_NS, SECRET
+    ));
+}
+
+#[test]
+fn registration_passwords_and_keys_are_redacted() {
+    let query = RegisterQuery {
+        username: Some("zet".to_string()),
+        password: Some(SECRET.to_string()),
+        ..Default::default()
+    };
+    let iq = Iq::new(
+        IqType::Set,
+        "1".to_string(),
+        Some(IqPayload::Register(query)),
+    );
+    let written = redacted(&iq.into_string());
+    assert!(written.contains("<username>zet</username>"), "{}", written);
+
+    redacted(&format!(
+        "<iq id='1' type='set' xmlns:r='{}'><r:query><r:key>{}</r:key></r:query></iq>",
+        REGISTER_NS, SECRET
+    ));
+}
+
+#[test]
+fn password_fields_of_forms_are_redacted() {
+    let mut form = DataForm::with_form_type(FormType::Submit, ADMIN_NS);
+    form.set_values("accountjid", vec!["zet@localhost".to_string()]);
+    form.set_values("password", vec![SECRET.to_string()]);
+    form.set_values("password-verify", vec![SECRET.to_string()]);
+    let command = Command {
+        form: Some(form),
+        ..Command::execute("http://jabber.org/protocol/admin#add-user")
+    };
+    let iq = Iq::new(
+        IqType::Set,
+        "1".to_string(),
+        Some(IqPayload::Command(command)),
+    );
+    let written = redacted(&iq.into_string());
+    assert!(written.contains("zet@localhost"), "{}", written);
+}
+
+#[test]
+fn dialback_keys_and_handshakes_are_redacted() {
+    let result = DialbackResult {
+        from: "a.example".to_string(),
+        to: "b.example".to_string(),
+        key: Some(SECRET.to_string()),
+        result_type: None,
+    };
+    let written = redacted(&result.into_string());
+    assert!(written.contains("a.example"), "{}", written);
+    redacted(&format!(
+        "<dialback:verify xmlns:dialback='{}' from='a' to='b' id='1'>{}</dialback:verify>",
+        DIALBACK_NS, SECRET
+    ));
+
+    redacted(
+        &ComponentHandshake {
+            digest: Some(SECRET.to_string()),
+        }
+        .into_string(),
+    );
+}
+
+#[test]
+fn undeclared_prefixes_are_redacted() {
+    redacted(&format!("<x:auth mechanism='PLAIN'>{}</x:auth>", SECRET));
+}
+
+#[test]
+fn malformed_xml_is_never_echoed() {
+    for xml in [
+        format!("<auth xmlns='{}'>{}</auth><a", SASL_NS, SECRET),
+        format!("<message><body>{}</body><!-- unterminated", SECRET),
+        format!("<message><body>{}</body><![CDATA[", SECRET),
+    ] {
+        assert_eq!(
+            redacted(&xml),
+            format!("[malformed XML, {} bytes]", xml.len()),
+            "{}",
+            xml
+        );
+    }
+
+    // Read leniently, but still redacted
+    redacted(&format!("<auth xmlns='{}'>{}</auth", SASL_NS, SECRET));
+}
@@ -0,0 +1,75 @@
+use mini_jabber::*;
+
+const MAX_DEPTH: usize = 4;
+const MAX_ATTRIBUTES: usize = 3;
+
+fn check(xml: &str) -> Result<(), StreamError> {
+    check_restricted_xml(xml, MAX_DEPTH, MAX_ATTRIBUTES)
+}
+
+fn condition(xml: &str) -> String {
+    check(xml).unwrap_err().condition
+}
+
+/// `depth` elements nested in each other.
+fn nested(depth: usize) -> String {
+    "<a>".repeat(depth) + &"</a>".repeat(depth)
+}
+
+#[test]
+fn plain_stanzas_pass() {
+    assert!(
+        check("<message to='amy@localhost'><body>&lt;3 &amp; &#x1F600;</body></message>").is_ok()
+    );
+    // The stream header is closed by a later frame
+    assert!(check("<?xml version='1.0'?><stream:stream xmlns='jabber:client'>").is_ok());
+    assert!(check("</stream:stream>").is_ok());
+}
+
+#[test]
+fn doctype_processing_instructions_and_comments_are_refused() {
+    assert_eq!(condition("<!DOCTYPE message><message/>"), "restricted-xml");
+    assert_eq!(
+        condition("<message><?php echo 1; ?></message>"),
+        "restricted-xml"
+    );
+    assert_eq!(
+        condition("<message><!-- hello --></message>"),
+        "restricted-xml"
+    );
+}
+
+#[test]
+fn only_predefined_entities_are_allowed() {
+    assert_eq!(
+        condition("<message><body>&lol;</body></message>"),
+        "restricted-xml"
+    );
+    assert_eq!(condition("<message to='&lol;'/>"), "restricted-xml");
+}
+
+#[test]
+fn depth_is_limited() {
+    assert!(check(&nested(MAX_DEPTH)).is_ok());
+    assert_eq!(condition(&nested(MAX_DEPTH + 1)), "policy-violation");
+    // Empty elements count as a level too
+    let empty_below = "<a>".repeat(MAX_DEPTH) + "<b/>" + &"</a>".repeat(MAX_DEPTH);
+    assert_eq!(condition(&empty_below), "policy-violation");
+}
+
+#[test]
+fn attributes_are_limited() {
+    assert!(check("<message a='1' b='2' c='3'/>").is_ok());
+    assert_eq!(
+        condition("<message a='1' b='2' c='3' d='4'/>"),
+        "policy-violation"
+    );
+}
+
+#[test]
+fn malformed_xml_is_not_well_formed() {
+    assert_eq!(condition("<message></presence>"), "not-well-formed");
+    assert_eq!(condition("<message a='1' a='2'/>"), "not-well-formed");
+    assert_eq!(condition("<message a=1/>"), "not-well-formed");
+    assert_eq!(condition("<message"), "not-well-formed");
+}
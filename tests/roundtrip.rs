@@ -0,0 +1,592 @@
+use std::fmt::Debug;
+
+use mini_jabber::*;
+use proptest::{collection::vec, option, prelude::*, sample::subsequence};
+
+/// Writes `value`, reads it back and writes it again: both the value and the document must
+/// come back unchanged.
+fn roundtrip<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: XmlCustomSerialize + XmlCustomDeserialize + PartialEq + Debug,
+{
+    let xml = value.into_string();
+    let parsed = T::from_string(&xml)
+        .map_err(|error| TestCaseError::fail(format!("{} in {}", error, xml)))?;
+    prop_assert_eq!(&parsed, value, "{}", xml);
+    prop_assert_eq!(parsed.into_string(), xml);
+    Ok(())
+}
+
+/// Escapes every special character as a character reference instead of an entity.
+fn char_refs(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '<' | '>' | '&' | '\'' | '"' => format!("&#{};", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Text with the characters that need escaping, in attributes as well as in text.
+fn text() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 <>&'\"é☃🦀]{1,16}"
+}
+
+/// Text that may be empty, for values that are written even when empty.
+fn any_text() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 <>&'\"é☃🦀]{0,16}"
+}
+
+fn word() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9-]{0,10}"
+}
+
+fn jid() -> impl Strategy<Value = String> {
+    "([a-z]{1,8}@)?[a-z]{1,8}\\.[a-z]{2,3}(/[a-zA-Z0-9]{1,8})?"
+}
+
+/// Unknown extensions without children, named so that they don't collide with the children
+/// we model.
+fn empty_element() -> impl Strategy<Value = Element> {
+    let ns = prop::sample::select(vec!["urn:example:a", "urn:example:b", "urn:example:c&d"]);
+    let attrs = vec(("[a-z]{1,6}", any_text()), 0..3).prop_map(|mut attrs| {
+        attrs.sort();
+        attrs.dedup_by(|(a, _), (b, _)| a == b);
+        attrs
+    });
+    ("x[a-z]{0,6}", ns, attrs).prop_map(|(name, ns, attrs)| Element {
+        name,
+        ns: ns.to_string(),
+        attrs,
+        children: Vec::new(),
+    })
+}
+
+fn element() -> impl Strategy<Value = Element> {
+    empty_element().prop_recursive(3, 16, 4, |inner| {
+        let child = prop_oneof![inner.prop_map(Node::Element), text().prop_map(Node::Text)];
+        (empty_element(), vec(child, 0..4)).prop_map(|(mut element, children)| {
+            for child in children {
+                match (child, element.children.last_mut()) {
+                    // Adjacent text reads back as one node
+                    (Node::Text(text), Some(Node::Text(last))) => last.push_str(&text),
+                    (child, _) => element.children.push(child),
+                }
+            }
+            element
+        })
+    })
+}
+
+fn chat_state() -> impl Strategy<Value = ChatState> {
+    prop_oneof![
+        Just(ChatState::Active),
+        Just(ChatState::Composing),
+        Just(ChatState::Paused),
+        Just(ChatState::Inactive),
+        Just(ChatState::Gone),
+    ]
+}
+
+fn reactions() -> impl Strategy<Value = Reactions> {
+    let emojis = vec!["👍", "❤️", "😂", "🎉", "🦀"];
+    (text(), subsequence(emojis, 0..=5)).prop_map(|(id, reactions)| Reactions {
+        id,
+        reactions: reactions.into_iter().map(str::to_string).collect(),
+    })
+}
+
+fn result_set() -> impl Strategy<Value = ResultSet> {
+    (
+        option::of(0..1000usize),
+        option::of(any_text()),
+        option::of(any_text()),
+        option::of(any_text()),
+        option::of(any_text()),
+        option::of(0..1000usize),
+    )
+        .prop_map(|(max, after, before, first, last, count)| ResultSet {
+            max,
+            after,
+            before,
+            first,
+            last,
+            count,
+        })
+}
+
+fn simple_message() -> impl Strategy<Value = Message> {
+    (
+        option::of(text()),
+        option::of(jid()),
+        option::of(jid()),
+        option::of(word()),
+        option::of(any_text()),
+        option::of(any_text()),
+    )
+        .prop_map(|(id, from, to, message_type, subject, body)| Message {
+            id,
+            from,
+            to,
+            message_type,
+            subject,
+            body,
+            ..Default::default()
+        })
+}
+
+fn mam_result() -> impl Strategy<Value = MamResult> {
+    (
+        option::of(text()),
+        text(),
+        option::of("20[0-9]{2}-[01][0-9]-[0-3][0-9]T[0-2][0-9]:[0-5][0-9]:[0-5][0-9]Z"),
+        simple_message(),
+    )
+        .prop_map(|(query_id, id, stamp, message)| MamResult {
+            query_id,
+            id,
+            stamp,
+            message: Box::new(message),
+        })
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (
+        simple_message(),
+        option::of(chat_state()),
+        option::of(text().prop_map(|id| Replace { id })),
+        option::of(text().prop_map(|id| Retract { id })),
+        option::of(reactions()),
+        option::of(mam_result()),
+        vec(element(), 0..3),
+    )
+        .prop_map(
+            |(message, chat_state, replace, retract, reactions, archived, payloads)| Message {
+                chat_state,
+                replace,
+                retract,
+                reactions,
+                archived,
+                payloads,
+                ..message
+            },
+        )
+}
+
+fn presence() -> impl Strategy<Value = Presence> {
+    (
+        option::of(text()),
+        option::of(jid()),
+        option::of(jid()),
+        option::of(word()),
+        option::of(any_text()),
+        option::of(any_text()),
+        vec(element(), 0..3),
+    )
+        .prop_map(
+            |(id, from, to, presence_type, show, status, payloads)| Presence {
+                id,
+                from,
+                to,
+                presence_type,
+                show,
+                status,
+                payloads,
+            },
+        )
+}
+
+fn stanza_error() -> impl Strategy<Value = StanzaError> {
+    (
+        prop::sample::select(vec!["auth", "cancel", "continue", "modify", "wait"]),
+        prop::sample::select(vec![
+            "bad-request",
+            "conflict",
+            "item-not-found",
+            "not-allowed",
+            "service-unavailable",
+        ]),
+        option::of(any_text()),
+    )
+        .prop_map(|(error_type, condition, text)| StanzaError {
+            error_type: error_type.to_string(),
+            condition: condition.to_string(),
+            text,
+        })
+}
+
+fn field_type() -> impl Strategy<Value = FieldType> {
+    prop::sample::select(vec![
+        FieldType::Boolean,
+        FieldType::Fixed,
+        FieldType::Hidden,
+        FieldType::JidMulti,
+        FieldType::JidSingle,
+        FieldType::ListMulti,
+        FieldType::ListSingle,
+        FieldType::TextMulti,
+        FieldType::TextPrivate,
+        FieldType::TextSingle,
+    ])
+}
+
+fn field() -> impl Strategy<Value = Field> {
+    let option = (option::of(any_text()), any_text())
+        .prop_map(|(label, value)| FieldOption { label, value });
+    (
+        option::of(text()),
+        option::of(field_type()),
+        option::of(any_text()),
+        option::of(any_text()),
+        any::<bool>(),
+        vec(any_text(), 0..3),
+        vec(option, 0..3),
+    )
+        .prop_map(
+            |(var, field_type, label, desc, required, values, options)| Field {
+                var,
+                field_type,
+                label,
+                desc,
+                required,
+                values,
+                options,
+            },
+        )
+}
+
+fn data_form() -> impl Strategy<Value = DataForm> {
+    (
+        prop::sample::select(vec![
+            FormType::Form,
+            FormType::Submit,
+            FormType::Cancel,
+            FormType::Result,
+        ]),
+        option::of(any_text()),
+        vec(any_text(), 0..2),
+        vec(field(), 0..3),
+        option::of(vec(field(), 0..2)),
+        vec(vec(field(), 0..2), 0..2),
+    )
+        .prop_map(
+            |(form_type, title, instructions, fields, reported, items)| DataForm {
+                form_type,
+                title,
+                instructions,
+                fields,
+                reported,
+                items,
+            },
+        )
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    let action = || {
+        prop::sample::select(vec![
+            CommandAction::Execute,
+            CommandAction::Next,
+            CommandAction::Prev,
+            CommandAction::Complete,
+            CommandAction::Cancel,
+        ])
+    };
+    let actions = vec![
+        CommandAction::Next,
+        CommandAction::Prev,
+        CommandAction::Complete,
+    ];
+    let status = prop::sample::select(vec![
+        CommandStatus::Executing,
+        CommandStatus::Completed,
+        CommandStatus::Canceled,
+    ]);
+    let note = (word(), any_text()).prop_map(|(note_type, text)| CommandNote { note_type, text });
+    (
+        any_text(),
+        option::of(text()),
+        option::of(action()),
+        option::of(status),
+        subsequence(actions, 0..=3).prop_shuffle(),
+        vec(note, 0..3),
+        option::of(data_form()),
+    )
+        .prop_map(
+            |(node, session_id, action, status, actions, notes, form)| Command {
+                node,
+                session_id,
+                action,
+                status,
+                actions,
+                notes,
+                form,
+            },
+        )
+}
+
+fn register_query() -> impl Strategy<Value = RegisterQuery> {
+    (
+        option::of(any_text()),
+        any::<bool>(),
+        option::of(any_text()),
+        option::of(any_text()),
+        option::of(any_text()),
+        option::of(any_text()),
+        any::<bool>(),
+        option::of(data_form()),
+    )
+        .prop_map(
+            |(instructions, registered, username, password, email, key, remove, form)| {
+                RegisterQuery {
+                    instructions,
+                    registered,
+                    username,
+                    password,
+                    email,
+                    key,
+                    remove,
+                    form,
+                }
+            },
+        )
+}
+
+fn mam_query() -> impl Strategy<Value = MamQuery> {
+    (
+        option::of(text()),
+        option::of(data_form()),
+        option::of(result_set()),
+    )
+        .prop_map(|(query_id, form, set)| MamQuery {
+            query_id,
+            form,
+            set,
+        })
+}
+
+fn mam_fin() -> impl Strategy<Value = MamFin> {
+    (any::<bool>(), result_set()).prop_map(|(complete, set)| MamFin { complete, set })
+}
+
+fn iq() -> impl Strategy<Value = Iq> {
+    let payload = prop_oneof![
+        register_query().prop_map(IqPayload::Register),
+        command().prop_map(IqPayload::Command),
+        mam_query().prop_map(IqPayload::MamQuery),
+        mam_fin().prop_map(IqPayload::MamFin),
+        element().prop_map(IqPayload::Other),
+    ];
+    (
+        text(),
+        option::of(jid()),
+        option::of(jid()),
+        prop::sample::select(vec![
+            IqType::Get,
+            IqType::Set,
+            IqType::Result,
+            IqType::Error,
+        ]),
+        option::of(payload),
+        option::of(stanza_error()),
+    )
+        .prop_map(|(id, from, to, iq_type, payload, error)| Iq {
+            id,
+            from,
+            to,
+            iq_type,
+            payload,
+            error,
+        })
+}
+
+fn dialback_type() -> impl Strategy<Value = DialbackType> {
+    prop::sample::select(vec![DialbackType::Valid, DialbackType::Invalid])
+}
+
+/// Dialback keys are trimmed, and an empty key is no key.
+fn dialback_key() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9<>&'\"]([a-zA-Z0-9 <>&'\"]{0,14}[a-zA-Z0-9<>&'\"])?"
+}
+
+fn stream_header() -> impl Strategy<Value = StreamHeader> {
+    (
+        option::of(jid()),
+        jid(),
+        "[0-9]\\.[0-9]",
+        "[a-z]{2}(-[A-Z]{2})?",
+        prop::sample::select(vec![CLIENT_NS, "jabber:server", COMPONENT_NS]),
+    )
+        .prop_map(|(from, to, version, xml_lang, xmlns)| StreamHeader {
+            from,
+            to,
+            version,
+            xml_lang,
+            xmlns: xmlns.to_string(),
+            xmlns_stream: STREAM_NS.to_string(),
+        })
+}
+
+fn start_tls() -> impl Strategy<Value = StartTls> {
+    any::<bool>().prop_map(|required| StartTls {
+        xmlns: TLS_NS.to_string(),
+        required,
+    })
+}
+
+fn mechanisms() -> impl Strategy<Value = Mechanisms> {
+    vec("[A-Z0-9-]{1,20}", 0..4).prop_map(|mechanisms| Mechanisms {
+        xmlns: SASL_NS.to_string(),
+        mechanisms: mechanisms.into_iter().map(Mechanism).collect(),
+    })
+}
+
+proptest! {
+    #[test]
+    fn stream_header_roundtrip(header in stream_header()) {
+        roundtrip(&header)?;
+    }
+
+    #[test]
+    fn stream_header_response_roundtrip(header in stream_header(), id in text()) {
+        let mut response = header.into_response(id);
+        response.from = "localhost".to_string();
+        roundtrip(&response)?;
+    }
+
+    #[test]
+    fn stream_features_roundtrip(
+        start_tls in option::of(start_tls()),
+        mechanisms in option::of(mechanisms()),
+        dialback in any::<bool>(),
+    ) {
+        roundtrip(&StreamFeatures { start_tls, mechanisms, dialback })?;
+    }
+
+    #[test]
+    fn start_tls_roundtrip(start_tls in start_tls()) {
+        roundtrip(&start_tls)?;
+    }
+
+    #[test]
+    fn mechanisms_roundtrip(mechanisms in mechanisms()) {
+        roundtrip(&mechanisms)?;
+    }
+
+    #[test]
+    fn sasl_roundtrip(mechanism in "[A-Z0-9-]{1,20}", data in "[A-Za-z0-9+/=]{0,40}", condition in word()) {
+        roundtrip(&SaslAuth { xmlns: SASL_NS.to_string(), mechanism, data })?;
+        roundtrip(&SaslFailure { condition })?;
+    }
+
+    #[test]
+    fn stream_error_roundtrip(condition in word(), text in option::of(text())) {
+        roundtrip(&StreamError { condition, text })?;
+    }
+
+    #[test]
+    fn component_handshake_roundtrip(digest in option::of("[0-9a-f]{40}")) {
+        roundtrip(&ComponentHandshake { digest })?;
+    }
+
+    #[test]
+    fn message_roundtrip(message in message()) {
+        roundtrip(&message)?;
+    }
+
+    #[test]
+    fn presence_roundtrip(presence in presence()) {
+        roundtrip(&presence)?;
+    }
+
+    #[test]
+    fn iq_roundtrip(iq in iq()) {
+        roundtrip(&iq)?;
+    }
+
+    #[test]
+    fn payloads_roundtrip(
+        command in command(),
+        query in register_query(),
+        form in data_form(),
+        mam_query in mam_query(),
+        mam_fin in mam_fin(),
+        mam_result in mam_result(),
+        set in result_set(),
+        error in stanza_error(),
+    ) {
+        roundtrip(&command)?;
+        roundtrip(&query)?;
+        roundtrip(&form)?;
+        roundtrip(&mam_query)?;
+        roundtrip(&mam_fin)?;
+        roundtrip(&mam_result)?;
+        roundtrip(&set)?;
+        roundtrip(&error)?;
+    }
+
+    /// Stanzas convert to the element their serialization parses to.
+    #[test]
+    fn stanza_elements_match_serialization(message in message(), presence in presence(), iq in iq()) {
+        for stanza in [Stanza::Message(message), Stanza::Presence(presence), Stanza::Iq(iq)] {
+            let parsed = Element::from_string(&stanza.into_string()).unwrap();
+            prop_assert_eq!(Element::from(stanza), parsed);
+        }
+    }
+
+    #[test]
+    fn element_roundtrip(element in element()) {
+        roundtrip(&element)?;
+    }
+
+    #[test]
+    fn dialback_roundtrip(
+        from in jid(),
+        to in jid(),
+        id in text(),
+        key in option::of(dialback_key()),
+        result_type in option::of(dialback_type()),
+    ) {
+        roundtrip(&DialbackResult {
+            from: from.clone(),
+            to: to.clone(),
+            key: key.clone(),
+            result_type,
+        })?;
+        roundtrip(&DialbackVerify { from, to, id, key, verify_type: result_type })?;
+    }
+
+    /// Character references and single quotes read the same as what we write.
+    #[test]
+    fn message_escaping_is_canonical(to in jid(), id in text(), body in any_text()) {
+        let written = Message {
+            id: Some(id.clone()),
+            to: Some(to.clone()),
+            body: Some(body.clone()),
+            ..Default::default()
+        };
+        let xml = format!(
+            "<message id='{}' to='{}'><body>{}</body></message>",
+            char_refs(&id),
+            to,
+            char_refs(&body)
+        );
+        let parsed = Message::from_string(&xml).unwrap();
+        prop_assert_eq!(&parsed, &written);
+        prop_assert_eq!(parsed.into_string(), written.into_string());
+    }
+
+    #[test]
+    fn element_escaping_is_canonical(name in "x[a-z]{0,6}", value in any_text(), text in text()) {
+        let written = Element::new(&name, "urn:example:a")
+            .with_attr("a", &value)
+            .with_text(&text);
+        let xml = format!(
+            "<{name} xmlns='urn:example:a' a='{}'>{}</{name}>",
+            char_refs(&value),
+            char_refs(&text)
+        );
+        let parsed = Element::from_string(&xml).unwrap();
+        prop_assert_eq!(&parsed, &written);
+        prop_assert_eq!(parsed.into_string(), written.into_string());
+    }
+}
@@ -0,0 +1,46 @@
+use mini_jabber::*;
+
+#[test]
+fn plain_responses_split_into_username_and_password() {
+    let data = sasl_plain_encode("zet", "hunter2");
+    assert_eq!(
+        sasl_plain_decode(&data).unwrap(),
+        ("zet".to_string(), "hunter2".to_string())
+    );
+
+    for data in ["not base64!", "emV0", "AHpldABodW50ZXIyAGV4dHJh"] {
+        let error = sasl_plain_decode(data).unwrap_err();
+        assert!(matches!(error, Error::Auth(_)), "{}", error);
+    }
+}
+
+#[test]
+fn external_responses_carry_an_optional_authzid() {
+    assert_eq!(sasl_external_decode("=").unwrap(), None);
+    assert_eq!(sasl_external_decode("").unwrap(), None);
+    let data = sasl_external_encode(Some("zet@localhost"));
+    assert_eq!(
+        sasl_external_decode(&data).unwrap().as_deref(),
+        Some("zet@localhost")
+    );
+
+    let error = sasl_external_decode("not base64!").unwrap_err();
+    assert!(matches!(error, Error::Auth(_)), "{}", error);
+}
+
+#[test]
+fn reactions_are_single_emojis() {
+    let reactions = Reactions::new(
+        "1".to_string(),
+        vec!["👍".to_string(), "👍".to_string(), "🎉".to_string()],
+    )
+    .unwrap();
+    assert_eq!(reactions.reactions, ["👍", "🎉"]);
+
+    let error = Reactions::new("1".to_string(), vec!["ok".to_string()]).unwrap_err();
+    assert!(
+        matches!(&error, Error::InvalidValue { value, .. } if value == "ok"),
+        "{}",
+        error
+    );
+}
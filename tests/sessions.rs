@@ -0,0 +1,126 @@
+use std::{
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use mini_jabber::*;
+use tokio::time::timeout;
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// A server process with open registration, stopped when dropped.
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Self {
+        let port = free_port();
+        let process = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--domain", "localhost", "--registration", "open"])
+            .args(["--port", &port.to_string()])
+            .args(["--s2s-port", &free_port().to_string()])
+            .args(["--component-port", &free_port().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let started = Instant::now();
+        while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "server did not start"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Self { process, port }
+    }
+
+    fn builder(&self, jid: &str, password: &str) -> ClientBuilder {
+        Client::builder(jid, password)
+            .server(&format!("ws://127.0.0.1:{}", self.port))
+            .tls(TlsPolicy::Optional)
+            .reconnect(ReconnectPolicy::never())
+    }
+
+    async fn connect(&self, jid: &str, register: bool) -> Client {
+        let mut builder = self.builder(jid, "secret");
+        if register {
+            builder = builder.register(None);
+        }
+        builder.connect().await.unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+/// Body of the next message `client` receives.
+async fn next_body(client: &mut Client) -> Option<String> {
+    loop {
+        match timeout(Duration::from_secs(5), client.next())
+            .await
+            .ok()??
+        {
+            ClientEvent::Message(message) => return message.body,
+            _ => continue,
+        }
+    }
+}
+
+/// A second connection of a user does not replace the first one, both get their messages.
+#[tokio::test]
+async fn every_session_of_a_user_gets_their_messages() {
+    let server = Server::start();
+    let mut laptop = server.connect("zet@localhost", true).await;
+    let mut phone = server.connect("zet@localhost", false).await;
+    let amy = server.connect("amy@localhost", true).await;
+
+    let message = Message {
+        to: Some("zet@localhost".to_string()),
+        body: Some("hello".to_string()),
+        ..Default::default()
+    };
+    amy.send(message.clone().into()).await.unwrap();
+    assert_eq!(next_body(&mut laptop).await.as_deref(), Some("hello"));
+    assert_eq!(next_body(&mut phone).await.as_deref(), Some("hello"));
+
+    // The remaining session still gets them once the other one is gone
+    laptop.disconnect().await.unwrap();
+    let message = Message {
+        body: Some("still there?".to_string()),
+        ..message
+    };
+    amy.send(message.into()).await.unwrap();
+    assert_eq!(next_body(&mut phone).await.as_deref(), Some("still there?"));
+}
+
+/// Logging in to one account does not forget the failures of another from the same address.
+#[tokio::test]
+async fn failed_logins_are_counted_for_each_account() {
+    let server = Server::start();
+    server.connect("zet@localhost", true).await;
+    server.connect("amy@localhost", true).await;
+
+    let guess = server.builder("zet@localhost", "guessed");
+    for _ in 0..4 {
+        assert!(matches!(guess.clone().connect().await, Err(Error::Auth(_))));
+    }
+    server.connect("amy@localhost", false).await;
+    assert!(matches!(guess.clone().connect().await, Err(Error::Auth(_))));
+
+    // Five failures in a row lock zet out, even with the right password
+    let login = server.builder("zet@localhost", "secret").connect().await;
+    assert!(login.is_err());
+}
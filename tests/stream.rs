@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use mini_jabber::*;
+use tokio::{io::AsyncWriteExt, time::timeout};
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+
+/// Frames read from `data`, written in chunks of `chunk` bytes.
+async fn frames(data: &str, chunk: usize) -> Vec<String> {
+    let (client, mut server) = tokio::io::duplex(64);
+    let mut stream = XmlStream::new(client, None);
+    let data = data.as_bytes().to_vec();
+    tokio::spawn(async move {
+        for piece in data.chunks(chunk) {
+            server.write_all(piece).await.unwrap();
+        }
+    });
+
+    let mut frames = Vec::new();
+    while let Some(text) = timeout(Duration::from_secs(5), stream.get_next_text())
+        .await
+        .unwrap()
+    {
+        frames.push(text);
+    }
+    frames
+}
+
+#[tokio::test]
+async fn xml_streams_are_split_into_elements() {
+    let data = "<?xml version='1.0'?>\
+        <stream:stream xmlns='jabber:server' xmlns:stream='http://etherx.jabber.org/streams'>\
+        <stream:features/>\n  \
+        <message to='amy@localhost' note='a > b'><body><![CDATA[<not/> an </element>]]></body></message> \
+        <presence/></stream:stream>";
+    let expected = [
+        "<stream:stream xmlns='jabber:server' xmlns:stream='http://etherx.jabber.org/streams'>",
+        "<stream:features/>",
+        "<message to='amy@localhost' note='a > b'><body><![CDATA[<not/> an </element>]]></body></message>",
+        "<presence/>",
+    ];
+    for chunk in [1, 3, 7, data.len()] {
+        assert_eq!(frames(data, chunk).await, expected, "chunks of {}", chunk);
+    }
+}
+
+/// Each read resumes where the previous one stopped, large elements arriving in many pieces
+/// are not scanned again from their start.
+#[tokio::test]
+async fn large_elements_are_read_in_pieces() {
+    let items = "<item name='a &gt; b'>text</item>".repeat(20_000);
+    let element = format!("<query xmlns='jabber:iq:roster'>{}<!-- end --></query>", items);
+    let data = format!("<stream:stream>{}<presence/></stream:stream>", element);
+    let frames = timeout(Duration::from_secs(30), frames(&data, 97))
+        .await
+        .unwrap();
+    assert_eq!(frames, ["<stream:stream>", element.as_str(), "<presence/>"]);
+}
+
+#[tokio::test]
+async fn large_elements_are_refused() {
+    let (client, mut server) = tokio::io::duplex(1024);
+    let mut stream = XmlStream::new(client, Some(16));
+    server
+        .write_all(b"<message><body>hello, world</body></message>")
+        .await
+        .unwrap();
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(tungstenite::Error::Capacity(_)))
+    ));
+}
+
+#[tokio::test]
+async fn closing_ends_the_stream() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = XmlStream::new(client, None);
+    let mut server = XmlStream::new(server, None);
+
+    client
+        .send(WsMessage::Text("<presence/>".to_string()))
+        .await
+        .unwrap();
+    client.close().await.unwrap();
+    assert_eq!(server.get_next_text().await.as_deref(), Some("<presence/>"));
+    assert_eq!(server.get_next_text().await, None);
+}
@@ -0,0 +1,17 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(name = "item")]
+struct Item {
+    #[xml(text)]
+    name: String,
+}
+
+#[derive(XmlElement)]
+#[xml(name = "list")]
+struct List {
+    #[xml(children)]
+    items: Option<Item>,
+}
+
+fn main() {}
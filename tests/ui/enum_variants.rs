@@ -0,0 +1,16 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(name = "ping")]
+struct Ping {
+    #[xml(attribute)]
+    id: String,
+}
+
+#[derive(XmlElement)]
+enum Request {
+    Ping(Ping),
+    Pong { id: String },
+}
+
+fn main() {}
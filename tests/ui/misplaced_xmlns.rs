@@ -0,0 +1,10 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(name = "ping")]
+struct Ping {
+    #[xml(attribute, xmlns = "urn:example")]
+    id: String,
+}
+
+fn main() {}
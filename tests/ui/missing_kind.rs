@@ -0,0 +1,9 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(name = "ping")]
+struct Ping {
+    id: String,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(xmlns = "urn:example")]
+struct Ping {
+    #[xml(attribute)]
+    id: String,
+}
+
+fn main() {}
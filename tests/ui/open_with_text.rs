@@ -0,0 +1,12 @@
+use mini_jabber::XmlElement;
+
+#[derive(XmlElement)]
+#[xml(name = "stream:stream", open)]
+struct Header {
+    #[xml(attribute)]
+    to: String,
+    #[xml(text)]
+    body: String,
+}
+
+fn main() {}